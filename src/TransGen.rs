@@ -1,43 +1,309 @@
+//! Transaction workload generator. It signs transfers between accounts derived from a seed,
+//! which it first funds from the default account of the node wallet. The pace, the values and
+//! which accounts pay which follow a `GeneratorConfig`, and can be changed while it runs.
+
 use crate::network::server::Handle as ServerHandle;
-use log::info;
-use crate::blockchain::{Blockchain, State};
-use crate::block::{Block, Content, Header};
-use crate::crypto::merkle::{MerkleTree};
+use log::{debug, info};
+use crate::blockchain::State;
+use crate::config::GeneratorConfig;
 use crate::transaction::{Transaction, SignedTransaction};
 use crate::crypto::hash::{H256, H160, Hashable};
 use crate::network::message::Message;
-use crossbeam::channel::{unbounded, Receiver, Sender, TryRecvError};
+use crossbeam::channel::{unbounded, Receiver, RecvTimeoutError, Sender};
 use std::time;
 use std::sync::{Arc, Mutex};
 use std::thread;
-extern crate rand;
-use rand::Rng;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 use crate::crypto::key_pair;
 use crate::transaction::sign;
-use ring::signature::{Ed25519KeyPair, Signature, KeyPair, VerificationAlgorithm, EdDSAParameters};
-use std::collections::{HashMap, VecDeque};
+use ring::signature::KeyPair;
+use std::collections::{HashSet, VecDeque};
+use crate::wallet::Wallet;
+use crate::events::EventBus;
+use crate::shutdown::Stopped;
+use crate::node::Shared;
+use crate::mempool::{self, Admission, Mempool};
+use serde::Serialize;
+
+/// Coins sent from the node wallet to each generated account that has none
+pub const ACCOUNT_FUNDING: u32 = 100;
+
+/// How the gaps between two transactions are drawn
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Arrivals {
+    /// Exactly the configured interval
+    Constant,
+    /// Exponentially distributed around the configured interval
+    Poisson,
+}
+
+/// Parses `constant` or `poisson`
+impl std::str::FromStr for Arrivals {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "constant" => Ok(Arrivals::Constant),
+            "poisson" => Ok(Arrivals::Poisson),
+            _ => Err(format!("unknown arrivals {}", s)),
+        }
+    }
+}
+
+/// How the value of each transaction is drawn. Values are at least 1.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Values {
+    Constant(u32),
+    /// Uniform between the bounds, both included
+    Uniform(u32, u32),
+    /// Exponentially distributed with this mean
+    Exponential(u32),
+}
+
+/// Parses `N`, `uniform:MIN-MAX` or `exponential:MEAN`
+impl std::str::FromStr for Values {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let number = |v: &str| v.parse::<u32>().map_err(|e| format!("invalid value {}: {}", s, e));
+        let values = match s.split_once(':') {
+            None => Values::Constant(number(s)?),
+            Some(("uniform", range)) => match range.split_once('-') {
+                Some((min, max)) if number(min)? <= number(max)? => Values::Uniform(number(min)?, number(max)?),
+                _ => return Err(format!("invalid value range {}", s)),
+            },
+            Some(("exponential", mean)) => Values::Exponential(number(mean)?),
+            Some(_) => return Err(format!("unknown value distribution {}", s)),
+        };
+        Ok(values)
+    }
+}
+
+impl Values {
+    fn sample<R: Rng>(&self, rng: &mut R) -> u32 {
+        let value = match *self {
+            Values::Constant(value) => value,
+            Values::Uniform(min, max) => rng.gen_range(min as u64, max as u64 + 1) as u32,
+            Values::Exponential(mean) => (exponential(rng, mean as f64).round() as u64).min(u32::MAX as u64) as u32,
+        };
+        value.max(1)
+    }
+}
+
+/// Which accounts pay which
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pattern {
+    /// Any account pays any other
+    Uniform,
+    /// This percentage of the transactions pays the first account, the rest are uniform
+    HotSpot(u32),
+    /// Each account pays the next one, in a ring
+    Chain,
+    /// Every transaction is sent along with a conflicting one spending the same nonce
+    DoubleSpend,
+}
+
+/// Parses `uniform`, `hotspot`, `hotspot:PERCENT`, `chain` or `double-spend`
+impl std::str::FromStr for Pattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "uniform" => Ok(Pattern::Uniform),
+            None if s == "hotspot" => Ok(Pattern::HotSpot(80)),
+            None if s == "chain" => Ok(Pattern::Chain),
+            None if s == "double-spend" => Ok(Pattern::DoubleSpend),
+            Some(("hotspot", percent)) => match percent.parse::<u32>() {
+                Ok(percent) if percent <= 100 => Ok(Pattern::HotSpot(percent)),
+                _ => Err(format!("invalid hotspot percentage {}", percent)),
+            },
+            _ => Err(format!("unknown pattern {}", s)),
+        }
+    }
+}
+
+/// The settings of a `GeneratorConfig`, parsed
+#[derive(Debug, Clone, PartialEq)]
+pub struct Workload {
+    pub seed: u64,
+    pub accounts: usize,
+    pub interval_ms: u64,
+    pub arrivals: Arrivals,
+    pub values: Values,
+    pub pattern: Pattern,
+}
+
+impl Workload {
+    pub fn parse(config: &GeneratorConfig) -> Result<Self, String> {
+        if config.accounts < 2 {
+            return Err("the generator needs at least 2 accounts".to_string());
+        }
+        Ok(Self {
+            seed: config.seed,
+            accounts: config.accounts,
+            interval_ms: config.interval_ms,
+            arrivals: config.arrivals.parse()?,
+            values: config.value.parse()?,
+            pattern: config.pattern.parse()?,
+        })
+    }
+
+    /// Wallet for the chain `chain_id` holding the keys of the accounts, derived from the seed
+    pub fn wallet(&self, chain_id: u64) -> Wallet {
+        Wallet::with_keys((0..self.accounts as u64).map(|i| key_pair::from_seed(self.seed, i)).collect(), chain_id)
+    }
+
+    /// Time to wait before the next transaction
+    fn next_delay<R: Rng>(&self, rng: &mut R) -> time::Duration {
+        match self.arrivals {
+            Arrivals::Constant => time::Duration::from_millis(self.interval_ms),
+            Arrivals::Poisson => time::Duration::from_secs_f64(exponential(rng, self.interval_ms as f64) / 1000.0),
+        }
+    }
+
+    /// Indices of the paying and the paid account for the `count`th transaction
+    fn pick<R: Rng>(&self, rng: &mut R, count: u64) -> (usize, usize) {
+        let n = self.accounts;
+        match self.pattern {
+            Pattern::Chain => {
+                let from = (count % n as u64) as usize;
+                (from, (from + 1) % n)
+            }
+            Pattern::HotSpot(percent) if rng.gen_range(0, 100) < percent => (rng.gen_range(1, n), 0),
+            _ => {
+                let from = rng.gen_range(0, n);
+                (from, (from + rng.gen_range(1, n)) % n)
+            }
+        }
+    }
+}
 
+/// A sample of the exponential distribution with this mean
+fn exponential<R: Rng>(rng: &mut R, mean: f64) -> f64 {
+    let uniform: f64 = rng.gen();
+    -(1.0 - uniform).ln() * mean
+}
+
+enum ControlSignal {
+    Start,
+    Stop,
+    Configure(GeneratorConfig, Workload),
+    Exit,
+}
+
+/// Snapshot of the generator's activity, as returned by `Handle::status`
+#[derive(Serialize, Debug, Clone)]
+pub struct Status {
+    /// One of "stopped", "running" or "shutdown"
+    pub state: &'static str,
+    /// Transactions sent since the node started, funding and conflicting ones included
+    pub sent: u64,
+    pub config: GeneratorConfig,
+    /// Addresses of the generated accounts
+    pub accounts: Vec<String>,
+}
 
 pub struct Context {
+    control_chan: Receiver<ControlSignal>,
+    running: bool,
+    workload: Workload,
+    /// Keys of the generated accounts
+    accounts: Wallet,
+    addresses: Vec<H160>,
+    /// Accounts a funding transaction was sent to
+    funded: HashSet<H160>,
+    rng: StdRng,
+    /// Transfers between generated accounts so far
+    count: u64,
+    status: Arc<Mutex<Status>>,
     server: ServerHandle,
-    mempool : Arc<Mutex<HashMap<H256, SignedTransaction>>>,
+    mempool : Arc<Mutex<Mempool>>,
+    max_mempool : usize,
     states : Arc<Mutex<State>>,
     txs: Arc<Mutex<VecDeque<SignedTransaction>>>,
+    /// The node wallet, whose default account funds the generated ones
+    wallet: Arc<Mutex<Wallet>>,
+    events: EventBus,
 }
 
+#[derive(Clone)]
+pub struct Handle {
+    control_chan: Sender<ControlSignal>,
+    status: Arc<Mutex<Status>>,
+}
 
-pub fn new(server: &ServerHandle, mempool: &Arc<Mutex<HashMap<H256, SignedTransaction>>>, states: &Arc<Mutex<State>>, txs: &Arc<Mutex<VecDeque<SignedTransaction>>>) -> Context {
-
-    let ctx = Context {
-        server: server.clone(),
-        mempool: Arc::clone(mempool),
-        states: Arc::clone(states),
-        txs: Arc::clone(txs),
+/// Create a stopped generator running the workload of `config`, which must be valid
+pub fn new(node: &Shared, config: &GeneratorConfig) -> Result<(Context, Handle), String> {
+    let workload = Workload::parse(config)?;
+    let (signal_chan_sender, signal_chan_receiver) = unbounded();
+    let status = Arc::new(Mutex::new(Status { state: "stopped", sent: 0, config: config.clone(), accounts: Vec::new() }));
+    let mut ctx = Context {
+        control_chan: signal_chan_receiver,
+        running: false,
+        workload: workload.clone(),
+        accounts: Wallet::with_keys(Vec::new(), 0),
+        addresses: Vec::new(),
+        funded: HashSet::new(),
+        rng: StdRng::seed_from_u64(workload.seed),
+        count: 0,
+        status: Arc::clone(&status),
+        server: node.server.clone(),
+        mempool: Arc::clone(&node.mempool),
+        max_mempool: node.max_mempool,
+        states: Arc::clone(&node.states),
+        txs: Arc::clone(&node.txs),
+        wallet: Arc::clone(&node.wallet),
+        events: node.events.clone(),
     };
+    ctx.configure(config.clone(), workload);
+    let handle = Handle { control_chan: signal_chan_sender, status };
+    Ok((ctx, handle))
+}
 
-    return ctx;
+/// Why `Handle::configure` refused a workload
+#[derive(Debug, PartialEq)]
+pub enum ConfigureError {
+    Invalid(String),
+    Stopped(Stopped),
 }
 
+impl std::fmt::Display for ConfigureError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ConfigureError::Invalid(e) => write!(f, "{}", e),
+            ConfigureError::Stopped(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Handle {
+    fn send(&self, signal: ControlSignal) -> Result<(), Stopped> {
+        self.control_chan.send(signal).map_err(|_| Stopped("generator"))
+    }
+
+    pub fn start(&self) -> Result<(), Stopped> {
+        self.send(ControlSignal::Start)
+    }
+
+    pub fn stop(&self) -> Result<(), Stopped> {
+        self.send(ControlSignal::Stop)
+    }
+
+    pub fn exit(&self) -> Result<(), Stopped> {
+        self.send(ControlSignal::Exit)
+    }
+
+    /// Switch to the workload of `config`, refusing it if invalid
+    pub fn configure(&self, config: GeneratorConfig) -> Result<(), ConfigureError> {
+        let workload = Workload::parse(&config).map_err(ConfigureError::Invalid)?;
+        self.send(ControlSignal::Configure(config, workload)).map_err(ConfigureError::Stopped)
+    }
+
+    pub fn status(&self) -> Status {
+        self.status.lock().unwrap().clone()
+    }
+}
 
 impl Context {
     pub fn start(mut self) {
@@ -47,58 +313,189 @@ impl Context {
                 self.generate();
             })
             .unwrap();
-        info!("Generator is ready to move!");
+        info!("Generator initialized into stopped mode");
+    }
+
+    fn configure(&mut self, config: GeneratorConfig, workload: Workload) {
+        self.accounts = workload.wallet(self.wallet.lock().unwrap().chain_id());
+        self.addresses = self.accounts.addresses();
+        self.rng = StdRng::seed_from_u64(workload.seed);
+        self.count = 0;
+        self.workload = workload;
+        let mut status = self.status.lock().unwrap();
+        status.config = config;
+        status.accounts = self.addresses.iter().map(|a| a.to_string()).collect();
+    }
+
+    /// Apply `signal`, returning false once the generator should exit
+    fn handle_control_signal(&mut self, signal: ControlSignal) -> bool {
+        match signal {
+            ControlSignal::Start => {
+                info!("Generator starting");
+                self.running = true;
+                self.status.lock().unwrap().state = "running";
+            }
+            ControlSignal::Stop => {
+                info!("Generator stopped");
+                self.running = false;
+                self.status.lock().unwrap().state = "stopped";
+            }
+            ControlSignal::Configure(config, workload) => {
+                info!("Generator workload set to {:?}", workload);
+                self.configure(config, workload);
+            }
+            ControlSignal::Exit => {
+                info!("Generator shutting down");
+                self.status.lock().unwrap().state = "shutdown";
+                return false;
+            }
+        }
+        true
     }
 
     fn generate(&mut self) {
-        // main mining loop
-        let mut rng = rand::thread_rng();
-        let nonce : u32 = rng.gen();
-        let random_recipient: Vec<u8> = (0..20).map(|_| 1).collect();
-        let mut raw_bytes_recipient = [0; 20];
-        raw_bytes_recipient.copy_from_slice(&random_recipient);
-        let Recipient : H160 =(&raw_bytes_recipient).into();
-        let mut accountNonce : u16 = 1;
-        let key = key_pair::Hardcoded();
-        let hash_key : H256 = ring::digest::digest(&ring::digest::SHA256, key.public_key().as_ref()).into();
-        let hash_key_20bytes : H160 = hash_key.as_ref()[12..=31].into();
-
-        //In transaction generator, we decide to let sender's address and receiver's address be hardcoded
-        //The code above is to generate random key
-        let senderAddress : H160 = [70, 8, 220, 215, 80, 53, 152, 74, 136, 126, 87, 62, 230, 168, 2, 10, 237, 58, 51, 50].into();
-        let recverAddress : H160 = [140, 160, 200, 230, 190, 145, 185, 70, 100, 30, 122, 218, 43, 212, 90, 238, 170, 7, 122, 128].into();
-        // println!("{:?}", senderAddress);
-        // println!("{:?}", recverAddress);
-
-        //State Initialization
-        info!("ICO with 10,000 in two accounts");
-        self.states.lock().unwrap().accountMaping.insert(senderAddress, (0, 10000));
-        self.states.lock().unwrap().accountMaping.insert(recverAddress, (0, 10000));
-        println!("{:?}", self.states.lock().unwrap().accountMaping);
-
-        info!("{:?}", "Generate new transaction...");
         loop {
-            //let val : u32 = rng.gen();
-            info!("{:?}", self.states.lock().unwrap().accountMaping);
-            let val = 1;
-            let transaction = Transaction{recipAddress : recverAddress, val : val, accountNonce : accountNonce};
-            let signature = sign(&transaction, &key);
-            let signed_transaction = SignedTransaction{Transaction: transaction, Signature : signature.as_ref().to_vec(), public_key : key.public_key().as_ref().to_vec(), sender_addr : senderAddress};
-            self.txs.lock().unwrap().push_back(signed_transaction.clone());
-            self.mempool.lock().unwrap().insert(signed_transaction.hash(), signed_transaction.clone());
-            println!("mempool size: {}", self.mempool.lock().unwrap().len());
-            //println!("txs len: {}", self.txs.lock().unwrap().len());
-            let mut new_blockHash: Vec<H256> = Vec::new();
-            new_blockHash.push(signed_transaction.hash());
-            self.server.broadcast(Message::NewTransactionHashes(new_blockHash));
-
-            accountNonce += 1;
-
-            let interval = time::Duration::from_millis(1000);
-            thread::sleep(interval);
+            let signal = if self.running {
+                match self.control_chan.recv_timeout(self.workload.next_delay(&mut self.rng)) {
+                    Ok(signal) => Some(signal),
+                    Err(RecvTimeoutError::Timeout) => None,
+                    Err(RecvTimeoutError::Disconnected) => return,
+                }
+            } else {
+                match self.control_chan.recv() {
+                    Ok(signal) => Some(signal),
+                    Err(_) => return,
+                }
+            };
+            match signal {
+                Some(signal) => {
+                    if !self.handle_control_signal(signal) {
+                        return;
+                    }
+                }
+                None => self.send_next(),
+            }
+        }
+    }
+
+    /// Send the next transaction of the workload, after funding the accounts that need it
+    fn send_next(&mut self) {
+        let mut mempool = self.mempool.lock().unwrap();
+        let state = self.states.lock().unwrap().clone();
+        let mut sent = Vec::new();
+
+        let unfunded: Vec<H160> = self
+            .addresses
+            .iter()
+            .filter(|addr| !state.accountMaping.contains_key(addr) && !self.funded.contains(addr))
+            .cloned()
+            .collect();
+        if !unfunded.is_empty() {
+            let wallet = self.wallet.lock().unwrap();
+            let faucet = wallet.default_address();
+            for addr in unfunded {
+                match wallet.create_transaction(&faucet, &addr, ACCOUNT_FUNDING, None, &state, &mempool) {
+                    Ok(tx) => {
+                        if mempool::admit(&mut mempool, &tx, self.max_mempool, &self.events) != Admission::Added {
+                            break;
+                        }
+                        self.funded.insert(addr);
+                        sent.push(tx);
+                    }
+                    Err(e) => {
+                        debug!("Generator cannot fund account {}: {}", addr, e);
+                        break;
+                    }
+                }
+            }
+        }
+
+        let (from, to) = self.workload.pick(&mut self.rng, self.count);
+        let value = self.workload.values.sample(&mut self.rng);
+        let (from, to) = (self.addresses[from], self.addresses[to]);
+        let mut conflict = None;
+        match self.accounts.create_transaction(&from, &to, value, None, &state, &mempool) {
+            Ok(tx) => {
+                if mempool::admit(&mut mempool, &tx, self.max_mempool, &self.events) == Admission::Added {
+                    self.count += 1;
+                    if let Pattern::DoubleSpend = self.workload.pattern {
+                        // same nonce, paid back to the sender instead; our mempool refuses it,
+                        // so it is pushed to the peers directly to race the original
+                        let key = self.accounts.key_pair(&from).unwrap();
+                        let transaction = Transaction { recipAddress: from, ..tx.Transaction.clone() };
+                        let signature = sign(&transaction, &from, key);
+                        let double_spend = SignedTransaction { Transaction: transaction, Signature: signature.as_ref().to_vec(), public_key: key.public_key().as_ref().to_vec(), sender_addr: from, multisig: None };
+                        mempool::admit(&mut mempool, &double_spend, self.max_mempool, &self.events);
+                        conflict = Some(double_spend);
+                    }
+                    sent.push(tx);
+                }
+            }
+            Err(e) => debug!("Generator cannot pay from {}: {}", from, e),
+        }
+        drop(mempool);
+
+        if let Some(double_spend) = conflict {
+            self.status.lock().unwrap().sent += 1;
+            self.server.broadcast(Message::Transactions(vec![double_spend]));
+        }
+
+        if sent.is_empty() {
+            return;
         }
-        
+        let hashes: Vec<H256> = sent.iter().map(|tx| tx.hash()).collect();
+        self.txs.lock().unwrap().extend(sent);
+        self.status.lock().unwrap().sent += hashes.len() as u64;
+        self.server.broadcast(Message::NewTransactionHashes(hashes));
+    }
+}
 
-        
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_workload() {
+        let config = GeneratorConfig {
+            accounts: 3,
+            arrivals: "poisson".to_string(),
+            value: "uniform:2-5".to_string(),
+            pattern: "hotspot:90".to_string(),
+            ..GeneratorConfig::default()
+        };
+        let workload = Workload::parse(&config).unwrap();
+        assert_eq!(workload.arrivals, Arrivals::Poisson);
+        assert_eq!(workload.values, Values::Uniform(2, 5));
+        assert_eq!(workload.pattern, Pattern::HotSpot(90));
+        assert_eq!("exponential:7".parse::<Values>(), Ok(Values::Exponential(7)));
+
+        for (key, bad) in [("value", "uniform:5-2"), ("value", "normal:3"), ("pattern", "hotspot:101"), ("arrivals", "bursty")] {
+            let mut config = config.clone();
+            match key {
+                "value" => config.value = bad.to_string(),
+                "pattern" => config.pattern = bad.to_string(),
+                _ => config.arrivals = bad.to_string(),
+            }
+            assert!(Workload::parse(&config).is_err(), "{} accepted", bad);
+        }
+        assert!(Workload::parse(&GeneratorConfig { accounts: 1, ..config }).is_err());
+    }
+
+    #[test]
+    fn workload_is_reproducible() {
+        let config = GeneratorConfig { accounts: 4, pattern: "chain".to_string(), ..GeneratorConfig::default() };
+        let workload = Workload::parse(&config).unwrap();
+        assert_eq!(workload.wallet(0).addresses(), workload.wallet(0).addresses());
+        assert_ne!(workload.wallet(0).addresses(), Workload { seed: 1, ..workload.clone() }.wallet(0).addresses());
+
+        let mut rng = StdRng::seed_from_u64(0);
+        let pairs: Vec<(usize, usize)> = (0..5).map(|i| workload.pick(&mut rng, i)).collect();
+        assert_eq!(pairs, vec![(0, 1), (1, 2), (2, 3), (3, 0), (0, 1)]);
+
+        let uniform = Workload { pattern: Pattern::Uniform, ..workload };
+        for i in 0..100 {
+            let (from, to) = uniform.pick(&mut rng, i);
+            assert!(from != to && from < 4 && to < 4);
+        }
     }
 }
@@ -0,0 +1,245 @@
+//! JSON views of chain data for the read-only explorer endpoints. Hashes, addresses, keys and
+//! signatures are hex encoded.
+
+use serde::Serialize;
+use crate::block::{Block, Header};
+use crate::blockchain::{Blockchain, State};
+use crate::crypto::hash::{H256, H160, Hashable};
+use crate::transaction::SignedTransaction;
+use crate::mempool::Mempool;
+
+/// Upper bound on the number of headers or transactions returned by one range query
+pub const MAX_PAGE_SIZE: u64 = 500;
+
+#[derive(Serialize)]
+pub struct HeaderView {
+    pub hash: String,
+    pub height: Option<u64>,
+    pub parent: String,
+    pub nonce: u32,
+    pub extra_nonce: u32,
+    pub difficulty: String,
+    pub timestamp: u128,
+    pub merkle_root: String,
+    pub uncles_root: String,
+    pub miner: String,
+    pub seal: String,
+}
+
+#[derive(Serialize)]
+pub struct TransactionView {
+    pub hash: String,
+    /// Hash including the signature and public key, see `SignedTransaction::witness_hash`
+    pub witness_hash: String,
+    pub sender: String,
+    pub recipient: String,
+    pub value: u32,
+    pub account_nonce: u16,
+    pub chain_id: u64,
+    /// The last height whose block may include the transaction, if any
+    pub expiry: Option<u64>,
+    pub public_key: String,
+    pub signature: String,
+    /// Set for transactions spending from a multisig account, whose key and signature are empty
+    pub multisig: Option<MultisigView>,
+}
+
+#[derive(Serialize)]
+pub struct MultisigView {
+    pub threshold: u8,
+    pub public_keys: Vec<String>,
+    /// Indices into `public_keys` of the keys that signed
+    pub signers: Vec<u8>,
+}
+
+#[derive(Serialize)]
+pub struct BlockView {
+    pub header: HeaderView,
+    pub transactions: Vec<TransactionView>,
+    /// Uncle headers, without heights since they are off the chain
+    pub uncles: Vec<HeaderView>,
+}
+
+#[derive(Serialize)]
+pub struct TransactionLookup {
+    pub transaction: TransactionView,
+    /// `None` while the transaction is still in the mempool
+    pub block: Option<String>,
+    pub height: Option<u64>,
+    pub confirmations: u64,
+}
+
+#[derive(Serialize)]
+pub struct AddressTransaction {
+    pub block: String,
+    pub height: Option<u64>,
+    pub index: usize,
+    pub transaction: TransactionView,
+}
+
+#[derive(Serialize)]
+pub struct AddressTransactions {
+    pub address: String,
+    pub total: usize,
+    pub page: usize,
+    pub limit: usize,
+    pub transactions: Vec<AddressTransaction>,
+}
+
+#[derive(Serialize)]
+pub struct AccountView {
+    pub address: String,
+    pub nonce: u16,
+    pub balance: u32,
+}
+
+impl HeaderView {
+    pub fn new(header: &Header, height: Option<u64>) -> Self {
+        Self {
+            hash: header.hash().to_string(),
+            height,
+            parent: header.parent.to_string(),
+            nonce: header.nonce,
+            extra_nonce: header.extra_nonce,
+            difficulty: header.difficulty.to_string(),
+            timestamp: header.timestamp,
+            merkle_root: header.merkle_root.to_string(),
+            uncles_root: header.uncles_root.to_string(),
+            miner: header.miner.to_string(),
+            seal: hex::encode(&header.seal),
+        }
+    }
+}
+
+impl From<&SignedTransaction> for TransactionView {
+    fn from(tx: &SignedTransaction) -> Self {
+        Self {
+            hash: tx.hash().to_string(),
+            witness_hash: tx.witness_hash().to_string(),
+            sender: tx.sender_addr.to_string(),
+            recipient: tx.Transaction.recipAddress.to_string(),
+            value: tx.Transaction.val,
+            account_nonce: tx.Transaction.accountNonce,
+            chain_id: tx.Transaction.chain_id,
+            expiry: tx.Transaction.expiry,
+            public_key: hex::encode(&tx.public_key),
+            signature: hex::encode(&tx.Signature),
+            multisig: tx.multisig.as_ref().map(|witness| MultisigView {
+                threshold: witness.policy.threshold,
+                public_keys: witness.policy.public_keys.iter().map(hex::encode).collect(),
+                signers: witness.signatures.iter().map(|(index, _)| *index).collect(),
+            }),
+        }
+    }
+}
+
+impl BlockView {
+    pub fn new(block: &Block, height: Option<u64>) -> Self {
+        Self {
+            header: HeaderView::new(&block.header, height),
+            transactions: block.content.data.iter().map(TransactionView::from).collect(),
+            uncles: block.content.uncles.iter().map(|uncle| HeaderView::new(uncle, None)).collect(),
+        }
+    }
+}
+
+pub fn tip(blockchain: &Blockchain) -> HeaderView {
+    let tip = blockchain.tip();
+    HeaderView::new(&blockchain.hash_blocks[&tip].header, blockchain.height(&tip))
+}
+
+/// The deepest block of the longest chain that the consensus engine treats as final
+pub fn finalized(blockchain: &Blockchain) -> HeaderView {
+    let finalized = blockchain.consensus.finalized(blockchain);
+    HeaderView::new(&blockchain.hash_blocks[&finalized].header, blockchain.height(&finalized))
+}
+
+pub fn block_by_hash(blockchain: &Blockchain, hash: &H256) -> Option<BlockView> {
+    let block = blockchain.hash_blocks.get(hash)?;
+    Some(BlockView::new(block, blockchain.height(hash)))
+}
+
+pub fn block_by_height(blockchain: &Blockchain, height: u64) -> Option<BlockView> {
+    let hash = blockchain.block_at_height(height)?;
+    block_by_hash(blockchain, &hash)
+}
+
+/// Headers of the longest chain starting at height `from`, at most `count` (capped by
+/// `MAX_PAGE_SIZE`) of them
+pub fn headers(blockchain: &Blockchain, from: u64, count: u64) -> Vec<HeaderView> {
+    let tip_height = blockchain.height(&blockchain.tip()).unwrap_or(0);
+    let count = count.min(MAX_PAGE_SIZE);
+    if from > tip_height || count == 0 {
+        return Vec::new();
+    }
+    let to = (from + count - 1).min(tip_height);
+    let mut views = Vec::new();
+    let mut pointer = blockchain.block_at_height(to).unwrap();
+    for height in (from..=to).rev() {
+        let header = &blockchain.hash_blocks[&pointer].header;
+        views.push(HeaderView::new(header, Some(height)));
+        pointer = header.parent;
+    }
+    views.reverse();
+    views
+}
+
+pub fn transaction(
+    blockchain: &Blockchain,
+    mempool: &Mempool,
+    hash: &H256,
+) -> Option<TransactionLookup> {
+    if let Some((block_hash, index)) = blockchain.find_transaction(hash) {
+        let height = blockchain.height(&block_hash).unwrap();
+        let tip_height = blockchain.height(&blockchain.tip()).unwrap();
+        let tx = &blockchain.hash_blocks[&block_hash].content.data[index];
+        return Some(TransactionLookup {
+            transaction: TransactionView::from(tx),
+            block: Some(block_hash.to_string()),
+            height: Some(height),
+            confirmations: tip_height - height + 1,
+        });
+    }
+    mempool.get(hash).map(|tx| TransactionLookup {
+        transaction: TransactionView::from(tx),
+        block: None,
+        height: None,
+        confirmations: 0,
+    })
+}
+
+/// One page of the transactions involving `address`, or `None` if the address index is disabled
+pub fn address_transactions(
+    blockchain: &Blockchain,
+    address: &H160,
+    page: usize,
+    limit: usize,
+) -> Option<AddressTransactions> {
+    let index = blockchain.addr_index.as_ref()?;
+    let limit = limit.min(MAX_PAGE_SIZE as usize);
+    let transactions = index
+        .transactions(address, page, limit)
+        .into_iter()
+        .map(|(block_hash, i)| AddressTransaction {
+            block: block_hash.to_string(),
+            height: blockchain.height(&block_hash),
+            index: i,
+            transaction: TransactionView::from(&blockchain.hash_blocks[&block_hash].content.data[i]),
+        })
+        .collect();
+    Some(AddressTransactions {
+        address: address.to_string(),
+        total: index.count(address),
+        page,
+        limit,
+        transactions,
+    })
+}
+
+pub fn account(state: &State, address: &H160) -> Option<AccountView> {
+    state.accountMaping.get(address).map(|(nonce, balance)| AccountView {
+        address: address.to_string(),
+        nonce: *nonce,
+        balance: *balance,
+    })
+}
@@ -0,0 +1,86 @@
+//! Block templates for miners running outside the node. A miner fetches a template, searches
+//! for a nonce and submits the solved block, which is checked with `validation::validate_block`
+//! like any other.
+
+use serde::Serialize;
+use crate::block::Block;
+use crate::blockchain::Blockchain;
+use crate::crypto::domain;
+use crate::crypto::hash::{H160, Hashable};
+use crate::miner::block_template;
+use crate::transaction::SignedTransaction;
+use crate::validation::timestamp_bounds;
+use std::collections::VecDeque;
+
+#[derive(Serialize)]
+pub struct TemplateTransaction {
+    pub hash: String,
+    /// Hex encoding of the bincode-serialized `SignedTransaction`
+    pub data: String,
+}
+
+/// Everything needed to build a block on the current tip. Hashes and addresses are hex encoded.
+#[derive(Serialize)]
+pub struct BlockTemplate {
+    pub parent: String,
+    /// Height the new block will have
+    pub height: u64,
+    /// The block hash must not exceed this target
+    pub difficulty: String,
+    pub min_timestamp: u128,
+    pub max_timestamp: u128,
+    pub merkle_root: String,
+    pub uncles_root: String,
+    pub miner: String,
+    /// Hex of the canonical encoding of the header, with zero `nonce` and `extra_nonce` and the
+    /// current time. The block hash is the `Domain::Header` hash of this encoding, see
+    /// `crypto::domain`.
+    pub header: String,
+    /// Proof of work the header must satisfy, as named by `ProofOfWork::name`
+    pub pow: String,
+    /// The block content, in order
+    pub transactions: Vec<TemplateTransaction>,
+    /// Hex encodings of the bincode-serialized uncle headers, in order
+    pub uncles: Vec<String>,
+}
+
+/// Build a template on the tip with up to `max_txs` transactions from the front of `txs`, or
+/// `None` if there is nothing to include
+pub fn template(
+    blockchain: &Blockchain,
+    txs: &VecDeque<SignedTransaction>,
+    max_txs: usize,
+    miner: &H160,
+) -> Option<BlockTemplate> {
+    let block = block_template(blockchain, txs, max_txs, miner)?;
+    let header = &block.header;
+    let (min_timestamp, max_timestamp) = timestamp_bounds(&blockchain.hash_blocks[&header.parent].header);
+    Some(BlockTemplate {
+        parent: header.parent.to_string(),
+        height: blockchain.height(&header.parent).unwrap_or(0) + 1,
+        difficulty: header.difficulty.to_string(),
+        min_timestamp,
+        max_timestamp,
+        merkle_root: header.merkle_root.to_string(),
+        uncles_root: header.uncles_root.to_string(),
+        miner: miner.to_string(),
+        header: hex::encode(domain::encode(header)),
+        pow: blockchain.pow.name(),
+        transactions: block
+            .content
+            .data
+            .iter()
+            .map(|tx| TemplateTransaction {
+                hash: tx.hash().to_string(),
+                data: hex::encode(bincode::serialize(tx).unwrap()),
+            })
+            .collect(),
+        uncles: block.content.uncles.iter().map(|uncle| hex::encode(bincode::serialize(uncle).unwrap())).collect(),
+    })
+}
+
+/// Decode a submitted block, the hex encoding of the bincode-serialized `Block`
+pub fn decode_block(encoded: &str) -> Result<Block, String> {
+    let bytes = hex::decode(encoded.trim()).map_err(|e| format!("error parsing block: {}", e))?;
+    bincode::deserialize(&bytes).map_err(|e| format!("error decoding block: {}", e))
+}
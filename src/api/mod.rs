@@ -1,11 +1,33 @@
+mod explorer;
+mod mining;
+mod rpc;
+
 use serde::Serialize;
 use crate::miner::Handle as MinerHandle;
+use crate::pool::Handle as PoolHandle;
+use crate::TransGen::{ConfigureError, Handle as GeneratorHandle};
+use crate::config::Config;
 use crate::network::server::Handle as NetworkServerHandle;
 use crate::network::message::Message;
+use crate::blockchain::{Blockchain, State};
+use crate::crypto::hash::{H256, H160, Hashable};
+use crate::transaction::{MultisigPolicy, SignedTransaction};
+use crate::verifier;
+use crate::wallet::Wallet;
+use crate::block::Block;
+use crate::miner;
+use crate::validation;
+use crate::events::EventBus;
+use crate::mempool::{self, Admission, Mempool};
+use crate::metrics;
+use crate::shutdown::Shutdown;
+use crate::node::Shared;
 
 use log::info;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::Duration;
 use tiny_http::Header;
 use tiny_http::Response;
 use tiny_http::Server as HTTPServer;
@@ -13,10 +35,31 @@ use url::Url;
 
 pub struct Server {
     handle: HTTPServer,
+    ctx: Context,
+}
+
+/// Handles to the node components that API requests act on
+#[derive(Clone)]
+pub struct Context {
     miner: MinerHandle,
     network: NetworkServerHandle,
+    blockchain: Arc<Mutex<Blockchain>>,
+    mempool: Arc<Mutex<Mempool>>,
+    /// Transactions are refused once the mempool holds this many
+    max_mempool: usize,
+    states: Arc<Mutex<State>>,
+    txs: Arc<Mutex<VecDeque<SignedTransaction>>>,
+    wallet: Arc<Mutex<Wallet>>,
+    events: EventBus,
+    /// `None` unless the node runs a mining pool
+    pool: Option<PoolHandle>,
+    generator: GeneratorHandle,
+    shutdown: Shutdown,
 }
 
+/// Longest time an `/events` long-poll is held open
+const MAX_POLL_TIMEOUT_MS: u64 = 30_000;
+
 #[derive(Serialize)]
 struct ApiResponse {
     success: bool,
@@ -24,35 +67,202 @@ struct ApiResponse {
 }
 
 macro_rules! respond_result {
-    ( $req:expr, $success:expr, $message:expr ) => {{
+    ( $req:expr, $success:expr, $message:expr ) => {
+        respond_result!($req, $success, $message, 200)
+    };
+    ( $req:expr, $success:expr, $message:expr, $status:expr ) => {{
         let content_type = "Content-Type: application/json".parse::<Header>().unwrap();
         let payload = ApiResponse {
             success: $success,
             message: $message.to_string(),
         };
         let resp = Response::from_string(serde_json::to_string_pretty(&payload).unwrap())
+            .with_header(content_type)
+            .with_status_code($status);
+        $req.respond(resp).unwrap();
+    }};
+}
+
+macro_rules! respond_json {
+    ( $req:expr, $payload:expr ) => {{
+        let content_type = "Content-Type: application/json".parse::<Header>().unwrap();
+        let resp = Response::from_string(serde_json::to_string_pretty(&$payload).unwrap())
             .with_header(content_type);
         $req.respond(resp).unwrap();
     }};
 }
 
+/// Look up and parse a query parameter, producing the error message returned to the client
+fn query_param<T>(params: &HashMap<String, String>, name: &str) -> Result<T, String>
+where
+    T: std::str::FromStr,
+    T::Err: std::fmt::Display,
+{
+    match params.get(name) {
+        Some(v) => v
+            .parse::<T>()
+            .map_err(|e| format!("error parsing {}: {}", name, e)),
+        None => Err(format!("missing {}", name)),
+    }
+}
+
+/// Like `query_param`, but `default` if the parameter is missing
+fn query_param_or<T>(params: &HashMap<String, String>, name: &str, default: T) -> Result<T, String>
+where
+    T: std::str::FromStr,
+    T::Err: std::fmt::Display,
+{
+    if params.contains_key(name) {
+        query_param(params, name)
+    } else {
+        Ok(default)
+    }
+}
+
+impl Context {
+    /// Build a transfer with a wallet key (the default one if `from` is `None`), expiring after
+    /// height `expiry` if set, add it to the mempool and announce it to peers
+    pub fn wallet_send(&self, from: Option<H160>, to: &H160, value: u32, expiry: Option<u64>) -> Result<H256, String> {
+        let wallet = self.wallet.lock().unwrap();
+        let from = from.unwrap_or_else(|| wallet.default_address());
+        // hold the mempool lock until the transaction is queued, so that
+        // concurrent sends from the same account get consecutive nonces
+        let mut mempool = self.mempool.lock().unwrap();
+        let signed_transaction = {
+            let states = self.states.lock().unwrap();
+            wallet
+                .create_transaction(&from, to, value, expiry, &states, &mempool)
+                .map_err(|e| e.to_string())?
+        };
+        let tx_hash = signed_transaction.hash();
+        match mempool::admit(&mut mempool, &signed_transaction, self.max_mempool, &self.events) {
+            Admission::Added => {}
+            Admission::Known => return Err("transaction already in mempool".to_string()),
+            Admission::Conflict(existing) => return Err(format!("transaction conflicts with {} in mempool", existing)),
+            Admission::Full => return Err("mempool is full".to_string()),
+        }
+        self.txs.lock().unwrap().push_back(signed_transaction);
+        drop(mempool);
+        self.network.broadcast(Message::NewTransactionHashes(vec![tx_hash]));
+        Ok(tx_hash)
+    }
+
+    /// Build a transfer from the multisig account of `policy`, signed with the keys of the policy
+    /// held by the wallet. It is not added to the mempool: the other signers complete it first.
+    pub fn wallet_multisig_propose(
+        &self,
+        policy: &MultisigPolicy,
+        to: &H160,
+        value: u32,
+        expiry: Option<u64>,
+    ) -> Result<SignedTransaction, String> {
+        let wallet = self.wallet.lock().unwrap();
+        let mempool = self.mempool.lock().unwrap();
+        let states = self.states.lock().unwrap();
+        wallet
+            .create_multisig_transaction(policy, to, value, expiry, &states, &mempool)
+            .map_err(|e| e.to_string())
+    }
+
+    /// Add the signatures of the wallet's keys to a multisig transaction proposed elsewhere
+    pub fn wallet_cosign(&self, mut signed_transaction: SignedTransaction) -> Result<SignedTransaction, String> {
+        self.wallet.lock().unwrap().cosign(&mut signed_transaction).map_err(|e| e.to_string())?;
+        Ok(signed_transaction)
+    }
+
+    /// Check the signature of a transaction signed elsewhere, add it to the mempool and
+    /// announce it to peers
+    pub fn submit_transaction(&self, signed_transaction: SignedTransaction) -> Result<H256, String> {
+        if verifier::global().verify_all(std::slice::from_ref(&signed_transaction)).is_err() {
+            return Err("invalid signature or sender address".to_string());
+        }
+        {
+            let blockchain = self.blockchain.lock().unwrap();
+            let height = blockchain.height(&blockchain.tip()).unwrap_or(0) + 1;
+            validation::check_transaction(&signed_transaction.Transaction, blockchain.chain_id(), height)
+                .map_err(|e| e.to_string())?;
+        }
+        let tx_hash = signed_transaction.hash();
+        let mut mempool = self.mempool.lock().unwrap();
+        match mempool::admit(&mut mempool, &signed_transaction, self.max_mempool, &self.events) {
+            Admission::Added => {}
+            Admission::Known => return Err("transaction already in mempool".to_string()),
+            Admission::Conflict(existing) => return Err(format!("transaction conflicts with {} in mempool", existing)),
+            Admission::Full => return Err("mempool is full".to_string()),
+        }
+        self.txs.lock().unwrap().push_back(signed_transaction);
+        drop(mempool);
+        self.network.broadcast(Message::NewTransactionHashes(vec![tx_hash]));
+        Ok(tx_hash)
+    }
+
+    /// Template for a block on the tip paying `miner` (the default wallet address if `None`),
+    /// with at most `max_txs` transactions (the miner's current setting if `None`)
+    pub fn block_template(&self, miner: Option<H160>, max_txs: Option<usize>) -> Result<mining::BlockTemplate, String> {
+        let miner = miner.unwrap_or_else(|| self.wallet.lock().unwrap().default_address());
+        let max_txs = max_txs.unwrap_or_else(|| self.miner.status().max_txs);
+        let blockchain = self.blockchain.lock().unwrap();
+        let txs = self.txs.lock().unwrap();
+        mining::template(&blockchain, &txs, max_txs, &miner).ok_or_else(|| "no transactions to include".to_string())
+    }
+
+    /// Validate a block solved outside the node, add it to the chain and announce it to peers
+    pub fn submit_block(&self, block: Block) -> Result<H256, String> {
+        validation::validate_block(&block, &self.blockchain.lock().unwrap()).map_err(|e| e.to_string())?;
+        miner::accept_block(&block, &self.blockchain, &self.mempool, &self.states, &self.txs, &self.network);
+        Ok(block.hash())
+    }
+}
+
 impl Server {
+    /// Serve the API at `addr`. If `auth_token` is set, requests without the header
+    /// `Authorization: Bearer <auth_token>` are refused.
     pub fn start(
         addr: std::net::SocketAddr,
+        auth_token: Option<String>,
+        node: &Shared,
         miner: &MinerHandle,
-        network: &NetworkServerHandle,
+        pool: Option<&PoolHandle>,
+        generator: &GeneratorHandle,
+        shutdown: &Shutdown,
     ) {
-        let handle = HTTPServer::http(&addr).unwrap();
-        let server = Self {
-            handle,
+        let handle = HTTPServer::http(addr).unwrap();
+        let ctx = Context {
             miner: miner.clone(),
-            network: network.clone(),
+            network: node.server.clone(),
+            blockchain: Arc::clone(&node.blockchain),
+            mempool: Arc::clone(&node.mempool),
+            max_mempool: node.max_mempool,
+            states: Arc::clone(&node.states),
+            txs: Arc::clone(&node.txs),
+            wallet: Arc::clone(&node.wallet),
+            events: node.events.clone(),
+            pool: pool.cloned(),
+            generator: generator.clone(),
+            shutdown: shutdown.clone(),
         };
+        let server = Self { handle, ctx };
+        let auth = auth_token.map(|token| format!("Bearer {}", token));
         thread::spawn(move || {
             for req in server.handle.incoming_requests() {
-                let miner = server.miner.clone();
-                let network = server.network.clone();
+                let ctx = server.ctx.clone();
+                let auth = auth.clone();
                 thread::spawn(move || {
+                    if let Some(auth) = auth {
+                        let authorized = req
+                            .headers()
+                            .iter()
+                            .any(|h| h.field.equiv("Authorization") && h.value.as_str() == auth);
+                        if !authorized {
+                            let payload = ApiResponse { success: false, message: "unauthorized".to_string() };
+                            let content_type = "Content-Type: application/json".parse::<Header>().unwrap();
+                            let resp = Response::from_string(serde_json::to_string_pretty(&payload).unwrap())
+                                .with_header(content_type)
+                                .with_status_code(401);
+                            req.respond(resp).unwrap();
+                            return;
+                        }
+                    }
                     // a valid url requires a base
                     let base_url = Url::parse(&format!("http://{}/", &addr)).unwrap();
                     let url = match base_url.join(req.url()) {
@@ -63,6 +273,46 @@ impl Server {
                         }
                     };
                     match url.path() {
+                        "/rpc" => {
+                            let mut req = req;
+                            let mut body = String::new();
+                            if let Err(e) = req.as_reader().read_to_string(&mut body) {
+                                respond_result!(req, false, format!("error reading body: {}", e));
+                                return;
+                            }
+                            match rpc::handle(&ctx, &body) {
+                                Some(payload) => {
+                                    let content_type = "Content-Type: application/json".parse::<Header>().unwrap();
+                                    req.respond(Response::from_string(payload).with_header(content_type)).unwrap();
+                                }
+                                None => {
+                                    req.respond(Response::empty(204)).unwrap();
+                                }
+                            }
+                        }
+                        "/metrics" => {
+                            // sampled on scrape rather than tracked at every mutation
+                            metrics::global().mempool_size.set(ctx.mempool.lock().unwrap().len() as f64);
+                            if let Ok(peers) = ctx.network.peers() {
+                                metrics::global().peers.set(peers.len() as f64);
+                            }
+                            let content_type = "Content-Type: text/plain; version=0.0.4".parse::<Header>().unwrap();
+                            let resp = Response::from_string(metrics::global().render()).with_header(content_type);
+                            req.respond(resp).unwrap();
+                        }
+                        "/events" => {
+                            let params: HashMap<_, _> = url.query_pairs().into_owned().collect();
+                            let since = query_param::<u64>(&params, "since").unwrap_or(0);
+                            let timeout = query_param::<u64>(&params, "timeout")
+                                .unwrap_or(MAX_POLL_TIMEOUT_MS)
+                                .min(MAX_POLL_TIMEOUT_MS);
+                            let kinds: Vec<String> = match params.get("types") {
+                                Some(v) => v.split(',').map(|k| k.trim().to_string()).filter(|k| !k.is_empty()).collect(),
+                                None => Vec::new(),
+                            };
+                            let poll = ctx.events.poll(since, &kinds, Duration::from_millis(timeout));
+                            respond_json!(req, poll);
+                        }
                         "/miner/start" => {
                             let params = url.query_pairs();
                             let params: HashMap<_, _> = params.into_owned().collect();
@@ -84,17 +334,317 @@ impl Server {
                                     return;
                                 }
                             };
-                            miner.start(lambda);
-                            respond_result!(req, true, "ok");
+                            match ctx.miner.start(lambda) {
+                                Ok(()) => respond_result!(req, true, "ok"),
+                                Err(e) => respond_result!(req, false, e, 503),
+                            }
+                        }
+                        "/miner/status" => {
+                            respond_json!(req, ctx.miner.status());
                         }
-                        "/miner/stop" => {
-                            miner.exit();
+                        "/miner/stop" | "/miner/pause" => match ctx.miner.pause() {
+                            Ok(()) => respond_result!(req, true, "ok"),
+                            Err(e) => respond_result!(req, false, e, 503),
+                        },
+                        "/miner/resume" => match ctx.miner.resume() {
+                            Ok(()) => respond_result!(req, true, "ok"),
+                            Err(e) => respond_result!(req, false, e, 503),
+                        },
+                        "/miner/update" => {
+                            let params: HashMap<_, _> = url.query_pairs().into_owned().collect();
+                            if params.is_empty() {
+                                respond_result!(req, false, "nothing to update");
+                                return;
+                            }
+                            // validate everything before applying anything
+                            let lambda = match params.get("lambda").map(|_| query_param::<u64>(&params, "lambda")).transpose() {
+                                Ok(v) => v,
+                                Err(e) => {
+                                    respond_result!(req, false, e);
+                                    return;
+                                }
+                            };
+                            let address = match params.get("address").map(|_| query_param::<H160>(&params, "address")).transpose() {
+                                Ok(v) => v,
+                                Err(e) => {
+                                    respond_result!(req, false, e);
+                                    return;
+                                }
+                            };
+                            let max_txs = match params.get("max_txs").map(|_| query_param::<usize>(&params, "max_txs")).transpose() {
+                                Ok(v) => v,
+                                Err(e) => {
+                                    respond_result!(req, false, e);
+                                    return;
+                                }
+                            };
+                            let threads = match params.get("threads").map(|_| query_param::<usize>(&params, "threads")).transpose() {
+                                Ok(v) => v,
+                                Err(e) => {
+                                    respond_result!(req, false, e);
+                                    return;
+                                }
+                            };
+                            let applied = lambda.map_or(Ok(()), |lambda| ctx.miner.set_lambda(lambda))
+                                .and_then(|_| address.map_or(Ok(()), |address| ctx.miner.set_address(address)))
+                                .and_then(|_| max_txs.map_or(Ok(()), |max_txs| ctx.miner.set_max_txs(max_txs)))
+                                .and_then(|_| threads.map_or(Ok(()), |threads| ctx.miner.set_threads(threads)));
+                            match applied {
+                                Ok(()) => respond_result!(req, true, "ok"),
+                                Err(e) => respond_result!(req, false, e, 503),
+                            }
+                        }
+                        "/mining/template" => {
+                            let params: HashMap<_, _> = url.query_pairs().into_owned().collect();
+                            let address = match params.get("address").map(|_| query_param::<H160>(&params, "address")).transpose() {
+                                Ok(v) => v,
+                                Err(e) => {
+                                    respond_result!(req, false, e);
+                                    return;
+                                }
+                            };
+                            let max_txs = match params.get("max_txs").map(|_| query_param::<usize>(&params, "max_txs")).transpose() {
+                                Ok(v) => v,
+                                Err(e) => {
+                                    respond_result!(req, false, e);
+                                    return;
+                                }
+                            };
+                            match ctx.block_template(address, max_txs) {
+                                Ok(template) => respond_json!(req, template),
+                                Err(e) => respond_result!(req, false, e),
+                            }
+                        }
+                        "/mining/submit" => {
+                            let mut req = req;
+                            let mut body = String::new();
+                            if let Err(e) = req.as_reader().read_to_string(&mut body) {
+                                respond_result!(req, false, format!("error reading body: {}", e));
+                                return;
+                            }
+                            let block = match mining::decode_block(&body) {
+                                Ok(block) => block,
+                                Err(e) => {
+                                    respond_result!(req, false, e);
+                                    return;
+                                }
+                            };
+                            match ctx.submit_block(block) {
+                                Ok(hash) => respond_result!(req, true, hash),
+                                Err(e) => respond_result!(req, false, e),
+                            }
+                        }
+                        "/pool/stats" => match &ctx.pool {
+                            Some(pool) => respond_json!(req, pool.stats()),
+                            None => respond_result!(req, false, "pool is not running"),
+                        },
+                        "/generator/start" => match ctx.generator.start() {
+                            Ok(()) => respond_result!(req, true, "ok"),
+                            Err(e) => respond_result!(req, false, e, 503),
+                        },
+                        "/generator/stop" => match ctx.generator.stop() {
+                            Ok(()) => respond_result!(req, true, "ok"),
+                            Err(e) => respond_result!(req, false, e, 503),
+                        },
+                        "/generator/config" => {
+                            // without parameters, report the workload; otherwise change the given settings,
+                            // named as the `generator` settings of the config file
+                            let params: HashMap<_, _> = url.query_pairs().into_owned().collect();
+                            if params.is_empty() {
+                                respond_json!(req, ctx.generator.status());
+                                return;
+                            }
+                            let mut config = Config { generator: ctx.generator.status().config, ..Config::default() };
+                            let updated = params
+                                .iter()
+                                .try_for_each(|(key, value)| config.set(&format!("generator.{}", key), value))
+                                .map_err(ConfigureError::Invalid)
+                                .and_then(|_| ctx.generator.configure(config.generator));
+                            match updated {
+                                Ok(()) => respond_result!(req, true, "ok"),
+                                Err(e @ ConfigureError::Stopped(_)) => respond_result!(req, false, e, 503),
+                                Err(e) => respond_result!(req, false, e),
+                            }
+                        }
+                        "/node/shutdown" => {
+                            info!("Shutdown requested through the API");
+                            // answer first, the process may be gone soon after the request
                             respond_result!(req, true, "ok");
+                            ctx.shutdown.request();
                         }
                         "/network/ping" => {
-                            network.broadcast(Message::Ping(String::from("Test ping")));
+                            ctx.network.broadcast(Message::Ping(String::from("Test ping")));
                             respond_result!(req, true, "ok");
                         }
+                        "/wallet/send" => {
+                            let params = url.query_pairs();
+                            let params: HashMap<_, _> = params.into_owned().collect();
+                            let to = match params.get("to") {
+                                Some(v) => v,
+                                None => {
+                                    respond_result!(req, false, "missing to");
+                                    return;
+                                }
+                            };
+                            let to = match to.parse::<H160>() {
+                                Ok(v) => v,
+                                Err(e) => {
+                                    respond_result!(req, false, format!("error parsing to: {}", e));
+                                    return;
+                                }
+                            };
+                            let value = match params.get("value") {
+                                Some(v) => v,
+                                None => {
+                                    respond_result!(req, false, "missing value");
+                                    return;
+                                }
+                            };
+                            let value = match value.parse::<u32>() {
+                                Ok(v) => v,
+                                Err(e) => {
+                                    respond_result!(req, false, format!("error parsing value: {}", e));
+                                    return;
+                                }
+                            };
+                            let from = match params.get("from") {
+                                Some(v) => match v.parse::<H160>() {
+                                    Ok(v) => Some(v),
+                                    Err(e) => {
+                                        respond_result!(req, false, format!("error parsing from: {}", e));
+                                        return;
+                                    }
+                                },
+                                None => None,
+                            };
+                            let expiry = match params.get("expiry").map(|_| query_param::<u64>(&params, "expiry")).transpose() {
+                                Ok(v) => v,
+                                Err(e) => {
+                                    respond_result!(req, false, e);
+                                    return;
+                                }
+                            };
+                            match ctx.wallet_send(from, &to, value, expiry) {
+                                Ok(tx_hash) => respond_result!(req, true, tx_hash),
+                                Err(e) => respond_result!(req, false, e),
+                            }
+                        }
+                        "/blockchain/tip" => {
+                            let blockchain = ctx.blockchain.lock().unwrap();
+                            respond_json!(req, explorer::tip(&blockchain));
+                        }
+                        "/blockchain/finalized" => {
+                            let blockchain = ctx.blockchain.lock().unwrap();
+                            respond_json!(req, explorer::finalized(&blockchain));
+                        }
+                        "/blockchain/block" => {
+                            let params: HashMap<_, _> = url.query_pairs().into_owned().collect();
+                            let blockchain = ctx.blockchain.lock().unwrap();
+                            let view = if params.contains_key("hash") {
+                                match query_param::<H256>(&params, "hash") {
+                                    Ok(hash) => explorer::block_by_hash(&blockchain, &hash),
+                                    Err(e) => {
+                                        respond_result!(req, false, e);
+                                        return;
+                                    }
+                                }
+                            } else {
+                                match query_param::<u64>(&params, "height") {
+                                    Ok(height) => explorer::block_by_height(&blockchain, height),
+                                    Err(e) => {
+                                        respond_result!(req, false, e);
+                                        return;
+                                    }
+                                }
+                            };
+                            match view {
+                                Some(view) => respond_json!(req, view),
+                                None => respond_result!(req, false, "block not found"),
+                            }
+                        }
+                        "/blockchain/headers" => {
+                            let params: HashMap<_, _> = url.query_pairs().into_owned().collect();
+                            let from = match query_param::<u64>(&params, "from") {
+                                Ok(v) => v,
+                                Err(e) => {
+                                    respond_result!(req, false, e);
+                                    return;
+                                }
+                            };
+                            let count = match query_param::<u64>(&params, "count") {
+                                Ok(v) => v,
+                                Err(e) => {
+                                    respond_result!(req, false, e);
+                                    return;
+                                }
+                            };
+                            let blockchain = ctx.blockchain.lock().unwrap();
+                            respond_json!(req, explorer::headers(&blockchain, from, count));
+                        }
+                        "/transaction" => {
+                            let params: HashMap<_, _> = url.query_pairs().into_owned().collect();
+                            let hash = match query_param::<H256>(&params, "hash") {
+                                Ok(v) => v,
+                                Err(e) => {
+                                    respond_result!(req, false, e);
+                                    return;
+                                }
+                            };
+                            let blockchain = ctx.blockchain.lock().unwrap();
+                            let mempool = ctx.mempool.lock().unwrap();
+                            match explorer::transaction(&blockchain, &mempool, &hash) {
+                                Some(view) => respond_json!(req, view),
+                                None => respond_result!(req, false, "transaction not found"),
+                            }
+                        }
+                        "/address/transactions" => {
+                            let params: HashMap<_, _> = url.query_pairs().into_owned().collect();
+                            let address = match query_param::<H160>(&params, "address") {
+                                Ok(v) => v,
+                                Err(e) => {
+                                    respond_result!(req, false, e);
+                                    return;
+                                }
+                            };
+                            let page = match query_param_or::<usize>(&params, "page", 0) {
+                                Ok(v) => v,
+                                Err(e) => {
+                                    respond_result!(req, false, e);
+                                    return;
+                                }
+                            };
+                            let limit = match query_param_or::<usize>(&params, "limit", 50) {
+                                Ok(0) => {
+                                    respond_result!(req, false, "limit must be positive");
+                                    return;
+                                }
+                                Ok(v) => v,
+                                Err(e) => {
+                                    respond_result!(req, false, e);
+                                    return;
+                                }
+                            };
+                            let blockchain = ctx.blockchain.lock().unwrap();
+                            match explorer::address_transactions(&blockchain, &address, page, limit) {
+                                Some(view) => respond_json!(req, view),
+                                None => respond_result!(req, false, "address index is disabled"),
+                            }
+                        }
+                        "/account" => {
+                            let params: HashMap<_, _> = url.query_pairs().into_owned().collect();
+                            let address = match query_param::<H160>(&params, "address") {
+                                Ok(v) => v,
+                                Err(e) => {
+                                    respond_result!(req, false, e);
+                                    return;
+                                }
+                            };
+                            let states = ctx.states.lock().unwrap();
+                            match explorer::account(&states, &address) {
+                                Some(view) => respond_json!(req, view),
+                                None => respond_result!(req, false, "account not found"),
+                            }
+                        }
                         _ => {
                             let content_type =
                                 "Content-Type: application/json".parse::<Header>().unwrap();
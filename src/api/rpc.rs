@@ -0,0 +1,528 @@
+//! JSON-RPC 2.0 interface to the node, served at `POST /rpc`. Supports single and batch requests
+//! and notifications (requests without an `id`, which get no response).
+
+use serde::{Serialize, Deserialize, Deserializer};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use super::explorer;
+use super::Context;
+use crate::crypto::hash::{H256, H160, Hashable};
+use crate::network::message::Message;
+use crate::shutdown::Stopped;
+use crate::transaction::{MultisigPolicy, SignedTransaction};
+use log::info;
+
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+pub const INTERNAL_ERROR: i64 = -32603;
+/// The requested block, transaction or account does not exist
+pub const NOT_FOUND: i64 = -32000;
+/// The node refused to carry out the request, e.g. a transaction failed validation
+pub const REJECTED: i64 = -32001;
+/// The component that would carry out the request has stopped, e.g. during shutdown
+pub const UNAVAILABLE: i64 = -32002;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Request {
+    pub jsonrpc: String,
+    pub method: String,
+    #[serde(default)]
+    pub params: Value,
+    /// Absent for notifications. A null id is still a request, answered with a null id.
+    #[serde(default, deserialize_with = "present", skip_serializing_if = "Option::is_none")]
+    pub id: Option<Value>,
+}
+
+/// Deserialize a field that is present, even if null, as `Some`; `default` covers its absence
+fn present<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Value>, D::Error> {
+    Value::deserialize(deserializer).map(Some)
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Response {
+    pub jsonrpc: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<Error>,
+    pub id: Value,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Error {
+    pub code: i64,
+    pub message: String,
+}
+
+impl Error {
+    fn new(code: i64, message: impl std::fmt::Display) -> Self {
+        Self { code, message: message.to_string() }
+    }
+}
+
+impl From<Stopped> for Error {
+    fn from(e: Stopped) -> Self {
+        Self::new(UNAVAILABLE, e)
+    }
+}
+
+impl Response {
+    fn new(id: Value, outcome: Result<Value, Error>) -> Self {
+        let (result, error) = match outcome {
+            Ok(v) => (Some(v), None),
+            Err(e) => (None, Some(e)),
+        };
+        Self { jsonrpc: "2.0".to_string(), result, error, id }
+    }
+}
+
+#[derive(Deserialize)]
+struct LambdaParams {
+    lambda: u64,
+}
+
+#[derive(Deserialize)]
+struct MinerUpdateParams {
+    lambda: Option<u64>,
+    address: Option<String>,
+    max_txs: Option<usize>,
+    threads: Option<usize>,
+}
+
+#[derive(Deserialize)]
+struct TemplateParams {
+    address: Option<String>,
+    max_txs: Option<usize>,
+}
+
+#[derive(Deserialize)]
+struct BlockParams {
+    /// Hex encoding of the bincode-serialized `Block`
+    block: String,
+}
+
+#[derive(Deserialize)]
+struct HashParams {
+    hash: String,
+}
+
+#[derive(Deserialize)]
+struct HeightParams {
+    height: u64,
+}
+
+#[derive(Deserialize)]
+struct RangeParams {
+    from: u64,
+    count: u64,
+}
+
+#[derive(Deserialize)]
+struct AddressParams {
+    address: String,
+    #[serde(default)]
+    page: usize,
+    #[serde(default = "default_limit")]
+    limit: usize,
+}
+
+fn default_limit() -> usize {
+    50
+}
+
+#[derive(Deserialize)]
+struct PeerParams {
+    addr: String,
+}
+
+#[derive(Deserialize)]
+struct SubmitParams {
+    /// Hex encoding of the bincode-serialized `SignedTransaction`
+    transaction: String,
+}
+
+#[derive(Deserialize)]
+struct MultisigParams {
+    threshold: u8,
+    /// Hex encoded Ed25519 public keys, in the order the account commits to
+    public_keys: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct ProposeParams {
+    #[serde(flatten)]
+    policy: MultisigParams,
+    to: String,
+    value: u32,
+    expiry: Option<u64>,
+}
+
+/// A multisig transaction being signed, passed from signer to signer until complete
+#[derive(Serialize)]
+struct PartialTransaction {
+    hash: String,
+    /// Hex encoding of the bincode-serialized `SignedTransaction`, for `wallet_multisigSign` or,
+    /// once complete, `tx_submit`
+    transaction: String,
+    signatures: usize,
+    threshold: u8,
+    complete: bool,
+}
+
+impl PartialTransaction {
+    fn new(tx: &SignedTransaction) -> Result<Self, Error> {
+        let witness = tx.multisig.as_ref().ok_or_else(|| Error::new(INTERNAL_ERROR, "not a multisig transaction"))?;
+        Ok(Self {
+            hash: tx.hash().to_string(),
+            transaction: hex::encode(bincode::serialize(tx).unwrap()),
+            signatures: witness.signatures.len(),
+            threshold: witness.policy.threshold,
+            complete: witness.is_complete(),
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct SendParams {
+    to: String,
+    value: u32,
+    from: Option<String>,
+    expiry: Option<u64>,
+}
+
+/// Handle the body of an RPC call, returning the serialized response, or `None` when there is
+/// nothing to respond with (only notifications were sent)
+pub fn handle(ctx: &Context, body: &str) -> Option<String> {
+    handle_with(body, &|method: &str, params: Value| dispatch(ctx, method, params))
+}
+
+/// `handle`, with each call carried out by `dispatch`
+fn handle_with(body: &str, dispatch: &dyn Fn(&str, Value) -> Result<Value, Error>) -> Option<String> {
+    let parsed: Value = match serde_json::from_str(body) {
+        Ok(v) => v,
+        Err(e) => {
+            let resp = Response::new(Value::Null, Err(Error::new(PARSE_ERROR, e)));
+            return Some(serde_json::to_string(&resp).unwrap());
+        }
+    };
+    match parsed {
+        Value::Array(calls) => {
+            if calls.is_empty() {
+                let resp = Response::new(Value::Null, Err(Error::new(INVALID_REQUEST, "empty batch")));
+                return Some(serde_json::to_string(&resp).unwrap());
+            }
+            let responses: Vec<Response> = calls.into_iter().filter_map(|call| handle_call(dispatch, call)).collect();
+            if responses.is_empty() {
+                None
+            } else {
+                Some(serde_json::to_string(&responses).unwrap())
+            }
+        }
+        call => handle_call(dispatch, call).map(|resp| serde_json::to_string(&resp).unwrap()),
+    }
+}
+
+fn handle_call(dispatch: &dyn Fn(&str, Value) -> Result<Value, Error>, call: Value) -> Option<Response> {
+    let request: Request = match serde_json::from_value(call) {
+        Ok(r) => r,
+        Err(e) => return Some(Response::new(Value::Null, Err(Error::new(INVALID_REQUEST, e)))),
+    };
+    if request.jsonrpc != "2.0" {
+        let id = request.id.unwrap_or(Value::Null);
+        return Some(Response::new(id, Err(Error::new(INVALID_REQUEST, "jsonrpc must be \"2.0\""))));
+    }
+    let outcome = dispatch(&request.method, request.params);
+    request.id.map(|id| Response::new(id, outcome))
+}
+
+fn params<T: DeserializeOwned>(params: Value) -> Result<T, Error> {
+    let params = if params.is_null() { Value::Object(Default::default()) } else { params };
+    serde_json::from_value(params).map_err(|e| Error::new(INVALID_PARAMS, e))
+}
+
+fn parse<T>(field: &str, value: &str) -> Result<T, Error>
+where
+    T: std::str::FromStr,
+    T::Err: std::fmt::Display,
+{
+    value
+        .parse::<T>()
+        .map_err(|e| Error::new(INVALID_PARAMS, format!("error parsing {}: {}", field, e)))
+}
+
+fn policy(p: &MultisigParams) -> Result<MultisigPolicy, Error> {
+    let public_keys = p
+        .public_keys
+        .iter()
+        .map(|key| hex::decode(key).map_err(|e| Error::new(INVALID_PARAMS, format!("error parsing public key: {}", e))))
+        .collect::<Result<Vec<Vec<u8>>, Error>>()?;
+    MultisigPolicy::new(p.threshold, public_keys).map_err(|e| Error::new(INVALID_PARAMS, e))
+}
+
+fn decode_transaction(transaction: &str) -> Result<SignedTransaction, Error> {
+    let bytes = hex::decode(transaction)
+        .map_err(|e| Error::new(INVALID_PARAMS, format!("error parsing transaction: {}", e)))?;
+    bincode::deserialize(&bytes).map_err(|e| Error::new(INVALID_PARAMS, format!("error decoding transaction: {}", e)))
+}
+
+fn to_value<T: Serialize>(result: T) -> Result<Value, Error> {
+    serde_json::to_value(result).map_err(|e| Error::new(INTERNAL_ERROR, e))
+}
+
+fn found<T: Serialize>(result: Option<T>, what: &str) -> Result<Value, Error> {
+    match result {
+        Some(v) => to_value(v),
+        None => Err(Error::new(NOT_FOUND, format!("{} not found", what))),
+    }
+}
+
+fn dispatch(ctx: &Context, method: &str, raw: Value) -> Result<Value, Error> {
+    match method {
+        "miner_start" => {
+            let p: LambdaParams = params(raw)?;
+            ctx.miner.start(p.lambda)?;
+            to_value(true)
+        }
+        "miner_stop" | "miner_pause" => {
+            ctx.miner.pause()?;
+            to_value(true)
+        }
+        "miner_resume" => {
+            ctx.miner.resume()?;
+            to_value(true)
+        }
+        "miner_update" => {
+            let p: MinerUpdateParams = params(raw)?;
+            let address = match p.address {
+                Some(address) => Some(parse::<H160>("address", &address)?),
+                None => None,
+            };
+            if let Some(lambda) = p.lambda {
+                ctx.miner.set_lambda(lambda)?;
+            }
+            if let Some(address) = address {
+                ctx.miner.set_address(address)?;
+            }
+            if let Some(max_txs) = p.max_txs {
+                ctx.miner.set_max_txs(max_txs)?;
+            }
+            if let Some(threads) = p.threads {
+                ctx.miner.set_threads(threads)?;
+            }
+            to_value(true)
+        }
+        "miner_status" => to_value(ctx.miner.status()),
+        "mining_getBlockTemplate" => {
+            let p: TemplateParams = params(raw)?;
+            let address = match p.address {
+                Some(address) => Some(parse::<H160>("address", &address)?),
+                None => None,
+            };
+            match ctx.block_template(address, p.max_txs) {
+                Ok(template) => to_value(template),
+                Err(e) => Err(Error::new(NOT_FOUND, e)),
+            }
+        }
+        "pool_getStats" => match &ctx.pool {
+            Some(pool) => to_value(pool.stats()),
+            None => Err(Error::new(REJECTED, "pool is not running")),
+        },
+        "mining_submitBlock" => {
+            let p: BlockParams = params(raw)?;
+            let block = super::mining::decode_block(&p.block).map_err(|e| Error::new(INVALID_PARAMS, e))?;
+            match ctx.submit_block(block) {
+                Ok(hash) => to_value(hash.to_string()),
+                Err(e) => Err(Error::new(REJECTED, e)),
+            }
+        }
+        "chain_getTip" => to_value(explorer::tip(&ctx.blockchain.lock().unwrap())),
+        "chain_getFinalized" => to_value(explorer::finalized(&ctx.blockchain.lock().unwrap())),
+        "chain_getBlockByHash" => {
+            let p: HashParams = params(raw)?;
+            let hash: H256 = parse("hash", &p.hash)?;
+            found(explorer::block_by_hash(&ctx.blockchain.lock().unwrap(), &hash), "block")
+        }
+        "chain_getBlockByHeight" => {
+            let p: HeightParams = params(raw)?;
+            found(explorer::block_by_height(&ctx.blockchain.lock().unwrap(), p.height), "block")
+        }
+        "chain_getHeaders" => {
+            let p: RangeParams = params(raw)?;
+            to_value(explorer::headers(&ctx.blockchain.lock().unwrap(), p.from, p.count))
+        }
+        "chain_getTransaction" => {
+            let p: HashParams = params(raw)?;
+            let hash: H256 = parse("hash", &p.hash)?;
+            let blockchain = ctx.blockchain.lock().unwrap();
+            let mempool = ctx.mempool.lock().unwrap();
+            found(explorer::transaction(&blockchain, &mempool, &hash), "transaction")
+        }
+        "chain_getAccount" => {
+            let p: AddressParams = params(raw)?;
+            let address: H160 = parse("address", &p.address)?;
+            found(explorer::account(&ctx.states.lock().unwrap(), &address), "account")
+        }
+        "chain_getAddressTransactions" => {
+            let p: AddressParams = params(raw)?;
+            let address: H160 = parse("address", &p.address)?;
+            let blockchain = ctx.blockchain.lock().unwrap();
+            match explorer::address_transactions(&blockchain, &address, p.page, p.limit) {
+                Some(v) => to_value(v),
+                None => Err(Error::new(REJECTED, "address index is disabled")),
+            }
+        }
+        "mempool_getSize" => to_value(ctx.mempool.lock().unwrap().len()),
+        "mempool_getTransactions" => {
+            let mempool = ctx.mempool.lock().unwrap();
+            let views: Vec<explorer::TransactionView> = mempool.values().map(explorer::TransactionView::from).collect();
+            to_value(views)
+        }
+        "mempool_getTransaction" => {
+            let p: HashParams = params(raw)?;
+            let hash: H256 = parse("hash", &p.hash)?;
+            let mempool = ctx.mempool.lock().unwrap();
+            found(mempool.get(&hash).map(explorer::TransactionView::from), "transaction")
+        }
+        "node_shutdown" => {
+            info!("Shutdown requested through the API");
+            ctx.shutdown.request();
+            to_value(true)
+        }
+        "peer_list" => {
+            let peers: Vec<String> = ctx.network.peers()?.iter().map(|a| a.to_string()).collect();
+            to_value(peers)
+        }
+        "peer_connect" => {
+            let p: PeerParams = params(raw)?;
+            let addr: std::net::SocketAddr = parse("addr", &p.addr)?;
+            match ctx.network.connect(addr) {
+                Ok(_) => to_value(true),
+                Err(e) => Err(Error::new(REJECTED, format!("error connecting to {}: {}", addr, e))),
+            }
+        }
+        "peer_ping" => {
+            ctx.network.broadcast(Message::Ping(String::from("Test ping")));
+            to_value(true)
+        }
+        "tx_submit" => {
+            let p: SubmitParams = params(raw)?;
+            match ctx.submit_transaction(decode_transaction(&p.transaction)?) {
+                Ok(hash) => to_value(hash.to_string()),
+                Err(e) => Err(Error::new(REJECTED, e)),
+            }
+        }
+        "wallet_send" => {
+            let p: SendParams = params(raw)?;
+            let to: H160 = parse("to", &p.to)?;
+            let from = match p.from {
+                Some(from) => Some(parse::<H160>("from", &from)?),
+                None => None,
+            };
+            match ctx.wallet_send(from, &to, p.value, p.expiry) {
+                Ok(hash) => to_value(hash.to_string()),
+                Err(e) => Err(Error::new(REJECTED, e)),
+            }
+        }
+        "wallet_publicKeys" => {
+            let keys: Vec<String> = ctx.wallet.lock().unwrap().public_keys().iter().map(hex::encode).collect();
+            to_value(keys)
+        }
+        "wallet_multisigAddress" => {
+            let p: MultisigParams = params(raw)?;
+            to_value(policy(&p)?.address().to_string())
+        }
+        "wallet_multisigPropose" => {
+            let p: ProposeParams = params(raw)?;
+            let to: H160 = parse("to", &p.to)?;
+            match ctx.wallet_multisig_propose(&policy(&p.policy)?, &to, p.value, p.expiry) {
+                Ok(tx) => to_value(PartialTransaction::new(&tx)?),
+                Err(e) => Err(Error::new(REJECTED, e)),
+            }
+        }
+        "wallet_multisigSign" => {
+            let p: SubmitParams = params(raw)?;
+            match ctx.wallet_cosign(decode_transaction(&p.transaction)?) {
+                Ok(tx) => to_value(PartialTransaction::new(&tx)?),
+                Err(e) => Err(Error::new(REJECTED, e)),
+            }
+        }
+        _ => Err(Error::new(METHOD_NOT_FOUND, format!("method {} not found", method))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn response_shape() {
+        let ok = Response::new(Value::from(1), Ok(Value::from(true)));
+        assert_eq!(serde_json::to_string(&ok).unwrap(), r#"{"jsonrpc":"2.0","result":true,"id":1}"#);
+        let err = Response::new(Value::Null, Err(Error::new(METHOD_NOT_FOUND, "no")));
+        assert_eq!(
+            serde_json::to_string(&err).unwrap(),
+            r#"{"jsonrpc":"2.0","error":{"code":-32601,"message":"no"},"id":null}"#
+        );
+    }
+
+    #[test]
+    fn notification_has_no_id() {
+        let request: Request = serde_json::from_str(r#"{"jsonrpc":"2.0","method":"miner_stop"}"#).unwrap();
+        assert!(request.id.is_none());
+        assert!(request.params.is_null());
+        let request: Request = serde_json::from_str(r#"{"jsonrpc":"2.0","method":"miner_stop","id":null}"#).unwrap();
+        assert_eq!(request.id, Some(Value::Null));
+    }
+
+    /// Stands in for `dispatch`, with a method taking the parameters of `miner_start`
+    fn stub(method: &str, raw: Value) -> Result<Value, Error> {
+        match method {
+            "miner_start" => {
+                let p: LambdaParams = params(raw)?;
+                to_value(p.lambda)
+            }
+            _ => Err(Error::new(METHOD_NOT_FOUND, format!("method {} not found", method))),
+        }
+    }
+
+    fn call(body: &str) -> Value {
+        serde_json::from_str(&handle_with(body, &stub).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn dispatch_errors() {
+        let resp = call(r#"{"jsonrpc":"2.0","method":"nope","id":1}"#);
+        assert_eq!(resp["error"]["code"], METHOD_NOT_FOUND);
+        assert_eq!(resp["id"], 1);
+
+        let resp = call(r#"{"jsonrpc":"2.0","method":"miner_start","params":{"lambda":"x"},"id":2}"#);
+        assert_eq!(resp["error"]["code"], INVALID_PARAMS);
+
+        let resp = call(r#"{"jsonrpc":"2.0","method":"miner_start","params":{"lambda":5},"id":null}"#);
+        assert_eq!(resp["result"], 5);
+        assert!(resp["id"].is_null());
+
+        assert_eq!(call("[]")["error"]["code"], INVALID_REQUEST);
+        assert_eq!(call("{")["error"]["code"], PARSE_ERROR);
+    }
+
+    #[test]
+    fn batch_answers_requests_only() {
+        let body = r#"[
+            {"jsonrpc":"2.0","method":"miner_start","params":{"lambda":1},"id":"a"},
+            {"jsonrpc":"2.0","method":"miner_start","params":{"lambda":2}},
+            {"jsonrpc":"2.0","method":"nope","id":"b"},
+            {"jsonrpc":"2.0","method":"nope"}
+        ]"#;
+        let resp = call(body);
+        let resp = resp.as_array().unwrap();
+        assert_eq!(resp.len(), 2);
+        assert_eq!((&resp[0]["id"], &resp[0]["result"]), (&Value::from("a"), &Value::from(1)));
+        assert_eq!((&resp[1]["id"], &resp[1]["error"]["code"]), (&Value::from("b"), &Value::from(METHOD_NOT_FOUND)));
+
+        let notifications = r#"[{"jsonrpc":"2.0","method":"miner_start","params":{"lambda":1}}]"#;
+        assert_eq!(handle_with(notifications, &stub), None);
+    }
+}
@@ -1,9 +1,9 @@
 use serde::{Serialize, Deserialize};
-use crate::crypto::hash::{H256, Hashable};
+use crate::crypto::hash::{H256, H160, Hashable};
 use crate::crypto::merkle::{MerkleTree};
+use crate::crypto::domain::{self, Domain};
 use crate::transaction::{Transaction, SignedTransaction};
 use log::{info};
-//use crate::transaction::tests::generate_random_transaction;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Block {
@@ -15,35 +15,68 @@ pub struct Block {
 pub struct Header {
 	pub parent : H256,
 	pub nonce : u32,
+	/// Rolled by the miner once every `nonce` has been tried
+	pub extra_nonce : u32,
 	pub difficulty : H256,
 	pub timestamp : u128,
 	pub merkle_root : H256,
+	/// Commitment to `Content::uncles`, see `uncles::uncles_root`
+	pub uncles_root : H256,
+	/// Address of the account that mined the block
+	pub miner : H160,
+	/// Producer signature, for consensus engines that need one; empty otherwise
+	pub seal : Vec<u8>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Content {
 	pub data : Vec<SignedTransaction>,
+	/// Headers of recent stale blocks referenced by this block
+	pub uncles : Vec<Header>,
 }
 
+/// Digest of the unsigned transaction alone; see `transaction::sign` for what the sender signs
 impl Hashable for Transaction {
     fn hash(&self) -> H256 {
-    	let encoded = bincode::serialize(&self).unwrap();
-        ring::digest::digest(&ring::digest::SHA256, &encoded).into()
+        domain::hash(Domain::Tx, &domain::encode(self))
     }
 }
 
+/// The transaction id: the transaction and its sender, leaving out the witness (signature and
+/// public key), so re-encoding a signature cannot give a valid transaction a second id
 impl Hashable for SignedTransaction {
     fn hash(&self) -> H256 {
-    	let encoded = bincode::serialize(&self).unwrap();
-        ring::digest::digest(&ring::digest::SHA256, &encoded).into()
+        domain::hash(Domain::Txid, &domain::encode(&(&self.Transaction, &self.sender_addr)))
     }
 }
 
+impl SignedTransaction {
+    /// Hash of the whole transaction, witness included
+    pub fn witness_hash(&self) -> H256 {
+        domain::hash(Domain::Witness, &domain::encode(self))
+    }
+}
+
+/// Commitment to the transactions of a block, stored in `Header::merkle_root`. It is built over
+/// the witness hashes, so the header pins the signatures as well as the transactions.
+pub fn transactions_root(data: &[SignedTransaction]) -> H256 {
+    let witness_hashes: Vec<H256> = data.iter().map(|tx| tx.witness_hash()).collect();
+    MerkleTree::new(&witness_hashes).root()
+}
+
 
 impl Hashable for Header {
     fn hash(&self) -> H256 {
-        let encoded = bincode::serialize(&self).unwrap();
-        ring::digest::digest(&ring::digest::SHA256, &encoded).into()
+        domain::hash(Domain::Header, &domain::encode(self))
+    }
+}
+
+impl Header {
+    /// The hash a seal signs: the header hash with the seal left out
+    pub fn seal_hash(&self) -> H256 {
+        let mut unsealed = self.clone();
+        unsealed.seal = Vec::new();
+        unsealed.hash()
     }
 }
 
@@ -57,6 +90,7 @@ impl Hashable for Block {
 pub mod test {
     use super::*;
     use crate::crypto::hash::H256;
+    use crate::transaction::tests::generate_random_signed_transaction;
     extern crate rand;
     use rand::Rng;
 
@@ -67,20 +101,12 @@ pub mod test {
     	let Parent = parent.clone();
     	let mut difficulty_glob = hex!("0101010101010101010101010101010101010101010101010101010101010202").into();
     	let mut clock_glob = 1;
-    	let mut transactions: Vec<Transaction> = Vec::new();
-    	//transactions.push(generate_random_transaction()); // comment to make it compiles
-        let mut rng = rand::thread_rng();
-        let In : u8 = rng.gen();
-        let Out : u8 = rng.gen();
-        //println!("{:?}", In);
-        //println!("{:?}", Out);
-        let transaction = Transaction{Input: In, Output: Out};
-        transactions.push(transaction);
-    	let merkle_tree = MerkleTree::new(&transactions);
-    	let root = merkle_tree.root();
+    	let mut transactions: Vec<SignedTransaction> = Vec::new();
+        transactions.push(generate_random_signed_transaction());
+    	let root = transactions_root(&transactions);
  
-    	let header = Header{parent : Parent, nonce : nonce, difficulty : difficulty_glob, timestamp : clock_glob, merkle_root : root};
-    	let content = Content{data : transactions};
+    	let header = Header{parent : Parent, nonce : nonce, extra_nonce : 0, difficulty : difficulty_glob, timestamp : clock_glob, merkle_root : root, uncles_root : Default::default(), miner : Default::default(), seal : Vec::new()};
+    	let content = Content{data : transactions, uncles : Vec::new()};
     	return Block{header : header, content : content};
     }
 }
@@ -2,6 +2,7 @@ use crate::crypto::hash::{H256, H160, Hashable};
 use serde::{Serialize, Deserialize};
 use std::convert::TryInto;
 use crate::crypto::merkle::{MerkleTree};
+use crate::crypto::domain::{self, Domain};
 use crate::block::{Block, Header, Content};
 use crate::transaction::{Transaction, SignedTransaction};
 use crate::crypto::hash::generate_random_hash;
@@ -9,10 +10,18 @@ use crate::crypto::key_pair;
 use std::collections::HashMap;
 use std::collections::BTreeMap;
 extern crate rand;
-use rand::Rng;
 use crate::transaction::sign;
 use ring::signature::{Ed25519KeyPair, Signature, KeyPair, VerificationAlgorithm, EdDSAParameters};
-use log::{info};
+use log::{info, error, warn};
+use crate::index::AddressIndex;
+use crate::storage::Storage;
+use crate::events::{Event, EventBus};
+use crate::metrics;
+use crate::uncles;
+use std::path::Path;
+use std::sync::Arc;
+use crate::pow::{PowAlgorithm, ProofOfWork};
+use crate::consensus::{BlockTree, Consensus, ConsensusKind, ForkChoice};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct State {
@@ -25,6 +34,81 @@ impl State {
 
         return Self{accountMaping : accountMaping};
     }
+
+    /// Apply the transfers of `block`. A transfer takes effect if it carries the next nonce of
+    /// its sender and the sender can afford it; any other is skipped.
+    pub fn apply_transfers(&mut self, block: &Block) {
+        for tx in block.content.data.iter() {
+            let (nonce, balance) = match self.accountMaping.get(&tx.sender_addr) {
+                Some(account) => *account,
+                None => continue,
+            };
+            if tx.Transaction.accountNonce != nonce.wrapping_add(1) || balance < tx.Transaction.val {
+                continue;
+            }
+            self.accountMaping.insert(tx.sender_addr, (tx.Transaction.accountNonce, balance - tx.Transaction.val));
+            // paying an address opens its account
+            let recipient = self.accountMaping.entry(tx.Transaction.recipAddress).or_insert((0, 0));
+            recipient.1 = recipient.1.saturating_add(tx.Transaction.val);
+        }
+    }
+}
+
+/// Reward of a block on the development chain
+pub const DEFAULT_BLOCK_REWARD: u32 = 32;
+
+/// Tip moves between saves of the address index. An index left behind by a crash is stale and
+/// rebuilt on the next `open`.
+const ADDRESS_INDEX_SAVE_INTERVAL: u32 = 64;
+
+/// Parameters fixed for the lifetime of a chain, usually read from a `ChainSpec`. The genesis
+/// block commits to them, so nodes with different parameters do not share blocks.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct GenesisParams {
+    pub pow: PowAlgorithm,
+    pub consensus: ConsensusKind,
+    pub timestamp: u128,
+    /// Target of the genesis block, inherited by every block
+    pub difficulty: H256,
+    /// Credited to the miner of every block
+    pub block_reward: u32,
+    /// Initial balances, sorted by address
+    pub allocations: Vec<(H160, u32)>,
+}
+
+/// The development chain: the two accounts of the transaction generator are funded
+impl Default for GenesisParams {
+    fn default() -> Self {
+        let ico: H160 = [70, 8, 220, 215, 80, 53, 152, 74, 136, 126, 87, 62, 230, 168, 2, 10, 237, 58, 51, 50].into();
+        let recipient: H160 = [140, 160, 200, 230, 190, 145, 185, 70, 100, 30, 122, 218, 43, 212, 90, 238, 170, 7, 122, 128].into();
+        let mut allocations = vec![(ico, 10000), (recipient, 10000)];
+        allocations.sort_by(|a, b| a.0.as_ref().cmp(b.0.as_ref()));
+        Self {
+            pow: PowAlgorithm::default(),
+            consensus: ConsensusKind::default(),
+            timestamp: 0,
+            difficulty: [10u8; 32].into(),
+            block_reward: DEFAULT_BLOCK_REWARD,
+            allocations,
+        }
+    }
+}
+
+impl GenesisParams {
+    /// The account state before any block
+    pub fn genesis_state(&self) -> State {
+        let mut state = State::new();
+        for (addr, balance) in self.allocations.iter() {
+            state.accountMaping.insert(*addr, (0, *balance));
+        }
+        state
+    }
+}
+
+impl Hashable for GenesisParams {
+    fn hash(&self) -> H256 {
+        domain::hash(Domain::Genesis, &domain::encode(self))
+    }
 }
 
 pub struct Blockchain {
@@ -34,11 +118,36 @@ pub struct Blockchain {
     pub blocks_height : HashMap<H256, u16>,
     pub next_len : u16,
     pub chainState : HashMap<H256, State>,
+    /// Address-to-transaction index of the longest chain, if enabled
+    pub addr_index : Option<AddressIndex>,
+    /// Block and position of each transaction on the longest chain
+    tx_locations : HashMap<H256, (H256, usize)>,
+    /// Tip moves since the address index was last saved
+    index_unsaved : u32,
+    storage : Option<Storage>,
+    /// Where tip changes, reorgs and confirmations are published, if anyone listens
+    pub events : Option<EventBus>,
+    pub params : GenesisParams,
+    /// The puzzle selected by `params`
+    pub pow : Arc<dyn ProofOfWork>,
+    /// The engine selected by `params`
+    pub consensus : Arc<dyn Consensus>,
+    /// The rule choosing `tip`, see `set_fork_choice`
+    pub fork_choice : ForkChoice,
+    /// Subtree weights of all known blocks, for GHOST
+    pub tree : BlockTree,
+    /// The tip the engine prefers, which is `tip` unless the fork choice is GHOST
+    longest_tip : H256,
 }
 
 impl Blockchain {
-    /// Create a new blockchain, only containing the genesis block
+    /// Create a new blockchain with the default parameters, only containing the genesis block
     pub fn new() -> Self {
+        Self::with_params(GenesisParams::default())
+    }
+
+    /// Create a new blockchain, only containing the genesis block for `params`
+    pub fn with_params(params: GenesisParams) -> Self {
 
         let mut hash_blocks: HashMap<H256, Block> = HashMap::new();
         let mut blocks_height: HashMap<H256, u16> = HashMap::new();
@@ -46,74 +155,248 @@ impl Blockchain {
         let mut tip : H256;
         let mut next_len : u16 = 1;
 
-        let mut rng = rand::thread_rng();
         let nonce : u32 = 0;
         
-        //to hard-code the blockchain difficulty and parent and genesis root
-        let random_diff: Vec<u8> = (0..32).map(|_| 10).collect();
-        let mut raw_bytes = [0; 32];
-        raw_bytes.copy_from_slice(&random_diff);
-        let difficulty_glob =(&raw_bytes).into();
+        //to hard-code the genesis parent, the difficulty comes from the chain parameters
+        let difficulty_glob = params.difficulty;
 
         let random_parent: Vec<u8> = (0..32).map(|_| 1).collect();
         let mut raw_bytes_parent = [0; 32];
         raw_bytes_parent.copy_from_slice(&random_parent);
         let Parent =(&raw_bytes_parent).into();
 
-        let random_genesisRoot: Vec<u8> = (0..32).map(|_| 5).collect();
-        let mut raw_bytes_parent = [0; 32];
-        raw_bytes_parent.copy_from_slice(&random_genesisRoot);
-        let genesis_root =(&raw_bytes_parent).into();
-
-        //create transaction content for genesis block
-        println!("{:?}", "Start create transaction content for genesis block");
-        let random_recipient: Vec<u8> = (0..20).map(|_| 1).collect();
-        let mut raw_bytes_recipient = [0; 20];
-        raw_bytes_recipient.copy_from_slice(&random_recipient);
-        let Recipient : H160 =(&raw_bytes_recipient).into();
-        let val : u32 = rng.gen();
-        let mut accountNonce : u16 = 0;
-
-        //sign the transaction and store it in SignedTransactions vector
-        println!("{:?}", "Sign the transaction and store it in SignedTransactions vector");
-        let mut SignedTransactions: Vec<SignedTransaction> = Vec::new();
-        /*let transaction = Transaction{recipArrress : Recipient, val : val, accountNonce : accountNonce};
-        let key = key_pair::random();
-        let signature = sign(&transaction, &key);
-        let hash_key : H256 = ring::digest::digest(&ring::digest::SHA256, key.public_key().as_ref()).into();
-        let hash_key_20bytes : H160 = hash_key.as_ref()[12..=31].into();
-        let signed_transaction = SignedTransaction{Transaction: transaction, Signature : signature.as_ref().to_vec(), public_key : key.public_key().as_ref().to_vec(), sender_addr : hash_key_20bytes};*/
-        //SignedTransactions.push(signed_transaction);
-
-        //let merkle_tree = MerkleTree::new(&SignedTransactions);
-        //let root = merkle_tree.root();
-        let root = genesis_root;
-        
-        let header = Header{parent : Parent, nonce : nonce, difficulty : difficulty_glob, timestamp : 0, merkle_root : root};
-        let content = Content{data : SignedTransactions};
+        // the genesis block has no transactions, its root commits to the chain parameters instead
+        let root = params.hash();
+        let SignedTransactions: Vec<SignedTransaction> = Vec::new();
+
+        let header = Header{parent : Parent, nonce : nonce, extra_nonce : 0, difficulty : difficulty_glob, timestamp : params.timestamp, merkle_root : root, uncles_root : Default::default(), miner : Default::default(), seal : Vec::new()};
+        let content = Content{data : SignedTransactions, uncles : Vec::new()};
         let genesis_block = Block{header : header, content : content};
         tip = genesis_block.hash();
         hash_blocks.insert(genesis_block.hash(), genesis_block.clone());
         blocks_height.insert(genesis_block.hash(), next_len);
+        chainState.insert(genesis_block.hash(), params.genesis_state());
         next_len += 1;
 
-        return Self{hash_blocks : hash_blocks, genesis : genesis_block, tip : tip, blocks_height : blocks_height, next_len : next_len ,chainState: chainState};
-        
+        Self{hash_blocks : hash_blocks, genesis : genesis_block, tip : tip, blocks_height : blocks_height, next_len : next_len ,chainState: chainState, addr_index : None, tx_locations : HashMap::new(), index_unsaved : 0, storage : None, events : None, pow : params.pow.build(), consensus : params.consensus.build(params.pow.build()), params : params, fork_choice : ForkChoice::default(), tree : BlockTree::new(tip), longest_tip : tip}
+    }
+
+    /// Open the blockchain persisted in `dir`, replaying its stored blocks, and the account state
+    /// they lead to, on top of the genesis block. Newly inserted blocks are appended to the store.
+    /// If `addr_index` is set, the stored address index is loaded, or rebuilt from the chain when
+    /// it is missing or stale. A store written under other genesis parameters is an error.
+    pub fn open(dir: &Path, addr_index: bool, params: GenesisParams) -> std::io::Result<Self> {
+        let storage = Storage::open(dir)?;
+        let genesis = params.hash();
+        match storage.load_genesis()? {
+            Some(stored) if stored != genesis => {
+                let msg = format!("{} holds a chain of other genesis parameters ({}, expected {})", dir.display(), stored, genesis);
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, msg));
+            }
+            Some(_) => {}
+            None => storage.save_genesis(&genesis)?,
+        }
+        let mut blockchain = Self::with_params(params);
+        let stored_index = if addr_index { storage.load_address_index()? } else { None };
+        let blocks = storage.load_blocks()?;
+        let replay_index = addr_index && stored_index.is_none();
+        if replay_index {
+            blockchain.addr_index = Some(AddressIndex::new(blockchain.tip));
+        }
+        for block in blocks.iter() {
+            if !blockchain.hash_blocks.contains_key(&block.header.parent) {
+                let msg = format!("stored block {} does not extend the chain of {}", block.hash(), dir.display());
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, msg));
+            }
+            blockchain.insert(block);
+        }
+        info!("Loaded {} blocks from {}", blocks.len(), dir.display());
+
+        if let Some(index) = stored_index {
+            if index.tip == blockchain.tip {
+                blockchain.addr_index = Some(index);
+            } else {
+                info!("Stored address index is stale, rebuilding");
+                blockchain.rebuild_address_index();
+            }
+        }
+        blockchain.storage = Some(storage);
+        if replay_index {
+            blockchain.save_address_index();
+        }
+        Ok(blockchain)
+    }
+
+    /// Rebuild the address index from the blocks of the longest chain
+    pub fn rebuild_address_index(&mut self) {
+        let mut index = AddressIndex::new(self.genesis.hash());
+        for hash in self.all_blocks_in_longest_chain().iter().skip(1) {
+            index.connect_block(&self.hash_blocks[hash]);
+        }
+        self.addr_index = Some(index);
+        self.save_address_index();
     }
 
-    /// Insert a block into blockchain
+    fn save_address_index(&self) {
+        if let (Some(storage), Some(index)) = (&self.storage, &self.addr_index) {
+            if let Err(e) = storage.save_address_index(index) {
+                error!("Error saving address index: {}", e);
+            }
+        }
+    }
+
+    /// Identifier of this chain, signed into its transactions
+    pub fn chain_id(&self) -> u64 {
+        crate::transaction::chain_id(&self.genesis.hash())
+    }
+
+    /// Write everything held in memory that the store lacks, before the node exits
+    pub fn flush(&self) {
+        if let Some(storage) = &self.storage {
+            if let Err(e) = storage.sync_blocks() {
+                error!("Error syncing blocks: {}", e);
+            }
+        }
+        self.save_address_index();
+    }
+
+    /// Insert a block into blockchain, whose parent must be in it already. The account state
+    /// after the block is derived from the state after its parent and kept in `chainState`.
     pub fn insert(&mut self, block: &Block) {
+        if self.hash_blocks.contains_key(&block.hash()) {
+            return;
+        }
+        let mut state = self.chainState.get(&block.header.parent).cloned().unwrap_or_else(State::new);
+        state.apply_transfers(block);
+        uncles::credit(&mut state, &uncles::rewards(block, self));
+        self.chainState.insert(block.hash(), state);
         self.hash_blocks.insert(block.hash(), block.clone());
-        println!("{:?}", "before find parent");
         let parent_height = self.blocks_height[&block.header.parent];
         //println!("{:?}", "after find parent");
         self.blocks_height.insert(block.hash(), parent_height + 1);
         //self.blocks_height.insert(block.hash(), self.next_len);
-        if(self.blocks_height[&block.hash()] > self.blocks_height[&self.tip])
+        self.tree.insert(block, &self.hash_blocks);
+        // a branch leaving the chain below its finalized block is stored but never followed
+        let finalized = self.consensus.finalized(self);
+        if self.descends_from(&block.hash(), &finalized) && self.consensus.clone().prefers(self, &block.hash(), &self.longest_tip)
         {
-            self.tip = block.hash();
+            self.longest_tip = block.hash();
+        }
+        let ghost_tip = self.ghost_tip();
+        if ghost_tip != self.longest_tip {
+            metrics::global().fork_choice_disagreements.inc();
         }
         self.next_len += 1;
+
+        if let Some(storage) = &self.storage {
+            if let Err(e) = storage.append_block(block) {
+                error!("Error storing block {}: {}", block.hash(), e);
+            }
+        }
+        let new_tip = match self.fork_choice {
+            ForkChoice::Longest => self.longest_tip,
+            ForkChoice::Ghost => ghost_tip,
+        };
+        self.move_tip(new_tip);
+    }
+
+    /// Switch the rule choosing the tip, moving the tip if the new rule picks another block
+    pub fn set_fork_choice(&mut self, fork_choice: ForkChoice) {
+        self.fork_choice = fork_choice;
+        let new_tip = match fork_choice {
+            ForkChoice::Longest => self.longest_tip,
+            ForkChoice::Ghost => self.ghost_tip(),
+        };
+        self.move_tip(new_tip);
+    }
+
+    /// Make `new_tip` the tip, updating the address index and publishing the change. The index is
+    /// saved every `ADDRESS_INDEX_SAVE_INTERVAL` moves and by `flush`.
+    fn move_tip(&mut self, new_tip: H256) {
+        if new_tip == self.tip {
+            return;
+        }
+        let finalized = self.consensus.finalized(self);
+        if !self.descends_from(&new_tip, &finalized) {
+            warn!("Not moving the tip to {}, which would revert the finalized block {}", new_tip, finalized);
+            return;
+        }
+        let prev_tip = self.tip;
+        self.tip = new_tip;
+        let (disconnected, connected) = self.reorg_path(&prev_tip, &self.tip);
+        if !disconnected.is_empty() {
+            metrics::global().reorgs.inc();
+            metrics::global().reorg_depth.observe(disconnected.len() as f64);
+        }
+        for hash in disconnected.iter() {
+            for tx in self.hash_blocks[hash].content.data.iter() {
+                self.tx_locations.remove(&tx.hash());
+            }
+        }
+        for hash in connected.iter() {
+            for (i, tx) in self.hash_blocks[hash].content.data.iter().enumerate() {
+                self.tx_locations.insert(tx.hash(), (*hash, i));
+            }
+        }
+        if let Some(index) = self.addr_index.as_mut() {
+            for hash in disconnected.iter() {
+                index.disconnect_block(&self.hash_blocks[hash]);
+            }
+            for hash in connected.iter() {
+                index.connect_block(&self.hash_blocks[hash]);
+            }
+        }
+        self.index_unsaved += 1;
+        if self.index_unsaved >= ADDRESS_INDEX_SAVE_INTERVAL {
+            self.index_unsaved = 0;
+            self.save_address_index();
+        }
+        self.publish_tip_change(&disconnected, &connected);
+    }
+
+    fn publish_tip_change(&self, disconnected: &[H256], connected: &[H256]) {
+        let events = match &self.events {
+            Some(events) => events,
+            None => return,
+        };
+        if !disconnected.is_empty() {
+            events.publish(Event::Reorg {
+                disconnected: disconnected.iter().map(|h| h.to_string()).collect(),
+                connected: connected.iter().map(|h| h.to_string()).collect(),
+            });
+        }
+        for hash in connected.iter() {
+            for tx in self.hash_blocks[hash].content.data.iter() {
+                events.publish(Event::TransactionConfirmed { hash: tx.hash().to_string(), block: hash.to_string() });
+            }
+        }
+        events.publish(Event::NewTip { hash: self.tip.to_string(), height: self.height(&self.tip).unwrap() });
+    }
+
+    /// The blocks leaving and joining the longest chain when its tip moves from `old_tip` to
+    /// `new_tip`. Disconnected blocks are listed from `old_tip` downwards, connected blocks from
+    /// the common ancestor upwards.
+    pub fn reorg_path(&self, old_tip: &H256, new_tip: &H256) -> (Vec<H256>, Vec<H256>) {
+        let mut disconnected = Vec::new();
+        let mut connected = Vec::new();
+        let mut old = *old_tip;
+        let mut new = *new_tip;
+        while self.blocks_height[&old] > self.blocks_height[&new] {
+            disconnected.push(old);
+            old = self.hash_blocks[&old].header.parent;
+        }
+        while self.blocks_height[&new] > self.blocks_height[&old] {
+            connected.push(new);
+            new = self.hash_blocks[&new].header.parent;
+        }
+        while old != new {
+            disconnected.push(old);
+            old = self.hash_blocks[&old].header.parent;
+            connected.push(new);
+            new = self.hash_blocks[&new].header.parent;
+        }
+        connected.reverse();
+        (disconnected, connected)
     }
 
     /// Get the last block's hash of the longest chain
@@ -121,6 +404,58 @@ impl Blockchain {
         return self.tip;
     }
 
+    /// The account state after the tip, to be taken whenever the tip may have moved
+    pub fn tip_state(&self) -> State {
+        self.chainState.get(&self.tip).cloned().unwrap_or_else(|| self.params.genesis_state())
+    }
+
+    /// The tip the engine's own rule picks, whatever the configured fork choice
+    pub fn longest_tip(&self) -> H256 {
+        self.longest_tip
+    }
+
+    /// The tip GHOST picks, whatever the configured fork choice. The walk starts at the
+    /// finalized block, so it never leaves it.
+    pub fn ghost_tip(&self) -> H256 {
+        self.tree.ghost_tip(&self.consensus.finalized(self))
+    }
+
+    /// Whether `hash` is `ancestor` or built on it
+    pub fn descends_from(&self, hash: &H256, ancestor: &H256) -> bool {
+        let (height, ancestor_height) = match (self.height(hash), self.height(ancestor)) {
+            (Some(height), Some(ancestor_height)) if height >= ancestor_height => (height, ancestor_height),
+            _ => return false,
+        };
+        let mut pointer = *hash;
+        for _ in ancestor_height..height {
+            pointer = self.hash_blocks[&pointer].header.parent;
+        }
+        pointer == *ancestor
+    }
+
+    /// Get the height of a block, counting the genesis block as height 0
+    pub fn height(&self, hash: &H256) -> Option<u64> {
+        self.blocks_height.get(hash).map(|h| (*h - 1) as u64)
+    }
+
+    /// Get the hash of the block at `height` on the longest chain
+    pub fn block_at_height(&self, height: u64) -> Option<H256> {
+        let tip_height = self.height(&self.tip)?;
+        if height > tip_height {
+            return None;
+        }
+        let mut pointer = self.tip;
+        for _ in height..tip_height {
+            pointer = self.hash_blocks[&pointer].header.parent;
+        }
+        Some(pointer)
+    }
+
+    /// Find a transaction on the longest chain, returning the containing block and its index
+    pub fn find_transaction(&self, tx_hash: &H256) -> Option<(H256, usize)> {
+        self.tx_locations.get(tx_hash).copied()
+    }
+
     /// Get the last block's hash of the longest chain
     ///#[cfg(any(test, test_utilities))]
     pub fn all_blocks_in_longest_chain(&self) -> Vec<H256> {
@@ -186,5 +521,202 @@ mod tests {
         assert_eq!(blockchain.tip(), block_6.hash());
     }
 
+    /// Genesis with a three-block chain `a` and a wider but shorter subtree of four blocks `b`
+    fn forked_tree(blockchain: &mut Blockchain) -> (Vec<Block>, Vec<Block>) {
+        let genesis_hash = blockchain.genesis.hash();
+        let a1 = generate_random_block(&genesis_hash);
+        let a2 = generate_random_block(&a1.hash());
+        let a3 = generate_random_block(&a2.hash());
+        let b1 = generate_random_block(&genesis_hash);
+        let b2 = generate_random_block(&b1.hash());
+        let b2_uncle = generate_random_block(&b1.hash());
+        let b2_other = generate_random_block(&b1.hash());
+        let a = vec![a1, a2, a3];
+        let b = vec![b1, b2, b2_uncle, b2_other];
+        for block in a.iter().chain(b.iter()) {
+            blockchain.insert(block);
+        }
+        (a, b)
+    }
+
+    #[test]
+    fn ghost_follows_heaviest_subtree() {
+        let mut blockchain = Blockchain::new();
+        blockchain.set_fork_choice(ForkChoice::Ghost);
+        let (a, b) = forked_tree(&mut blockchain);
+        let genesis_hash = blockchain.genesis.hash();
+        assert_eq!(blockchain.tree.weight(&genesis_hash), 8);
+        assert_eq!(blockchain.tree.weight(&a[0].hash()), 3);
+        assert_eq!(blockchain.tree.weight(&b[0].hash()), 4);
+        assert_eq!(blockchain.tree.children(&b[0].hash()).len(), 3);
+
+        // equal sibling subtrees go to the one seen first
+        assert_eq!(blockchain.tip(), b[1].hash());
+        assert_eq!(blockchain.ghost_tip(), b[1].hash());
+        assert_eq!(blockchain.longest_tip(), a[2].hash());
+        assert_eq!(blockchain.height(&blockchain.tip()), Some(2));
+
+        // one more block ties the subtrees, and `a` was seen first
+        let a4 = generate_random_block(&a[2].hash());
+        blockchain.insert(&a4);
+        assert_eq!(blockchain.tip(), a4.hash());
+        assert_eq!(blockchain.ghost_tip(), blockchain.longest_tip());
+
+        let b3 = generate_random_block(&b[2].hash());
+        let b4 = generate_random_block(&b3.hash());
+        blockchain.insert(&b3);
+        blockchain.insert(&b4);
+        assert_eq!(blockchain.tip(), b4.hash());
+        assert_eq!(blockchain.longest_tip(), a4.hash());
+    }
+
+    #[test]
+    fn switching_fork_choice_moves_tip() {
+        let mut blockchain = Blockchain::new();
+        let (a, b) = forked_tree(&mut blockchain);
+        assert_eq!(blockchain.tip(), a[2].hash());
+
+        blockchain.set_fork_choice(ForkChoice::Ghost);
+        assert_eq!(blockchain.tip(), b[1].hash());
+        let chain = blockchain.all_blocks_in_longest_chain();
+        assert_eq!(chain, vec![blockchain.genesis.hash(), b[0].hash(), b[1].hash()]);
+
+        blockchain.set_fork_choice(ForkChoice::Longest);
+        assert_eq!(blockchain.tip(), a[2].hash());
+    }
+
+    #[test]
+    fn tip_state_follows_fork_choice() {
+        let mut blockchain = Blockchain::new();
+        forked_tree(&mut blockchain);
+        let reward = blockchain.params.block_reward;
+        let miner = H160::default();
+        assert_eq!(blockchain.tip_state().accountMaping[&miner].1, 3 * reward);
+
+        blockchain.set_fork_choice(ForkChoice::Ghost);
+        assert_eq!(blockchain.tip_state().accountMaping[&miner].1, 2 * reward);
+    }
+
+    #[test]
+    fn reorg_updates_address_index() {
+        let mut blockchain = Blockchain::new();
+        blockchain.addr_index = Some(AddressIndex::new(blockchain.tip()));
+        let genesis_hash = blockchain.tip();
+        let block_1 = generate_random_block(&genesis_hash);
+        blockchain.insert(&block_1);
+        let block_2 = generate_random_block(&block_1.hash());
+        blockchain.insert(&block_2);
+        let fork_2 = generate_random_block(&block_1.hash());
+        blockchain.insert(&fork_2);
+        let fork_3 = generate_random_block(&fork_2.hash());
+        blockchain.insert(&fork_3);
+
+        assert_eq!(
+            blockchain.reorg_path(&block_2.hash(), &fork_3.hash()),
+            (vec![block_2.hash()], vec![fork_2.hash(), fork_3.hash()])
+        );
+        let index = blockchain.addr_index.as_ref().unwrap();
+        assert_eq!(index.tip, fork_3.hash());
+        assert_eq!(index.count(&block_1.content.data[0].sender_addr), 1);
+        assert_eq!(index.count(&block_2.content.data[0].sender_addr), 0);
+        assert_eq!(index.count(&fork_3.content.data[0].sender_addr), 1);
+    }
+
+    #[test]
+    fn reopen_from_storage() {
+        let dir = std::env::temp_dir().join(format!("blockchain-test-{}", generate_random_hash()));
+        let mut blockchain = Blockchain::open(&dir, true, GenesisParams::default()).unwrap();
+        let block_1 = generate_random_block(&blockchain.tip());
+        blockchain.insert(&block_1);
+        let block_2 = generate_random_block(&block_1.hash());
+        blockchain.insert(&block_2);
+        drop(blockchain);
+
+        let reopened = Blockchain::open(&dir, true, GenesisParams::default()).unwrap();
+        assert_eq!(reopened.tip(), block_2.hash());
+        let sender = block_2.content.data[0].sender_addr;
+        assert_eq!(reopened.addr_index.as_ref().unwrap().count(&sender), 1);
+        let reward = reopened.params.block_reward;
+        assert_eq!(reopened.tip_state().accountMaping[&H160::default()].1, 2 * reward);
+        drop(reopened);
+
+        // a torn record is cut off the log, so later blocks are appended after the last whole one
+        let mut log = std::fs::OpenOptions::new().append(true).open(dir.join("blocks.dat")).unwrap();
+        std::io::Write::write_all(&mut log, &[0, 0, 1, 0, 7]).unwrap();
+        let mut reopened = Blockchain::open(&dir, true, GenesisParams::default()).unwrap();
+        let block_3 = generate_random_block(&block_2.hash());
+        reopened.insert(&block_3);
+        drop(reopened);
+        assert_eq!(Blockchain::open(&dir, true, GenesisParams::default()).unwrap().tip(), block_3.hash());
+
+        let other = GenesisParams { timestamp: 1, ..GenesisParams::default() };
+        assert!(Blockchain::open(&dir, true, other).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn finalized_blocks_are_not_reverted() {
+        let mut blockchain = Blockchain::new();
+        let depth = blockchain.consensus.finality_depth() as usize;
+        let mut chain = vec![blockchain.tip()];
+        for _ in 0..depth + 2 {
+            let block = generate_random_block(chain.last().unwrap());
+            blockchain.insert(&block);
+            chain.push(block.hash());
+        }
+        let tip = blockchain.tip();
+        let finalized = blockchain.consensus.finalized(&blockchain);
+        assert_eq!(finalized, chain[2]);
+
+        // a longer branch leaving below the finalized block is kept out of the chain
+        let mut fork = chain[1];
+        for _ in 0..depth + 4 {
+            let block = generate_random_block(&fork);
+            blockchain.insert(&block);
+            fork = block.hash();
+        }
+        assert_eq!(blockchain.tip(), tip);
+        blockchain.set_fork_choice(ForkChoice::Ghost);
+        assert_eq!(blockchain.tip(), tip);
+        blockchain.set_fork_choice(ForkChoice::Longest);
+
+        // one leaving at the finalized block may still take over
+        let mut fork = finalized;
+        for _ in 0..depth + 1 {
+            let block = generate_random_block(&fork);
+            blockchain.insert(&block);
+            fork = block.hash();
+        }
+        assert_eq!(blockchain.tip(), fork);
+    }
+
+    #[test]
+    fn height_lookup() {
+        let mut blockchain = Blockchain::new();
+        let genesis_hash = blockchain.tip();
+        let block_1 = generate_random_block(&genesis_hash);
+        blockchain.insert(&block_1);
+        let block_2 = generate_random_block(&block_1.hash());
+        blockchain.insert(&block_2);
+        let fork = generate_random_block(&block_1.hash());
+        blockchain.insert(&fork);
+
+        assert_eq!(blockchain.height(&genesis_hash), Some(0));
+        assert_eq!(blockchain.height(&fork.hash()), Some(2));
+        assert_eq!(blockchain.block_at_height(0), Some(genesis_hash));
+        assert_eq!(blockchain.block_at_height(2), Some(block_2.hash()));
+        assert_eq!(blockchain.block_at_height(3), None);
+
+        let tx_hash = block_2.content.data[0].hash();
+        assert_eq!(blockchain.find_transaction(&tx_hash), Some((block_2.hash(), 0)));
+        assert_eq!(blockchain.find_transaction(&fork.content.data[0].hash()), None);
+
+        // the fork takes over, so block 2 leaves the chain
+        let fork_3 = generate_random_block(&fork.hash());
+        blockchain.insert(&fork_3);
+        assert_eq!(blockchain.find_transaction(&tx_hash), None);
+        assert_eq!(blockchain.find_transaction(&fork.content.data[0].hash()), Some((fork.hash(), 0)));
+    }
+
 
 }
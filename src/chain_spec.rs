@@ -0,0 +1,115 @@
+//! Chain specs: JSON files describing the genesis of a chain, picked with `--chain`. Every field
+//! is optional and defaults to the development chain. Hashes and addresses are hex encoded,
+//! puzzles and engines are named as on the command line, e.g.
+//!
+//! ```json
+//! {
+//!     "name": "testnet",
+//!     "timestamp": 1588291200000,
+//!     "difficulty": "0000ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff",
+//!     "block_reward": 50,
+//!     "pow": "double-sha256",
+//!     "consensus": "pow",
+//!     "allocations": { "4608dcd75035984a887e573ee6a8020aed3a3332": 1000000 }
+//! }
+//! ```
+
+use crate::blockchain::GenesisParams;
+use crate::consensus::ConsensusKind;
+use crate::crypto::hash::{H160, H256};
+use crate::pow::PowAlgorithm;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChainSpec {
+    /// Shown in logs only, not part of the genesis
+    pub name: String,
+    pub timestamp: u128,
+    pub difficulty: String,
+    pub block_reward: u32,
+    pub pow: String,
+    pub consensus: String,
+    /// Initial balance of each address
+    pub allocations: BTreeMap<String, u32>,
+}
+
+impl ChainSpec {
+    /// Read the spec in the JSON file at `path`
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let data = std::fs::read_to_string(path).map_err(|e| format!("error reading {}: {}", path.display(), e))?;
+        serde_json::from_str(&data).map_err(|e| format!("error parsing {}: {}", path.display(), e))
+    }
+
+    /// The genesis parameters described by this spec
+    pub fn params(&self) -> Result<GenesisParams, String> {
+        let difficulty = self
+            .difficulty
+            .parse::<H256>()
+            .map_err(|e| format!("invalid difficulty {}: {}", self.difficulty, e))?;
+        let mut allocations = Vec::new();
+        for (addr, balance) in self.allocations.iter() {
+            let addr = addr.parse::<H160>().map_err(|e| format!("invalid address {}: {}", addr, e))?;
+            allocations.push((addr, *balance));
+        }
+        allocations.sort_by(|a, b| a.0.as_ref().cmp(b.0.as_ref()));
+        Ok(GenesisParams {
+            pow: self.pow.parse::<PowAlgorithm>()?,
+            consensus: self.consensus.parse::<ConsensusKind>()?,
+            timestamp: self.timestamp,
+            difficulty,
+            block_reward: self.block_reward,
+            allocations,
+        })
+    }
+}
+
+impl Default for ChainSpec {
+    fn default() -> Self {
+        let params = GenesisParams::default();
+        Self {
+            name: "dev".to_string(),
+            timestamp: params.timestamp,
+            difficulty: params.difficulty.to_string(),
+            block_reward: params.block_reward,
+            pow: params.pow.build().name(),
+            consensus: params.consensus.build(params.pow.build()).name(),
+            allocations: params.allocations.iter().map(|(addr, balance)| (addr.to_string(), *balance)).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_spec_is_dev_chain() {
+        assert_eq!(ChainSpec::default().params(), Ok(GenesisParams::default()));
+    }
+
+    #[test]
+    fn parses_partial_spec() {
+        let spec: ChainSpec = serde_json::from_str(
+            r#"{"block_reward": 50, "consensus": "pos:1000:12", "allocations": {"0101010101010101010101010101010101010101": 7}}"#,
+        )
+        .unwrap();
+        let params = spec.params().unwrap();
+        assert_eq!(params.block_reward, 50);
+        assert_eq!(params.consensus, ConsensusKind::ProofOfStake { slot_ms: 1000, finality_depth: 12 });
+        assert_eq!(params.pow, GenesisParams::default().pow);
+        assert_eq!(params.allocations, vec![([1u8; 20].into(), 7)]);
+        assert_eq!(params.genesis_state().accountMaping[&[1u8; 20].into()], (0, 7));
+
+        let mut bad = ChainSpec::default();
+        bad.allocations.insert("not hex".to_string(), 1);
+        assert!(bad.params().is_err());
+        let bad = ChainSpec { consensus: "pos:1000:x".to_string(), ..ChainSpec::default() };
+        assert!(bad.params().is_err());
+        let bad = ChainSpec { pow: "memory-hard:4294967295".to_string(), ..ChainSpec::default() };
+        assert!(bad.params().is_err());
+        assert!(serde_json::from_str::<ChainSpec>(r#"{"reward": 1}"#).is_err());
+    }
+}
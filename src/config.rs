@@ -0,0 +1,279 @@
+//! Node configuration. Settings are layered: built-in defaults, then the JSON file given with
+//! `--config`, then `BITCOIN_*` environment variables, then command line flags. Every setting
+//! has a dotted key, e.g. `mining.threads`, whose environment variable is `BITCOIN_` followed by
+//! the key in upper case with dots replaced by underscores, e.g. `BITCOIN_MINING_THREADS`.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::Path;
+
+/// Prefix of the environment variables overriding settings
+pub const ENV_PREFIX: &str = "BITCOIN_";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Persist the blockchain in this directory; kept in memory only if unset
+    pub data_dir: Option<String>,
+    pub addr_index: bool,
+    /// JSON chain spec; without one a development chain with `pow` and `consensus` is used
+    pub chain: Option<String>,
+    pub pow: String,
+    pub consensus: String,
+    pub fork_choice: String,
+    pub log: LogConfig,
+    pub p2p: P2pConfig,
+    pub api: ApiConfig,
+    pub mining: MiningConfig,
+    pub pool: PoolConfig,
+    pub generator: GeneratorConfig,
+    pub mempool: MempoolConfig,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    pub verbosity: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct P2pConfig {
+    pub addr: String,
+    pub workers: usize,
+    pub max_peers: usize,
+    /// Peers to connect to at start
+    pub connect: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ApiConfig {
+    pub addr: String,
+    /// If set, every request must carry `Authorization: Bearer <token>`
+    pub auth_token: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct MiningConfig {
+    /// Start mining at launch instead of waiting for `/miner/start`
+    pub enabled: bool,
+    pub lambda: u64,
+    pub threads: usize,
+    /// Address paid by mined blocks; the wallet's default address if unset
+    pub address: Option<String>,
+    pub max_txs: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct PoolConfig {
+    /// Run a mining pool server at this address
+    pub addr: Option<String>,
+    pub share_factor: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct GeneratorConfig {
+    /// Start generating at launch instead of waiting for `/generator/start`
+    pub enabled: bool,
+    /// Seed the accounts and the random choices are derived from
+    pub seed: u64,
+    pub accounts: usize,
+    /// Mean time between two transactions
+    pub interval_ms: u64,
+    /// `constant` or `poisson`
+    pub arrivals: String,
+    /// `N`, `uniform:MIN-MAX` or `exponential:MEAN`
+    pub value: String,
+    /// `uniform`, `hotspot[:PERCENT]`, `chain` or `double-spend`
+    pub pattern: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct MempoolConfig {
+    /// Transactions beyond this many are dropped
+    pub max_size: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            data_dir: None,
+            addr_index: false,
+            chain: None,
+            pow: "sha256".to_string(),
+            consensus: "pow".to_string(),
+            fork_choice: "longest".to_string(),
+            log: LogConfig::default(),
+            p2p: P2pConfig::default(),
+            api: ApiConfig::default(),
+            mining: MiningConfig::default(),
+            pool: PoolConfig::default(),
+            generator: GeneratorConfig::default(),
+            mempool: MempoolConfig::default(),
+        }
+    }
+}
+
+impl Default for P2pConfig {
+    fn default() -> Self {
+        Self { addr: "127.0.0.1:6000".to_string(), workers: 4, max_peers: 256, connect: Vec::new() }
+    }
+}
+
+impl Default for ApiConfig {
+    fn default() -> Self {
+        Self { addr: "127.0.0.1:7000".to_string(), auth_token: None }
+    }
+}
+
+impl Default for MiningConfig {
+    fn default() -> Self {
+        Self { enabled: false, lambda: 0, threads: 1, address: None, max_txs: crate::miner::DEFAULT_MAX_TXS }
+    }
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        Self { addr: None, share_factor: 16 }
+    }
+}
+
+impl Default for GeneratorConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            seed: 0,
+            accounts: 8,
+            interval_ms: 1000,
+            arrivals: "constant".to_string(),
+            value: "1".to_string(),
+            pattern: "uniform".to_string(),
+        }
+    }
+}
+
+impl Default for MempoolConfig {
+    fn default() -> Self {
+        Self { max_size: 10_000 }
+    }
+}
+
+impl Config {
+    /// Read the JSON file at `path` on top of the defaults
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let data = std::fs::read_to_string(path).map_err(|e| format!("error reading {}: {}", path.display(), e))?;
+        serde_json::from_str(&data).map_err(|e| format!("error parsing {}: {}", path.display(), e))
+    }
+
+    /// Dotted keys of every setting
+    pub fn keys() -> Vec<String> {
+        fn collect(value: &Value, prefix: &str, keys: &mut Vec<String>) {
+            match value {
+                Value::Object(fields) => {
+                    for (name, field) in fields.iter() {
+                        let key = if prefix.is_empty() { name.clone() } else { format!("{}.{}", prefix, name) };
+                        collect(field, &key, keys);
+                    }
+                }
+                _ => keys.push(prefix.to_string()),
+            }
+        }
+        let mut keys = Vec::new();
+        collect(&serde_json::to_value(Config::default()).unwrap(), "", &mut keys);
+        keys
+    }
+
+    /// The environment variable overriding `key`
+    pub fn env_var(key: &str) -> String {
+        format!("{}{}", ENV_PREFIX, key.replace('.', "_").to_uppercase())
+    }
+
+    /// Override the setting `key` with `value`, parsed according to the type of the setting.
+    /// Lists are comma separated, and an empty value unsets an optional setting.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let mut root = serde_json::to_value(&*self).unwrap();
+        let slot = key
+            .split('.')
+            .try_fold(&mut root, |node, name| node.get_mut(name))
+            .filter(|slot| !slot.is_object())
+            .ok_or_else(|| format!("unknown setting {}", key))?;
+        *slot = match slot {
+            Value::Bool(_) => Value::Bool(value.parse().map_err(|e| format!("invalid {} {}: {}", key, value, e))?),
+            Value::Number(_) => {
+                serde_json::from_str::<serde_json::Number>(value)
+                    .map(Value::Number)
+                    .map_err(|e| format!("invalid {} {}: {}", key, value, e))?
+            }
+            Value::Array(_) => Value::Array(
+                value.split(',').map(str::trim).filter(|v| !v.is_empty()).map(|v| Value::String(v.to_string())).collect(),
+            ),
+            _ if value.is_empty() => Value::Null,
+            _ => Value::String(value.to_string()),
+        };
+        *self = serde_json::from_value(root).map_err(|e| format!("invalid {} {}: {}", key, value, e))?;
+        Ok(())
+    }
+
+    /// Apply the `BITCOIN_*` variables found in `vars`
+    pub fn apply_env<I: IntoIterator<Item = (String, String)>>(&mut self, vars: I) -> Result<(), String> {
+        let keys = Self::keys();
+        for (name, value) in vars {
+            if !name.starts_with(ENV_PREFIX) {
+                continue;
+            }
+            match keys.iter().find(|key| Self::env_var(key) == name) {
+                Some(key) => self.set(key, &value)?,
+                None => return Err(format!("unknown setting in environment variable {}", name)),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layers_override_in_order() {
+        let mut config: Config = serde_json::from_str(r#"{"mining": {"threads": 2, "enabled": true}}"#).unwrap();
+        assert_eq!(config.mining.threads, 2);
+        assert_eq!(config.mining.lambda, 0);
+
+        let env = vec![
+            ("BITCOIN_MINING_THREADS".to_string(), "3".to_string()),
+            ("BITCOIN_API_AUTH_TOKEN".to_string(), "secret".to_string()),
+            ("HOME".to_string(), "/root".to_string()),
+        ];
+        config.apply_env(env).unwrap();
+        assert_eq!(config.mining.threads, 3);
+        assert_eq!(config.api.auth_token, Some("secret".to_string()));
+
+        config.set("mining.threads", "4").unwrap();
+        config.set("mining.enabled", "false").unwrap();
+        config.set("p2p.connect", "127.0.0.1:6001, 127.0.0.1:6002").unwrap();
+        config.set("api.auth_token", "").unwrap();
+        assert_eq!(config.mining.threads, 4);
+        assert!(!config.mining.enabled);
+        assert_eq!(config.p2p.connect, vec!["127.0.0.1:6001", "127.0.0.1:6002"]);
+        assert_eq!(config.api.auth_token, None);
+    }
+
+    #[test]
+    fn rejects_bad_settings() {
+        let mut config = Config::default();
+        assert!(config.set("mining.speed", "1").is_err());
+        assert!(config.set("mining", "1").is_err());
+        assert!(config.set("mining.threads", "many").is_err());
+        assert!(config.set("mining.threads", "-1").is_err());
+        assert!(config.apply_env(vec![("BITCOIN_TURBO".to_string(), "1".to_string())]).is_err());
+        assert_eq!(config, Config::default());
+        assert!(Config::keys().contains(&"generator.interval_ms".to_string()));
+        assert_eq!(Config::env_var("generator.interval_ms"), "BITCOIN_GENERATOR_INTERVAL_MS");
+    }
+}
@@ -0,0 +1,87 @@
+use crate::block::Block;
+use crate::crypto::hash::{H256, Hashable};
+use std::collections::HashMap;
+
+/// The rule picking the tip among the known blocks. Unlike the engine, the rule is a node
+/// setting: blocks are valid whatever the rule, only the chain followed differs.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ForkChoice {
+    /// Follow the chain the engine prefers, by default the longest one
+    #[default]
+    Longest,
+    /// Greedy heaviest-observed subtree: starting at genesis, step into the child whose subtree
+    /// holds the most blocks. Every block carries the same difficulty, so blocks stand for work.
+    Ghost,
+}
+
+/// Parses `longest` or `ghost`
+impl std::str::FromStr for ForkChoice {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "longest" => Ok(ForkChoice::Longest),
+            "ghost" => Ok(ForkChoice::Ghost),
+            _ => Err(format!("unknown fork choice {}", s)),
+        }
+    }
+}
+
+/// The children of every known block, in arrival order, and the number of blocks in the subtree
+/// rooted at each of them
+pub struct BlockTree {
+    children: HashMap<H256, Vec<H256>>,
+    weight: HashMap<H256, u64>,
+}
+
+impl BlockTree {
+    pub fn new(root: H256) -> Self {
+        let mut weight = HashMap::new();
+        weight.insert(root, 1);
+        Self { children: HashMap::new(), weight }
+    }
+
+    /// Add `block`, whose parent must already be in the tree, and count it in the weight of
+    /// every ancestor. `blocks` must hold all the ancestors.
+    pub fn insert(&mut self, block: &Block, blocks: &HashMap<H256, Block>) {
+        let hash = block.hash();
+        self.children.entry(block.header.parent).or_default().push(hash);
+        self.weight.insert(hash, 1);
+        let mut ancestor = block.header.parent;
+        while let Some(weight) = self.weight.get_mut(&ancestor) {
+            *weight += 1;
+            ancestor = match blocks.get(&ancestor) {
+                Some(block) => block.header.parent,
+                None => break,
+            };
+        }
+    }
+
+    /// Number of blocks in the subtree rooted at `hash`, itself included
+    pub fn weight(&self, hash: &H256) -> u64 {
+        self.weight.get(hash).cloned().unwrap_or(0)
+    }
+
+    pub fn children(&self, hash: &H256) -> &[H256] {
+        self.children.get(hash).map(|c| c.as_slice()).unwrap_or(&[])
+    }
+
+    /// The leaf reached from `root` by always stepping into the heaviest child. Among children
+    /// of equal weight the one seen first wins, so the tip does not flap between equal subtrees.
+    pub fn ghost_tip(&self, root: &H256) -> H256 {
+        let mut tip = *root;
+        loop {
+            let mut heaviest: Option<(H256, u64)> = None;
+            for child in self.children(&tip) {
+                let weight = self.weight(child);
+                if heaviest.is_none_or(|(_, w)| weight > w) {
+                    heaviest = Some((*child, weight));
+                }
+            }
+            match heaviest {
+                Some((child, _)) => tip = child,
+                None => return tip,
+            }
+        }
+    }
+}
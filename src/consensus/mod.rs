@@ -0,0 +1,103 @@
+//! Consensus engines. An engine decides who may produce a block, which headers are valid, which
+//! of two chains to follow and which blocks are final. The engine of a chain is fixed by
+//! `GenesisParams::consensus`. Which chain a node follows can also be switched to GHOST with
+//! `ForkChoice`.
+
+pub mod fork_choice;
+pub mod pos;
+pub mod pow;
+
+use crate::block::Header;
+use crate::blockchain::{Blockchain, State};
+use crate::crypto::hash::{H160, H256, Hashable};
+use crate::pow::ProofOfWork;
+use crate::validation::BlockError;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+pub use fork_choice::{BlockTree, ForkChoice};
+
+pub trait Consensus: Send + Sync {
+    /// Name of the engine, as accepted by `ConsensusKind::from_str`
+    fn name(&self) -> String;
+
+    /// Length of a block production slot in milliseconds, or `None` if blocks are produced by
+    /// searching for a nonce
+    fn slot_duration(&self) -> Option<u64>;
+
+    /// Whether `producer` may extend `parent` with a block stamped `timestamp`
+    fn is_eligible(&self, parent: &H256, producer: &H160, timestamp: u128, state: &State) -> bool;
+
+    /// Checks of `header` specific to this engine, against its `parent` and the account `state`
+    /// after that parent
+    fn verify_header(&self, header: &Header, parent: &Header, state: &State) -> Result<(), BlockError>;
+
+    /// Fork choice: whether `candidate` should replace `current` as the tip. Both blocks must
+    /// already be in `blockchain`.
+    fn prefers(&self, blockchain: &Blockchain, candidate: &H256, current: &H256) -> bool {
+        blockchain.height(candidate) > blockchain.height(current)
+    }
+
+    /// Number of blocks that must be built on top of a block before it is final
+    fn finality_depth(&self) -> u64;
+
+    /// The deepest block of the longest chain that is final. `Blockchain` never moves its tip to
+    /// a branch leaving the chain below this block.
+    fn finalized(&self, blockchain: &Blockchain) -> H256 {
+        let tip_height = blockchain.height(&blockchain.tip()).unwrap_or(0);
+        let height = tip_height.saturating_sub(self.finality_depth());
+        blockchain.block_at_height(height).unwrap_or_else(|| blockchain.genesis.hash())
+    }
+}
+
+/// The engine of a chain, as recorded in its genesis parameters
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ConsensusKind {
+    #[default]
+    ProofOfWork,
+    ProofOfStake { slot_ms: u64, finality_depth: u64 },
+}
+
+impl ConsensusKind {
+    pub fn build(&self, puzzle: Arc<dyn ProofOfWork>) -> Arc<dyn Consensus> {
+        match *self {
+            ConsensusKind::ProofOfWork => Arc::new(pow::Nakamoto::new(puzzle)),
+            ConsensusKind::ProofOfStake { slot_ms, finality_depth } => {
+                Arc::new(pos::ProofOfStake::new(slot_ms, finality_depth))
+            }
+        }
+    }
+}
+
+/// Parses `pow`, `pos`, `pos:<slot ms>` or `pos:<slot ms>:<finality depth>`
+impl std::str::FromStr for ConsensusKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pow" => Ok(ConsensusKind::ProofOfWork),
+            "pos" => Ok(ConsensusKind::ProofOfStake {
+                slot_ms: pos::DEFAULT_SLOT_MS,
+                finality_depth: pos::DEFAULT_FINALITY_DEPTH,
+            }),
+            _ => match s.strip_prefix("pos:") {
+                Some(args) => {
+                    let mut args = args.splitn(2, ':');
+                    let slot_ms = args.next().unwrap_or_default();
+                    let slot_ms = slot_ms
+                        .parse::<u64>()
+                        .map_err(|e| format!("invalid slot length {}: {}", slot_ms, e))?;
+                    let finality_depth = match args.next() {
+                        Some(depth) => depth
+                            .parse::<u64>()
+                            .map_err(|e| format!("invalid finality depth {}: {}", depth, e))?,
+                        None => pos::DEFAULT_FINALITY_DEPTH,
+                    };
+                    Ok(ConsensusKind::ProofOfStake { slot_ms: slot_ms.max(1), finality_depth })
+                }
+                None => Err(format!("unknown consensus {}", s)),
+            },
+        }
+    }
+}
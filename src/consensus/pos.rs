@@ -0,0 +1,155 @@
+use super::Consensus;
+use crate::block::Header;
+use crate::blockchain::State;
+use crate::crypto::hash::{H160, H256};
+use crate::transaction::address_from_public_key;
+use crate::validation::BlockError;
+use ring::signature::{Ed25519KeyPair, KeyPair};
+
+pub const DEFAULT_SLOT_MS: u64 = 2000;
+pub const DEFAULT_FINALITY_DEPTH: u64 = 3;
+
+/// Length of an Ed25519 public key, which starts the seal
+const PUBLIC_KEY_LEN: usize = 32;
+
+/// Slot-based proof of stake. Time is divided into slots; the leader of a slot is drawn from the
+/// accounts of `State`, weighted by balance, with a seed derived from the parent block and the
+/// slot number. Only the leader may produce a block in its slot, and signs the header.
+///
+/// Stake is read from the account state after the parent block, so every node agrees on the
+/// leader of a slot whatever branch it currently follows.
+pub struct ProofOfStake {
+    slot_ms: u64,
+    finality_depth: u64,
+}
+
+impl ProofOfStake {
+    pub fn new(slot_ms: u64, finality_depth: u64) -> Self {
+        Self { slot_ms: slot_ms.max(1), finality_depth }
+    }
+
+    pub fn slot(&self, timestamp: u128) -> u64 {
+        (timestamp / self.slot_ms as u128) as u64
+    }
+}
+
+/// The account chosen to produce the child of `parent` in `slot`, or `None` if nobody has stake
+pub fn leader(parent: &H256, slot: u64, state: &State) -> Option<H160> {
+    let mut stakes: Vec<(H160, u64)> = state
+        .accountMaping
+        .iter()
+        .filter(|(_, (_, balance))| *balance > 0)
+        .map(|(addr, (_, balance))| (*addr, *balance as u64))
+        .collect();
+    stakes.sort_by(|a, b| a.0.as_ref().cmp(b.0.as_ref()));
+    let total: u64 = stakes.iter().map(|(_, stake)| stake).sum();
+    if total == 0 {
+        return None;
+    }
+    let mut seed = parent.as_ref().to_vec();
+    seed.extend_from_slice(&slot.to_be_bytes());
+    let digest = ring::digest::digest(&ring::digest::SHA256, &seed);
+    let mut point = [0u8; 8];
+    point.copy_from_slice(&digest.as_ref()[..8]);
+    let mut point = u64::from_be_bytes(point) % total;
+    for (addr, stake) in stakes {
+        if point < stake {
+            return Some(addr);
+        }
+        point -= stake;
+    }
+    None
+}
+
+/// Sign `header` with `key`. The seal is the public key followed by the signature of
+/// `Header::seal_hash`.
+pub fn seal(header: &mut Header, key: &Ed25519KeyPair) {
+    let signature = key.sign(header.seal_hash().as_ref());
+    let mut seal = key.public_key().as_ref().to_vec();
+    seal.extend_from_slice(signature.as_ref());
+    header.seal = seal;
+}
+
+/// The address that sealed `header`, if the seal is a valid signature
+pub fn signer(header: &Header) -> Option<H160> {
+    if header.seal.len() <= PUBLIC_KEY_LEN {
+        return None;
+    }
+    let (public_key, signature) = header.seal.split_at(PUBLIC_KEY_LEN);
+    let public_key = ring::signature::UnparsedPublicKey::new(&ring::signature::ED25519, public_key);
+    public_key.verify(header.seal_hash().as_ref(), signature).ok()?;
+    Some(address_from_public_key(&header.seal[..PUBLIC_KEY_LEN]))
+}
+
+impl Consensus for ProofOfStake {
+    fn name(&self) -> String {
+        format!("pos:{}:{}", self.slot_ms, self.finality_depth)
+    }
+
+    fn slot_duration(&self) -> Option<u64> {
+        Some(self.slot_ms)
+    }
+
+    fn is_eligible(&self, parent: &H256, producer: &H160, timestamp: u128, state: &State) -> bool {
+        leader(parent, self.slot(timestamp), state) == Some(*producer)
+    }
+
+    fn verify_header(&self, header: &Header, parent: &Header, state: &State) -> Result<(), BlockError> {
+        if self.slot(header.timestamp) <= self.slot(parent.timestamp) {
+            return Err(BlockError::SlotNotAfterParent);
+        }
+        if signer(header) != Some(header.miner) {
+            return Err(BlockError::InvalidSeal);
+        }
+        if !self.is_eligible(&header.parent, &header.miner, header.timestamp, state) {
+            return Err(BlockError::NotSlotLeader(header.miner));
+        }
+        Ok(())
+    }
+
+    fn finality_depth(&self) -> u64 {
+        self.finality_depth
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::test::generate_random_block;
+    use crate::crypto::key_pair;
+    use crate::crypto::hash::Hashable;
+
+    #[test]
+    fn leader_follows_stake() {
+        let parent = H256::default();
+        let mut state = State::new();
+        assert_eq!(leader(&parent, 0, &state), None);
+        let rich: H160 = [1u8; 20].into();
+        let poor: H160 = [2u8; 20].into();
+        state.accountMaping.insert(rich, (0, 999));
+        state.accountMaping.insert(poor, (0, 1));
+        let wins = (0..1000).filter(|slot| leader(&parent, *slot, &state) == Some(rich)).count();
+        assert!(wins > 950);
+        assert_eq!(leader(&parent, 7, &state), leader(&parent, 7, &state));
+    }
+
+    #[test]
+    fn sealed_header_verifies() {
+        let key = key_pair::random();
+        let producer = address_from_public_key(key.public_key().as_ref());
+        let mut state = State::new();
+        state.accountMaping.insert(producer, (0, 10));
+
+        let engine = ProofOfStake::new(1000, DEFAULT_FINALITY_DEPTH);
+        let parent = generate_random_block(&H256::default()).header;
+        let mut header = generate_random_block(&parent.hash()).header;
+        header.timestamp = parent.timestamp + 1000;
+        header.miner = producer;
+        assert_eq!(engine.verify_header(&header, &parent, &state), Err(BlockError::InvalidSeal));
+        seal(&mut header, &key);
+        assert_eq!(engine.verify_header(&header, &parent, &state), Ok(()));
+
+        header.nonce += 1;
+        assert_eq!(engine.verify_header(&header, &parent, &state), Err(BlockError::InvalidSeal));
+    }
+}
@@ -0,0 +1,50 @@
+use super::Consensus;
+use crate::block::Header;
+use crate::blockchain::State;
+use crate::crypto::hash::{H160, H256};
+use crate::pow::ProofOfWork;
+use crate::validation::BlockError;
+use std::sync::Arc;
+
+/// Confirmations after which a proof-of-work block is treated as final
+pub const CONFIRMATIONS: u64 = 6;
+
+/// Nakamoto consensus: anyone may produce a block by solving the puzzle, and the longest chain
+/// wins. Finality is probabilistic, approximated by a fixed number of confirmations.
+pub struct Nakamoto {
+    puzzle: Arc<dyn ProofOfWork>,
+}
+
+impl Nakamoto {
+    pub fn new(puzzle: Arc<dyn ProofOfWork>) -> Self {
+        Self { puzzle }
+    }
+}
+
+impl Consensus for Nakamoto {
+    fn name(&self) -> String {
+        "pow".to_string()
+    }
+
+    fn slot_duration(&self) -> Option<u64> {
+        None
+    }
+
+    fn is_eligible(&self, _parent: &H256, _producer: &H160, _timestamp: u128, _state: &State) -> bool {
+        true
+    }
+
+    fn verify_header(&self, header: &Header, parent: &Header, _state: &State) -> Result<(), BlockError> {
+        if header.difficulty != parent.difficulty {
+            return Err(BlockError::WrongDifficulty);
+        }
+        if !self.puzzle.meets_target(header, &header.difficulty) {
+            return Err(BlockError::InsufficientWork);
+        }
+        Ok(())
+    }
+
+    fn finality_depth(&self) -> u64 {
+        CONFIRMATIONS
+    }
+}
@@ -54,6 +54,15 @@ impl std::fmt::Debug for H256 {
     }
 }
 
+impl std::fmt::Display for H160 {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for byte in self.0.iter() {
+            write!(f, "{:>02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
 //Dubug format for H160
 impl std::fmt::Debug for H160 {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
@@ -159,6 +168,28 @@ impl std::convert::From<ring::digest::Digest> for H160 {
     }
 }
 
+impl std::str::FromStr for H256 {
+    type Err = hex::FromHexError;
+
+    /// Parse a hash from its 64-character hex encoding.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut buffer: [u8; 32] = [0; 32];
+        hex::decode_to_slice(s, &mut buffer)?;
+        Ok(H256(buffer))
+    }
+}
+
+impl std::str::FromStr for H160 {
+    type Err = hex::FromHexError;
+
+    /// Parse an address from its 40-character hex encoding.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut buffer: [u8; 20] = [0; 20];
+        hex::decode_to_slice(s, &mut buffer)?;
+        Ok(H160(buffer))
+    }
+}
+
 impl Ord for H256 {
     fn cmp(&self, other: &H256) -> std::cmp::Ordering {
         let self_higher = u128::from_be_bytes(self.0[0..16].try_into().unwrap());
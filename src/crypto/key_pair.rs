@@ -1,3 +1,4 @@
+use ring::digest;
 use ring::rand;
 use ring::signature::Ed25519KeyPair;
 
@@ -8,6 +9,14 @@ pub fn random() -> Ed25519KeyPair {
     Ed25519KeyPair::from_pkcs8(pkcs8_bytes.as_ref().into()).unwrap()
 }
 
+/// The `index`th key pair derived from `seed`; the same seed always yields the same keys.
+pub fn from_seed(seed: u64, index: u64) -> Ed25519KeyPair {
+    let mut ctx = digest::Context::new(&digest::SHA256);
+    ctx.update(&seed.to_be_bytes());
+    ctx.update(&index.to_be_bytes());
+    Ed25519KeyPair::from_seed_unchecked(ctx.finish().as_ref()).unwrap()
+}
+
  pub fn Hardcoded() -> Ed25519KeyPair {
     let hardcode_bytes = [48, 83, 2, 1, 1, 48, 5, 6, 3, 43, 101, 112, 4, 34, 4, 32, 164, 196, 187, 131, 199, 71, 156, 239, 32, 227, 138, 181, 123, 135, 161, 30, 135, 62, 221, 229, 53, 40, 141, 194, 32, 153, 204, 201, 82, 74, 136, 52, 161, 35, 3, 33, 0, 214, 108, 94, 124, 153, 185, 216, 21, 188, 195, 246, 195, 103, 23, 29, 199, 96, 116, 165, 210, 11, 198, 245, 234, 179, 119, 199, 232, 74, 79, 155, 35];
     Ed25519KeyPair::from_pkcs8(&hardcode_bytes).unwrap()
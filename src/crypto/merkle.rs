@@ -1,5 +1,17 @@
+use super::domain::{self, Domain};
 use super::hash::{Hashable, H256};
 
+/// The leaf committing to an item whose hash is `datum`
+fn leaf(datum: &H256) -> H256 {
+    domain::hash(Domain::MerkleLeaf, datum.as_ref())
+}
+
+/// The parent of nodes `left` and `right`. Leaves and nodes are hashed in separate domains, so
+/// a node can never be passed off as a leaf.
+fn node(left: &H256, right: &H256) -> H256 {
+    domain::hash(Domain::MerkleNode, &[left.as_ref(), right.as_ref()].concat())
+}
+
 /// A Merkle tree.
 #[derive(Debug, Default)]
 pub struct MerkleTree {
@@ -9,78 +21,44 @@ pub struct MerkleTree {
 
 impl MerkleTree {
 
+    /// Build the tree over the hashes of `data`. A node left without a sibling moves up a level
+    /// as it is instead of being paired with a copy of itself, so no two lists of leaves share a
+    /// root. The root of no leaves is the zero hash.
     pub fn new<T>(data: &[T]) -> Self where T: Hashable, {
-        
-        let mut level_hash: Vec<H256> = Vec::new();
-        let mut level_hashes : Vec<Vec<H256>> = Vec::new();
-        
-        for it in data.iter() {
-            level_hash.push(it.hash());
-        }
-
-        /*for i in level_hash{
-            println!("{:?}", i);
-        }*/
-
-        if(level_hash.len() != 1 && (level_hash.len())%2 == 1)
-        {
-            level_hash.push(level_hash[level_hash.len()-1]);
+        let mut level_hash: Vec<H256> = data.iter().map(|it| leaf(&it.hash())).collect();
+        if level_hash.is_empty() {
+            return Self::default();
         }
+        let mut level_hashes : Vec<Vec<H256>> = Vec::new();
 
         while level_hash.len() != 1 {
-
-            if(level_hash.len() % 2 != 0)
-            {
-                //println!("{:?}", "odd!");
-                level_hash.push(level_hash[level_hash.len()-1]);
-            }
-            
-            let cur_size = level_hash.len();
-            level_hashes.push(level_hash.clone());
-
-            for i in (0..cur_size).step_by(2) {
-                
-                
-                let tmp1 = level_hash[i].clone();
-                let tmp2 = level_hash[i+1].clone();
-                let v1 = tmp1.as_ref();
-                let v2 = tmp2.as_ref();
-                let concatenation = [v1, v2].concat();
-                //println!("{:?}", &concatenation);
-
-                let hash_value = ring::digest::digest(&ring::digest::SHA256, &concatenation);
-                let new_hash = H256::from(hash_value);
-                //println!("{}", new_hash);
-                level_hash.push(new_hash.clone());
-
-            }
-            level_hash = level_hash[cur_size..].to_vec();
+            let next_level: Vec<H256> = level_hash
+                .chunks(2)
+                .map(|pair| if pair.len() == 2 { node(&pair[0], &pair[1]) } else { pair[0] })
+                .collect();
+            level_hashes.push(level_hash);
+            level_hash = next_level;
         }
-        level_hashes.push(level_hash.clone());
-        //println!("{:?}", level_hashes[2].len());
-        Self{level_hashes : level_hashes, Root : level_hash[0]}
+        let root = level_hash[0];
+        level_hashes.push(level_hash);
+        Self{level_hashes : level_hashes, Root : root}
     }
 
     pub fn root(&self) -> H256 {
         return self.Root;
     }
 
-    /// Returns the Merkle Proof of data at index i
+    /// Returns the Merkle Proof of data at index i, the siblings from the leaf up. Levels where
+    /// the node has no sibling contribute nothing.
     pub fn proof(&self, index: usize) -> Vec<H256> {
         
         let mut proofs: Vec<H256> = Vec::new();
-        let levels = self.level_hashes.len();
+        let levels = self.level_hashes.len().saturating_sub(1);
         let mut Idx = index;
 
-        for i in 0..(levels-1) {
-            let quotient = Idx/2;
-            let remain = Idx%2;
-
-            if remain == 0 {
-                proofs.push(self.level_hashes[i][2*quotient + 1].clone());
-            }
-            else {
-                proofs.push(self.level_hashes[i][2*quotient].clone());
+        for level in self.level_hashes.iter().take(levels) {
+            if let Some(sibling) = level.get(Idx ^ 1) {
+                proofs.push(*sibling);
             }
             Idx /= 2;
         }
@@ -90,37 +68,37 @@ impl MerkleTree {
 }
 
 /// Verify that the datum hash with a vector of proofs will produce the Merkle root. Also need the
-/// index of datum and `leaf_size`, the total number of leaves.
+/// index of datum and `leaf_size`, the total number of leaves, which tells the levels where the
+/// node has no sibling.
 pub fn verify(root: &H256, datum: &H256, proof: &[H256], index: usize, leaf_size: usize) -> bool {
-    
-    let proof_num = proof.len();
-    let mut cur_hash = datum.clone();
+    if index >= leaf_size {
+        return false;
+    }
+    let mut cur_hash = leaf(datum);
+    let mut siblings = proof.iter();
     let mut Idx = index;
-    let mut concatenation =  [cur_hash.as_ref(), cur_hash.as_ref()].concat();
-
-    for i in (0..proof_num) {
-        if(Idx % 2 == 0)
-        {
-            concatenation = [cur_hash.as_ref(), proof[i].as_ref()].concat();
-        }
-        else
-        {
-            concatenation = [proof[i].as_ref(), cur_hash.as_ref()].concat();
+    let mut width = leaf_size;
+
+    while width > 1 {
+        if (Idx ^ 1) < width {
+            let sibling = match siblings.next() {
+                Some(sibling) => sibling,
+                None => return false,
+            };
+            if(Idx % 2 == 0)
+            {
+                cur_hash = node(&cur_hash, sibling);
+            }
+            else
+            {
+                cur_hash = node(sibling, &cur_hash);
+            }
         }
-        let hash_value = ring::digest::digest(&ring::digest::SHA256, &concatenation);
-        cur_hash = H256::from(hash_value);
         Idx /= 2;
+        width = width.div_ceil(2);
     }
 
-    if cur_hash == *root {
-        //println!("{}", "true");
-        return true;
-    }
-    else {
-        //println!("{}", "false");
-        return false;
-    }
-    
+    siblings.next().is_none() && cur_hash == *root
 }
 
 #[cfg(test)]
@@ -160,14 +138,16 @@ mod tests {
         let root = merkle_tree.root();
         assert_eq!(
             root,
-            (hex!("6b787718210e0b3b608814e04e61fde06d0df794319a12162f287412df3ec920")).into()
+            (hex!("9f8c2cd952a6830a2743dc16846bfacf380146bc79668773133cc4a17c3a1928")).into()
         );
         // "b69566be6e1720872f73651d1851a0eae0060a132cf0f64a0ffaea248de6cba0" is the hash of
-        // "0a0b0c0d0e0f0e0d0a0b0c0d0e0f0e0d0a0b0c0d0e0f0e0d0a0b0c0d0e0f0e0d"
+        // "0a0b0c0d0e0f0e0d0a0b0c0d0e0f0e0d0a0b0c0d0e0f0e0d0a0b0c0d0e0f0e0d", and
+        // "f0b833bbd10af97cf91be0d361d5d5e481f17f8eb1576a26952128d66766f570" its leaf
         // "965b093a75a75895a351786dd7a188515173f6928a8af8c9baa4dcff268a4f0f" is the hash of
-        // "0101010101010101010101010101010101010101010101010101010101010202"
-        // "6b787718210e0b3b608814e04e61fde06d0df794319a12162f287412df3ec920" is the hash of
-        // the concatenation of these two hashes "b69..." and "965..."
+        // "0101010101010101010101010101010101010101010101010101010101010202", and
+        // "a3df90663822202f2a398d565105ac4bf2c187ee2b344084fd59f8905ae009ba" its leaf
+        // "9f8c2cd952a6830a2743dc16846bfacf380146bc79668773133cc4a17c3a1928" is the node over
+        // the concatenation of these two leaves "f0b..." and "a3d..."
         // notice that the order of these two matters
     }
 
@@ -177,9 +157,9 @@ mod tests {
         let merkle_tree = MerkleTree::new(&input_data);
         let proof = merkle_tree.proof(0);
         assert_eq!(proof,
-                   vec![hex!("965b093a75a75895a351786dd7a188515173f6928a8af8c9baa4dcff268a4f0f").into()]
+                   vec![hex!("a3df90663822202f2a398d565105ac4bf2c187ee2b344084fd59f8905ae009ba").into()]
         );
-        // "965b093a75a75895a351786dd7a188515173f6928a8af8c9baa4dcff268a4f0f" is the hash of
+        // "a3df90663822202f2a398d565105ac4bf2c187ee2b344084fd59f8905ae009ba" is the leaf of
         // "0101010101010101010101010101010101010101010101010101010101010202"
     }
 
@@ -191,4 +171,42 @@ mod tests {
         let proof = merkle_tree.proof(0);
         assert!(verify(&merkle_tree.root(), &input_data[0].hash(), &proof, 0, input_data.len()));
     }
+
+    #[test]
+    fn verifying_every_leaf() {
+        let input_data: Vec<H256> = gen_merkle_tree_data_v1!();
+        for len in 1..=input_data.len() {
+            let merkle_tree = MerkleTree::new(&input_data[..len]);
+            for (i, datum) in input_data[..len].iter().enumerate() {
+                let proof = merkle_tree.proof(i);
+                assert!(verify(&merkle_tree.root(), &datum.hash(), &proof, i, len));
+            }
+        }
+    }
+
+    #[test]
+    fn odd_leaf_is_not_duplicated() {
+        let input_data: Vec<H256> = gen_merkle_tree_data_v1!();
+        let three = input_data[..3].to_vec();
+        let mut four = three.clone();
+        four.push(three[2]);
+        assert_ne!(MerkleTree::new(&three).root(), MerkleTree::new(&four).root());
+    }
+
+    #[test]
+    fn empty_tree() {
+        let merkle_tree = MerkleTree::new::<H256>(&[]);
+        assert_eq!(merkle_tree.root(), H256::default());
+        assert!(merkle_tree.proof(0).is_empty());
+    }
+
+    #[test]
+    fn inner_node_is_not_a_leaf() {
+        let input_data: Vec<H256> = gen_merkle_tree_data_v1!();
+        let merkle_tree = MerkleTree::new(&input_data);
+        let levels = &merkle_tree.level_hashes;
+        // the top two nodes below the root, offered as a datum and its proof
+        let below_root = &levels[levels.len() - 2];
+        assert!(!verify(&merkle_tree.root(), &below_root[0], &below_root[1..], 0, 2));
+    }
 }
@@ -1,3 +1,4 @@
 pub mod hash;
+pub mod domain;
 pub mod merkle;
 pub mod key_pair;
@@ -0,0 +1,158 @@
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// Number of recent events kept for subscribers that poll late
+const EVENT_BUFFER_SIZE: usize = 4096;
+
+/// Something observable that happened on the node. Hashes and addresses are hex encoded.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    NewTip { hash: String, height: u64 },
+    /// The longest chain switched branches. Disconnected blocks are listed from the old tip
+    /// downwards, connected blocks from the common ancestor upwards.
+    Reorg { disconnected: Vec<String>, connected: Vec<String> },
+    NewTransaction { hash: String },
+    TransactionConfirmed { hash: String, block: String },
+    /// A transaction was refused by the mempool, as it spends the same sender nonce as another
+    TransactionConflict { hash: String, conflicts_with: String, sender: String, nonce: u16 },
+    PeerConnected { addr: String },
+    PeerDisconnected { addr: String },
+}
+
+impl Event {
+    /// The name used to filter subscriptions, the same as the `type` tag in JSON
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Event::NewTip { .. } => "new_tip",
+            Event::Reorg { .. } => "reorg",
+            Event::NewTransaction { .. } => "new_transaction",
+            Event::TransactionConfirmed { .. } => "transaction_confirmed",
+            Event::TransactionConflict { .. } => "transaction_conflict",
+            Event::PeerConnected { .. } => "peer_connected",
+            Event::PeerDisconnected { .. } => "peer_disconnected",
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Record {
+    pub seq: u64,
+    pub event: Event,
+}
+
+/// The answer to a poll: matching events after the requested sequence number, and the sequence
+/// number to poll from next
+#[derive(Serialize, Debug)]
+pub struct Poll {
+    pub next: u64,
+    /// Number of requested events that were dropped from the buffer before being polled
+    pub missed: u64,
+    pub events: Vec<Record>,
+}
+
+struct Buffer {
+    next_seq: u64,
+    records: VecDeque<Record>,
+}
+
+/// Fan-out point for node events. Producers publish, subscribers long-poll with the sequence
+/// number they have seen so far.
+#[derive(Clone)]
+pub struct EventBus {
+    inner: Arc<(Mutex<Buffer>, Condvar)>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        let buffer = Buffer { next_seq: 0, records: VecDeque::new() };
+        Self { inner: Arc::new((Mutex::new(buffer), Condvar::new())) }
+    }
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn publish(&self, event: Event) {
+        let (lock, cvar) = &*self.inner;
+        let mut buffer = lock.lock().unwrap();
+        let seq = buffer.next_seq;
+        buffer.next_seq += 1;
+        buffer.records.push_back(Record { seq, event });
+        if buffer.records.len() > EVENT_BUFFER_SIZE {
+            buffer.records.pop_front();
+        }
+        cvar.notify_all();
+    }
+
+    /// Wait up to `timeout` for events with sequence number `since` or later whose kind is in
+    /// `kinds` (all kinds if empty)
+    pub fn poll(&self, since: u64, kinds: &[String], timeout: Duration) -> Poll {
+        let (lock, cvar) = &*self.inner;
+        let deadline = Instant::now() + timeout;
+        let mut buffer = lock.lock().unwrap();
+        let mut since = since;
+        let mut missed = 0;
+        loop {
+            let oldest = buffer.records.front().map_or(buffer.next_seq, |r| r.seq);
+            if since < oldest {
+                missed += oldest - since;
+                since = oldest;
+            }
+            let events: Vec<Record> = buffer
+                .records
+                .iter()
+                .skip((since - oldest) as usize)
+                .filter(|r| kinds.is_empty() || kinds.iter().any(|k| k == r.event.kind()))
+                .cloned()
+                .collect();
+            since = buffer.next_seq;
+            let now = Instant::now();
+            if !events.is_empty() || now >= deadline {
+                return Poll { next: since, missed, events };
+            }
+            buffer = cvar.wait_timeout(buffer, deadline - now).unwrap().0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn poll_filters_and_advances() {
+        let bus = EventBus::new();
+        bus.publish(Event::NewTransaction { hash: "aa".to_string() });
+        bus.publish(Event::PeerConnected { addr: "127.0.0.1:6000".to_string() });
+
+        let all = bus.poll(0, &[], Duration::from_millis(0));
+        assert_eq!(all.next, 2);
+        assert_eq!(all.events.len(), 2);
+
+        let peers = bus.poll(0, &["peer_connected".to_string()], Duration::from_millis(0));
+        assert_eq!(peers.events.len(), 1);
+        assert_eq!(peers.events[0].seq, 1);
+
+        let none = bus.poll(all.next, &[], Duration::from_millis(10));
+        assert_eq!(none.next, 2);
+        assert!(none.events.is_empty());
+    }
+
+    #[test]
+    fn poll_wakes_on_publish() {
+        let bus = EventBus::new();
+        let publisher = bus.clone();
+        let handle = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            publisher.publish(Event::NewTip { hash: "bb".to_string(), height: 1 });
+        });
+        let polled = bus.poll(0, &[], Duration::from_secs(5));
+        handle.join().unwrap();
+        assert_eq!(polled.events.len(), 1);
+    }
+}
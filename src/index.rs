@@ -0,0 +1,90 @@
+use serde::{Serialize, Deserialize};
+use crate::block::Block;
+use crate::crypto::hash::{H256, H160, Hashable};
+use std::collections::HashMap;
+
+/// Index from an account address to the transactions on the longest chain that send from or
+/// pay to it, as `(block hash, index of the transaction in the block)` in chain order.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct AddressIndex {
+    /// The tip of the longest chain this index is consistent with
+    pub tip: H256,
+    entries: HashMap<H160, Vec<(H256, usize)>>,
+}
+
+impl AddressIndex {
+    pub fn new(tip: H256) -> Self {
+        Self { tip, entries: HashMap::new() }
+    }
+
+    /// Record the transactions of a block that joins the longest chain
+    pub fn connect_block(&mut self, block: &Block) {
+        let block_hash = block.hash();
+        for (i, tx) in block.content.data.iter().enumerate() {
+            self.entries.entry(tx.sender_addr).or_default().push((block_hash, i));
+            if tx.Transaction.recipAddress != tx.sender_addr {
+                self.entries.entry(tx.Transaction.recipAddress).or_default().push((block_hash, i));
+            }
+        }
+        self.tip = block_hash;
+    }
+
+    /// Drop the transactions of a block that leaves the longest chain. Blocks are disconnected
+    /// from the tip downwards, so their entries are always at the end of each list.
+    pub fn disconnect_block(&mut self, block: &Block) {
+        let block_hash = block.hash();
+        for tx in block.content.data.iter() {
+            for addr in [tx.sender_addr, tx.Transaction.recipAddress].iter() {
+                if let Some(list) = self.entries.get_mut(addr) {
+                    while list.last().is_some_and(|(hash, _)| *hash == block_hash) {
+                        list.pop();
+                    }
+                    if list.is_empty() {
+                        self.entries.remove(addr);
+                    }
+                }
+            }
+        }
+        self.tip = block.header.parent;
+    }
+
+    /// Number of indexed transactions involving `addr`
+    pub fn count(&self, addr: &H160) -> usize {
+        self.entries.get(addr).map_or(0, |list| list.len())
+    }
+
+    /// One page of the transactions involving `addr`, oldest first
+    pub fn transactions(&self, addr: &H160, page: usize, limit: usize) -> Vec<(H256, usize)> {
+        match self.entries.get(addr) {
+            Some(list) => list.iter().skip(page.saturating_mul(limit)).take(limit).cloned().collect(),
+            None => Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::test::generate_random_block;
+
+    #[test]
+    fn connect_disconnect() {
+        let parent: H256 = [1; 32].into();
+        let block = generate_random_block(&parent);
+        let sender = block.content.data[0].sender_addr;
+        let recipient = block.content.data[0].Transaction.recipAddress;
+
+        let mut index = AddressIndex::new(parent);
+        index.connect_block(&block);
+        assert_eq!(index.tip, block.hash());
+        assert_eq!(index.transactions(&sender, 0, 10), vec![(block.hash(), 0)]);
+        assert_eq!(index.count(&recipient), 1);
+        assert!(index.transactions(&sender, 1, 10).is_empty());
+        assert!(index.transactions(&sender, usize::MAX, 2).is_empty());
+
+        index.disconnect_block(&block);
+        assert_eq!(index.tip, parent);
+        assert_eq!(index.count(&sender), 0);
+        assert_eq!(index.count(&recipient), 0);
+    }
+}
@@ -5,11 +5,27 @@ extern crate hex_literal;
 pub mod api;
 pub mod block;
 pub mod blockchain;
+pub mod chain_spec;
+pub mod config;
+pub mod consensus;
 pub mod crypto;
+pub mod events;
+pub mod index;
+pub mod mempool;
+pub mod metrics;
 pub mod miner;
 pub mod network;
+pub mod node;
+pub mod pool;
+pub mod pow;
+pub mod shutdown;
+pub mod storage;
 pub mod transaction;
 pub mod TransGen;
+pub mod uncles;
+pub mod validation;
+pub mod verifier;
+pub mod wallet;
 
 use clap::clap_app;
 use crossbeam::channel;
@@ -21,32 +37,134 @@ use std::process;
 use std::thread;
 use std::time;
 use std::sync::{Arc, Mutex};
-use crate::blockchain::{Blockchain, State};
-use crate::crypto::hash::{H256, H160, Hashable};
+use crate::blockchain::{Blockchain, GenesisParams};
+use crate::chain_spec::ChainSpec;
+use crate::config::Config;
+use crate::pow::PowAlgorithm;
+use crate::consensus::{ConsensusKind, ForkChoice};
+use crate::crypto::hash::{H160, Hashable};
 use crate::transaction::{Transaction, SignedTransaction};
-use std::collections::{HashMap, VecDeque};
+use crate::wallet::Wallet;
+use crate::events::EventBus;
+use crate::shutdown::Shutdown;
+use crate::storage::Storage;
+use crate::mempool::Mempool;
+use std::collections::VecDeque;
+
+/// Command line flags taking a value, and the setting each overrides
+const VALUE_FLAGS: &[(&str, &str)] = &[
+    ("peer_addr", "p2p.addr"),
+    ("p2p_workers", "p2p.workers"),
+    ("max_peers", "p2p.max_peers"),
+    ("api_addr", "api.addr"),
+    ("api_token", "api.auth_token"),
+    ("data_dir", "data_dir"),
+    ("chain", "chain"),
+    ("pow", "pow"),
+    ("consensus", "consensus"),
+    ("fork_choice", "fork_choice"),
+    ("mining_threads", "mining.threads"),
+    ("mining_address", "mining.address"),
+    ("mining_lambda", "mining.lambda"),
+    ("block_txs", "mining.max_txs"),
+    ("pool_addr", "pool.addr"),
+    ("share_factor", "pool.share_factor"),
+    ("generator_interval", "generator.interval_ms"),
+    ("generator_value", "generator.value"),
+    ("generator_seed", "generator.seed"),
+    ("generator_accounts", "generator.accounts"),
+    ("generator_arrivals", "generator.arrivals"),
+    ("generator_pattern", "generator.pattern"),
+    ("mempool_size", "mempool.max_size"),
+];
+
+/// Command line switches, and the setting each sets to the given value
+const SWITCHES: &[(&str, &str, &str)] = &[
+    ("addr_index", "addr_index", "true"),
+    ("mine", "mining.enabled", "true"),
+    ("no_generator", "generator.enabled", "false"),
+];
 
 fn main() {
     // parse command line arguments
     let matches = clap_app!(Bitcoin =>
      (version: "0.1")
      (about: "Bitcoin client")
+     (@arg config: --config [FILE] "Reads settings from this JSON file; BITCOIN_* environment variables and flags override it")
+     (@arg dump_config: --("dump-config") "Prints the settings in effect as JSON and exits")
      (@arg verbose: -v ... "Increases the verbosity of logging")
-     (@arg peer_addr: --p2p [ADDR] default_value("127.0.0.1:6000") "Sets the IP address and the port of the P2P server")
-     (@arg api_addr: --api [ADDR] default_value("127.0.0.1:7000") "Sets the IP address and the port of the API server")
+     (@arg peer_addr: --p2p [ADDR] "Sets the IP address and the port of the P2P server [default: 127.0.0.1:6000]")
+     (@arg api_addr: --api [ADDR] "Sets the IP address and the port of the API server [default: 127.0.0.1:7000]")
+     (@arg api_token: --("api-token") [TOKEN] "Requires API requests to carry this bearer token")
      (@arg known_peer: -c --connect ... [PEER] "Sets the peers to connect to at start")
-     (@arg p2p_workers: --("p2p-workers") [INT] default_value("4") "Sets the number of worker threads for P2P server")
+     (@arg p2p_workers: --("p2p-workers") [INT] "Sets the number of worker threads for P2P server [default: 4]")
+     (@arg max_peers: --("max-peers") [INT] "Sets the most peers connected at once [default: 256]")
+     (@arg data_dir: --("data-dir") [DIR] "Persists the blockchain in this directory")
+     (@arg addr_index: --("addr-index") "Maintains an index from addresses to their transactions")
+     (@arg pool_addr: --pool [ADDR] "Runs a mining pool server at this address")
+     (@arg share_factor: --("share-factor") [INT] "Sets how many times easier pool shares are than blocks [default: 16]")
+     (@arg chain: --chain [FILE] "Reads the genesis of the chain from this JSON chain spec")
+     (@arg pow: --pow [ALGO] "Sets the proof of work of a new chain without a spec: sha256, double-sha256 or memory-hard[:KIB] [default: sha256]")
+     (@arg consensus: --consensus [ENGINE] "Sets the consensus of a new chain without a spec: pow, pos, pos:SLOT_MS or pos:SLOT_MS:FINALITY_DEPTH [default: pow]")
+     (@arg fork_choice: --("fork-choice") [RULE] "Sets the rule choosing the chain to follow: longest or ghost [default: longest]")
+     (@arg mine: --mine "Starts mining at launch")
+     (@arg mining_threads: --("mining-threads") [INT] "Sets the number of threads searching for nonces [default: 1]")
+     (@arg mining_address: --("mining-address") [ADDR] "Sets the address paid by mined blocks [default: the wallet's]")
+     (@arg mining_lambda: --("mining-lambda") [MICROS] "Sets the pause between mining attempts [default: 0]")
+     (@arg block_txs: --("block-txs") [INT] "Sets the most transactions per mined block [default: 1]")
+     (@arg no_generator: --("no-generator") "Does not generate transactions until /generator/start")
+     (@arg generator_interval: --("generator-interval") [MS] "Sets the mean time between generated transactions [default: 1000]")
+     (@arg generator_arrivals: --("generator-arrivals") [KIND] "Sets how generated transactions are spaced: constant or poisson [default: constant]")
+     (@arg generator_value: --("generator-value") [DIST] "Sets the value of generated transactions: N, uniform:MIN-MAX or exponential:MEAN [default: 1]")
+     (@arg generator_seed: --("generator-seed") [INT] "Sets the seed of the generated accounts and choices [default: 0]")
+     (@arg generator_accounts: --("generator-accounts") [INT] "Sets the number of generated accounts [default: 8]")
+     (@arg generator_pattern: --("generator-pattern") [PATTERN] "Sets who pays whom: uniform, hotspot[:PERCENT], chain or double-spend [default: uniform]")
+     (@arg mempool_size: --("mempool-size") [INT] "Sets the most transactions held in the mempool [default: 10000]")
     )
     .get_matches();
 
+    // layer the settings: defaults, file, environment, flags
+    let mut config = match matches.value_of("config") {
+        Some(path) => Config::load(std::path::Path::new(path)).unwrap_or_else(|e| {
+            eprintln!("Error loading config: {}", e);
+            process::exit(1);
+        }),
+        None => Config::default(),
+    };
+    let mut overrides: Vec<(&str, String)> = Vec::new();
+    for (flag, key) in VALUE_FLAGS.iter() {
+        if let Some(value) = matches.value_of(flag) {
+            overrides.push((key, value.to_string()));
+        }
+    }
+    for (flag, key, value) in SWITCHES.iter() {
+        if matches.is_present(flag) {
+            overrides.push((key, value.to_string()));
+        }
+    }
+    if let Some(known_peers) = matches.values_of("known_peer") {
+        overrides.push(("p2p.connect", known_peers.collect::<Vec<_>>().join(",")));
+    }
+    config
+        .apply_env(std::env::vars())
+        .and_then(|_| overrides.iter().try_for_each(|(key, value)| config.set(key, value)))
+        .unwrap_or_else(|e| {
+            eprintln!("Error in settings: {}", e);
+            process::exit(1);
+        });
+    config.log.verbosity += matches.occurrences_of("verbose") as usize;
+    if matches.is_present("dump_config") {
+        println!("{}", serde_json::to_string_pretty(&config).unwrap());
+        return;
+    }
+
     // init logger
-    let verbosity = matches.occurrences_of("verbose") as usize;
-    stderrlog::new().verbosity(verbosity).init().unwrap();
+    stderrlog::new().verbosity(config.log.verbosity).init().unwrap();
 
     // parse p2p server address
-    let p2p_addr = matches
-        .value_of("peer_addr")
-        .unwrap()
+    let p2p_addr = config
+        .p2p
+        .addr
         .parse::<net::SocketAddr>()
         .unwrap_or_else(|e| {
             error!("Error parsing P2P server address: {}", e);
@@ -54,78 +172,192 @@ fn main() {
         });
 
     // parse api server address
-    let api_addr = matches
-        .value_of("api_addr")
-        .unwrap()
+    let api_addr = config
+        .api
+        .addr
         .parse::<net::SocketAddr>()
         .unwrap_or_else(|e| {
             error!("Error parsing API server address: {}", e);
             process::exit(1);
         });
 
-    // create channels between server and worker
-    let (msg_tx, msg_rx) = channel::unbounded();
-
-    // start the p2p server
-    let (server_ctx, server) = server::new(p2p_addr, msg_tx).unwrap();
-    server_ctx.start().unwrap();
-
-    // start the worker
-    let p2p_workers = matches
-        .value_of("p2p_workers")
-        .unwrap()
-        .parse::<usize>()
+    // stop on SIGINT, SIGTERM or an API request
+    let shutdown = Shutdown::new();
+    {
+        let shutdown = shutdown.clone();
+        ctrlc::set_handler(move || {
+            info!("Shutdown signal received");
+            shutdown.request();
+        })
         .unwrap_or_else(|e| {
-            error!("Error parsing P2P workers: {}", e);
+            error!("Error installing signal handler: {}", e);
             process::exit(1);
         });
+    }
+
+    // create channels between server and worker
+    let (msg_tx, msg_rx) = channel::unbounded();
+
+    // node events for API subscribers
+    let events = EventBus::new();
 
         // add block chain in Context struct @ miner.rs, so we need to create a new blockchain here
 
     
-    let new_blockchain = Blockchain::new();
+    let addr_index = config.addr_index;
+    let pow = config.pow.parse::<PowAlgorithm>().unwrap_or_else(|e| {
+        error!("Error parsing proof of work: {}", e);
+        process::exit(1);
+    });
+    let consensus = config.consensus.parse::<ConsensusKind>().unwrap_or_else(|e| {
+        error!("Error parsing consensus: {}", e);
+        process::exit(1);
+    });
+    let fork_choice = config.fork_choice.parse::<ForkChoice>().unwrap_or_else(|e| {
+        error!("Error parsing fork choice: {}", e);
+        process::exit(1);
+    });
+    let params = match &config.chain {
+        Some(path) => {
+            let spec = ChainSpec::load(std::path::Path::new(path)).unwrap_or_else(|e| {
+                error!("Error loading chain spec: {}", e);
+                process::exit(1);
+            });
+            info!("Using chain spec {}", spec.name);
+            spec.params().unwrap_or_else(|e| {
+                error!("Error in chain spec {}: {}", path, e);
+                process::exit(1);
+            })
+        }
+        None => GenesisParams { pow, consensus, ..GenesisParams::default() },
+    };
+    let mut new_blockchain = match &config.data_dir {
+        Some(dir) => Blockchain::open(std::path::Path::new(dir), addr_index, params).unwrap_or_else(|e| {
+            error!("Error opening data directory {}: {}", dir, e);
+            process::exit(1);
+        }),
+        None => {
+            let mut blockchain = Blockchain::with_params(params);
+            if addr_index {
+                blockchain.rebuild_address_index();
+            }
+            blockchain
+        }
+    };
+    new_blockchain.set_fork_choice(fork_choice);
+    new_blockchain.events = Some(events.clone());
+    let genesis = new_blockchain.genesis.hash();
+    let states = new_blockchain.tip_state();
+
+    // start the p2p server, which only talks to peers with the same genesis
+    let (server_ctx, server) = server::new(p2p_addr, msg_tx, genesis, config.p2p.max_peers, &events).unwrap();
+    server_ctx.start().unwrap();
+
     let sync_blockchain = Arc::new(Mutex::new(new_blockchain));
-    let mut hash_signedTxs: HashMap<H256, SignedTransaction> = HashMap::new();
+    let hash_signedTxs = Mempool::new();
     //let mut signedTxs = VecDeque::new();
     let mut signedTxs: VecDeque<SignedTransaction> = VecDeque::new();
     let sync_txs = Arc::new(Mutex::new(signedTxs));
     let mempool = Arc::new(Mutex::new(hash_signedTxs));
-    let states = State::new();
     let sync_states =  Arc::new(Mutex::new(states));
+    let wallet = Arc::new(Mutex::new(Wallet::new(transaction::chain_id(&genesis))));
+    let max_mempool = config.mempool.max_size;
 
-    let worker_ctx = worker::new(
-        p2p_workers,
-        msg_rx,
-        &server,
-        &sync_blockchain,
-        &mempool,
-        &sync_states,
-        &sync_txs,
-    );
-    worker_ctx.start();
+    // pick up the pending transactions and peers saved by the last shutdown
+    let storage = config.data_dir.as_ref().map(|dir| Storage::open(std::path::Path::new(dir)).unwrap());
+    if let Some(storage) = &storage {
+        let saved = storage.load_mempool().unwrap_or_else(|e| {
+            error!("Error loading saved mempool: {}", e);
+            Vec::new()
+        });
+        // saved transactions are checked as if a peer had sent them, the chain may have moved
+        let (chain_id, height) = {
+            let blockchain = sync_blockchain.lock().unwrap();
+            (blockchain.chain_id(), blockchain.height(&blockchain.tip()).unwrap_or(0) + 1)
+        };
+        let signed = verifier::global().verify(&saved);
+        let mut pool = mempool.lock().unwrap();
+        let mut txs = sync_txs.lock().unwrap();
+        for (tx, signed) in saved.iter().zip(signed) {
+            if !signed
+                || validation::check_transaction(&tx.Transaction, chain_id, height).is_err()
+            {
+                continue;
+            }
+            if mempool::admit(&mut pool, tx, max_mempool, &events) == mempool::Admission::Added {
+                txs.push_back(tx.clone());
+            }
+        }
+        info!("Loaded {} of {} saved pending transactions", pool.len(), saved.len());
+        let peers = storage.load_peers().unwrap_or_else(|e| {
+            error!("Error loading saved peers: {}", e);
+            Vec::new()
+        });
+        for peer in peers {
+            if !config.p2p.connect.contains(&peer.to_string()) {
+                config.p2p.connect.push(peer.to_string());
+            }
+        }
+    }
 
-    // start the miner
+    let node = node::Shared {
+        server: server.clone(),
+        blockchain: Arc::clone(&sync_blockchain),
+        mempool: Arc::clone(&mempool),
+        max_mempool,
+        states: Arc::clone(&sync_states),
+        txs: Arc::clone(&sync_txs),
+        wallet: Arc::clone(&wallet),
+        events: events.clone(),
+    };
 
-    let (miner_ctx, miner) = miner::new(
-        &server,
-        &sync_blockchain,
-        &mempool,
-        &sync_states,
-        &sync_txs,
-    );
+    let worker_ctx = worker::new(config.p2p.workers, msg_rx, &node);
+    let workers = worker_ctx.start();
+
+    // start the miner
+    let mining_address = match &config.mining.address {
+        Some(addr) => addr.parse::<H160>().unwrap_or_else(|e| {
+            error!("Error parsing mining address: {}", e);
+            process::exit(1);
+        }),
+        None => wallet.lock().unwrap().default_address(),
+    };
+    let (miner_ctx, miner) = miner::new(&node, &mining_address, config.mining.threads.max(1));
     miner_ctx.start();
+    // the miner was just started, so it takes these
+    miner.set_max_txs(config.mining.max_txs).unwrap();
+    if config.mining.enabled {
+        miner.start(config.mining.lambda).unwrap();
+    }
 
-    let generator_ctx = TransGen::new(
-        &server,
-        &mempool,
-        &sync_states,
-        &sync_txs,
-    );
+    // start the mining pool
+    let pool = config.pool.addr.as_ref().map(|pool_addr| {
+        let pool_addr = pool_addr.parse::<net::SocketAddr>().unwrap_or_else(|e| {
+            error!("Error parsing pool address: {}", e);
+            process::exit(1);
+        });
+        let (pool_ctx, pool) = pool::new(pool_addr, &mining_address, config.pool.share_factor, &node);
+        pool_ctx.start().unwrap_or_else(|e| {
+            error!("Error starting pool server: {}", e);
+            process::exit(1);
+        });
+        pool
+    });
+
+    // start the transaction generator
+    let (generator_ctx, generator) = TransGen::new(&node, &config.generator).unwrap_or_else(|e| {
+        error!("Error in generator settings: {}", e);
+        process::exit(1);
+    });
     generator_ctx.start();
+    if config.generator.enabled {
+        // the generator was just started, so it takes this
+        generator.start().unwrap();
+    }
 
     // connect to known peers
-    if let Some(known_peers) = matches.values_of("known_peer") {
-        let known_peers: Vec<String> = known_peers.map(|x| x.to_owned()).collect();
+    if !config.p2p.connect.is_empty() {
+        let known_peers = config.p2p.connect.clone();
         let server = server.clone();
         thread::spawn(move || {
             for peer in known_peers {
@@ -160,11 +392,40 @@ fn main() {
     // start the API server
     ApiServer::start(
         api_addr,
+        config.api.auth_token.clone(),
+        &node,
         &miner,
-        &server,
+        pool.as_ref(),
+        &generator,
+        &shutdown,
     );
 
-    loop {
-        std::thread::park();
+    shutdown.wait();
+    info!("Shutting down");
+    // stop producing blocks and transactions, then let the workers finish what peers sent
+    if let Err(e) = miner.exit() {
+        error!("{}", e);
+    }
+    if let Err(e) = generator.exit() {
+        error!("{}", e);
+    }
+    let peers = server.shutdown();
+    for worker in workers {
+        worker.join().unwrap_or_else(|_| error!("Worker thread panicked"));
+    }
+
+    sync_blockchain.lock().unwrap().flush();
+    if let Some(storage) = &storage {
+        let mut pending: Vec<SignedTransaction> = mempool.lock().unwrap().values().cloned().collect();
+        // reloaded in this order, so each sender's transactions queue up by nonce
+        pending.sort_by_key(|tx| tx.Transaction.accountNonce);
+        if let Err(e) = storage.save_mempool(&pending) {
+            error!("Error saving mempool: {}", e);
+        }
+        if let Err(e) = storage.save_peers(&peers) {
+            error!("Error saving peers: {}", e);
+        }
+        info!("Saved {} pending transactions and {} peers", pending.len(), peers.len());
     }
+    info!("Shutdown complete");
 }
@@ -0,0 +1,168 @@
+//! Admission of transactions into the mempool. Two transactions from the same sender with the
+//! same account nonce conflict, as at most one of them can ever be confirmed. The mempool keeps
+//! the one it saw first: transactions carry no fee, so a replacement would have nothing to outbid.
+
+use crate::crypto::hash::{H160, H256, Hashable};
+use crate::events::{Event, EventBus};
+use crate::metrics;
+use crate::transaction::SignedTransaction;
+use log::info;
+use std::collections::{HashMap, VecDeque};
+
+/// What became of a transaction offered to the mempool
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Admission {
+    Added,
+    /// The same transaction is already in the mempool
+    Known,
+    /// Refused, as it spends the same nonce as this transaction already in the mempool
+    Conflict(H256),
+    /// Refused, as the mempool already holds its maximum number of transactions
+    Full,
+}
+
+/// Pending transactions by txid, also indexed by sender and nonce to find conflicts
+#[derive(Default)]
+pub struct Mempool {
+    txs: HashMap<H256, SignedTransaction>,
+    by_nonce: HashMap<(H160, u16), H256>,
+}
+
+impl Mempool {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.txs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.txs.is_empty()
+    }
+
+    pub fn contains_key(&self, hash: &H256) -> bool {
+        self.txs.contains_key(hash)
+    }
+
+    pub fn get(&self, hash: &H256) -> Option<&SignedTransaction> {
+        self.txs.get(hash)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &SignedTransaction> {
+        self.txs.values()
+    }
+
+    /// Add `tx` without checking for conflicts; `admit` is the way in for new transactions
+    pub fn insert(&mut self, tx: SignedTransaction) {
+        let hash = tx.hash();
+        self.by_nonce.insert((tx.sender_addr, tx.Transaction.accountNonce), hash);
+        self.txs.insert(hash, tx);
+    }
+
+    pub fn remove(&mut self, hash: &H256) -> Option<SignedTransaction> {
+        let tx = self.txs.remove(hash)?;
+        let key = (tx.sender_addr, tx.Transaction.accountNonce);
+        if self.by_nonce.get(&key) == Some(hash) {
+            self.by_nonce.remove(&key);
+        }
+        Some(tx)
+    }
+
+    /// Keep only the transactions for which `keep` holds
+    pub fn retain(&mut self, mut keep: impl FnMut(&H256, &SignedTransaction) -> bool) {
+        let dropped: Vec<H256> = self.txs.iter().filter(|(hash, tx)| !keep(hash, tx)).map(|(hash, _)| *hash).collect();
+        for hash in dropped.iter() {
+            self.remove(hash);
+        }
+    }
+
+    /// The transaction from the sender of `tx` with the same nonce, if any
+    pub fn find_conflict(&self, tx: &SignedTransaction) -> Option<H256> {
+        self.by_nonce.get(&(tx.sender_addr, tx.Transaction.accountNonce)).copied()
+    }
+}
+
+/// Offer `tx` to `mempool`, holding at most `max_size` transactions. An added transaction is
+/// announced on `events`; a conflict is announced and counted.
+pub fn admit(
+    mempool: &mut Mempool,
+    tx: &SignedTransaction,
+    max_size: usize,
+    events: &EventBus,
+) -> Admission {
+    let hash = tx.hash();
+    if mempool.contains_key(&hash) {
+        return Admission::Known;
+    }
+    if let Some(existing) = mempool.find_conflict(tx) {
+        info!("Transaction {} conflicts with {} in the mempool", hash, existing);
+        metrics::global().mempool_conflicts.inc();
+        events.publish(Event::TransactionConflict {
+            hash: hash.to_string(),
+            conflicts_with: existing.to_string(),
+            sender: tx.sender_addr.to_string(),
+            nonce: tx.Transaction.accountNonce,
+        });
+        return Admission::Conflict(existing);
+    }
+    if mempool.len() >= max_size {
+        return Admission::Full;
+    }
+    mempool.insert(tx.clone());
+    events.publish(Event::NewTransaction { hash: hash.to_string() });
+    Admission::Added
+}
+
+/// Drop the transactions of `mempool` and `txs` that a block at `height` may no longer include.
+/// Returns how many were dropped.
+pub fn evict_expired(
+    mempool: &mut Mempool,
+    txs: &mut VecDeque<SignedTransaction>,
+    height: u64,
+) -> usize {
+    let expired = |tx: &SignedTransaction| tx.Transaction.expiry.is_some_and(|expiry| expiry < height);
+    let before = mempool.len();
+    mempool.retain(|_, tx| !expired(tx));
+    txs.retain(|tx| !expired(tx));
+    let evicted = before - mempool.len();
+    if evicted > 0 {
+        info!("Evicted {} expired transactions from the mempool", evicted);
+    }
+    evicted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::tests::generate_random_signed_transaction;
+    use std::time::Duration;
+
+    #[test]
+    fn first_seen_wins() {
+        let events = EventBus::new();
+        let mut mempool = Mempool::new();
+        let first = generate_random_signed_transaction();
+        let mut second = first.clone();
+        second.Transaction.recipAddress = H160::from([9u8; 20]);
+
+        assert_eq!(admit(&mut mempool, &first, 10, &events), Admission::Added);
+        assert_eq!(admit(&mut mempool, &first, 10, &events), Admission::Known);
+        let before = metrics::global().mempool_conflicts.get();
+        assert_eq!(admit(&mut mempool, &second, 10, &events), Admission::Conflict(first.hash()));
+        assert!(metrics::global().mempool_conflicts.get() > before);
+        assert_eq!(mempool.len(), 1);
+
+        let conflicts = events.poll(0, &["transaction_conflict".to_string()], Duration::from_millis(0));
+        assert_eq!(conflicts.events.len(), 1);
+
+        let mut other_nonce = second.clone();
+        other_nonce.Transaction.accountNonce = first.Transaction.accountNonce.wrapping_add(1);
+        assert_eq!(admit(&mut mempool, &other_nonce, 1, &events), Admission::Full);
+        assert_eq!(admit(&mut mempool, &other_nonce, 2, &events), Admission::Added);
+
+        // once the first is gone, its nonce is free again
+        mempool.remove(&first.hash());
+        assert_eq!(admit(&mut mempool, &second, 2, &events), Admission::Added);
+    }
+}
@@ -0,0 +1,242 @@
+//! Process-wide metrics registry, rendered in the Prometheus text exposition format at
+//! `/metrics` on the API server.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+
+/// A monotonically increasing count
+#[derive(Default)]
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    pub fn add(&self, v: u64) {
+        self.0.fetch_add(v, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// A value that can go up and down
+#[derive(Default)]
+pub struct Gauge(AtomicU64);
+
+impl Gauge {
+    pub fn set(&self, v: f64) {
+        self.0.store(v.to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> f64 {
+        f64::from_bits(self.0.load(Ordering::Relaxed))
+    }
+}
+
+/// Counts of observations falling into cumulative buckets, plus their sum
+pub struct Histogram {
+    bounds: Vec<f64>,
+    inner: Mutex<HistogramData>,
+}
+
+struct HistogramData {
+    buckets: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    pub fn new(bounds: &[f64]) -> Self {
+        let data = HistogramData { buckets: vec![0; bounds.len()], sum: 0.0, count: 0 };
+        Self { bounds: bounds.to_vec(), inner: Mutex::new(data) }
+    }
+
+    pub fn observe(&self, v: f64) {
+        let mut data = self.inner.lock().unwrap();
+        for (i, bound) in self.bounds.iter().enumerate() {
+            if v <= *bound {
+                data.buckets[i] += 1;
+            }
+        }
+        data.sum += v;
+        data.count += 1;
+    }
+
+    pub fn count(&self) -> u64 {
+        self.inner.lock().unwrap().count
+    }
+}
+
+/// Counters keyed by a label value, e.g. the message type
+#[derive(Default)]
+pub struct CounterVec(Mutex<BTreeMap<&'static str, u64>>);
+
+impl CounterVec {
+    pub fn add(&self, label: &'static str, v: u64) {
+        *self.0.lock().unwrap().entry(label).or_insert(0) += v;
+    }
+
+    pub fn get(&self, label: &str) -> u64 {
+        self.0.lock().unwrap().get(label).cloned().unwrap_or(0)
+    }
+}
+
+pub struct Metrics {
+    pub blocks_mined: Counter,
+    pub blocks_received: Counter,
+    pub orphan_blocks: Counter,
+    pub orphan_pool_size: Gauge,
+    pub reorgs: Counter,
+    pub reorg_depth: Histogram,
+    pub fork_choice_disagreements: Counter,
+    pub block_propagation_delay_ms: Histogram,
+    pub block_size_bytes: Histogram,
+    pub mempool_size: Gauge,
+    pub mempool_conflicts: Counter,
+    pub signatures_verified: Counter,
+    pub signature_cache_hits: Counter,
+    pub peers: Gauge,
+    pub message_bytes_in: CounterVec,
+    pub message_bytes_out: CounterVec,
+    pub hashes: Counter,
+    pub hash_rate: Gauge,
+    pub pool_shares: CounterVec,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        Self {
+            blocks_mined: Counter::default(),
+            blocks_received: Counter::default(),
+            orphan_blocks: Counter::default(),
+            orphan_pool_size: Gauge::default(),
+            reorgs: Counter::default(),
+            reorg_depth: Histogram::new(&[1.0, 2.0, 3.0, 4.0, 6.0, 8.0, 16.0, 32.0]),
+            fork_choice_disagreements: Counter::default(),
+            block_propagation_delay_ms: Histogram::new(&[
+                10.0, 25.0, 50.0, 100.0, 250.0, 500.0, 1000.0, 2500.0, 5000.0, 10000.0,
+            ]),
+            block_size_bytes: Histogram::new(&[
+                256.0, 512.0, 1024.0, 4096.0, 16384.0, 65536.0, 262144.0, 1048576.0,
+            ]),
+            mempool_size: Gauge::default(),
+            mempool_conflicts: Counter::default(),
+            signatures_verified: Counter::default(),
+            signature_cache_hits: Counter::default(),
+            peers: Gauge::default(),
+            message_bytes_in: CounterVec::default(),
+            message_bytes_out: CounterVec::default(),
+            hashes: Counter::default(),
+            hash_rate: Gauge::default(),
+            pool_shares: CounterVec::default(),
+        }
+    }
+
+    /// Render every metric in the Prometheus text format
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        counter(&mut out, "blocks_mined_total", "Blocks mined by this node", &self.blocks_mined);
+        counter(&mut out, "blocks_received_total", "Blocks received from peers", &self.blocks_received);
+        counter(&mut out, "orphan_blocks_total", "Blocks received before their parent", &self.orphan_blocks);
+        gauge(&mut out, "orphan_pool_size", "Blocks waiting for their parent", &self.orphan_pool_size);
+        counter(&mut out, "reorgs_total", "Switches of the longest chain to another branch", &self.reorgs);
+        histogram(&mut out, "reorg_depth", "Blocks disconnected by a reorg", &self.reorg_depth);
+        counter(
+            &mut out,
+            "fork_choice_disagreements_total",
+            "Inserted blocks after which GHOST and longest chain pick different tips",
+            &self.fork_choice_disagreements,
+        );
+        histogram(
+            &mut out,
+            "block_propagation_delay_ms",
+            "Time from a block's timestamp to its arrival, in milliseconds",
+            &self.block_propagation_delay_ms,
+        );
+        histogram(&mut out, "block_size_bytes", "Serialized size of received blocks", &self.block_size_bytes);
+        gauge(&mut out, "mempool_size", "Transactions in the mempool", &self.mempool_size);
+        counter(
+            &mut out,
+            "mempool_conflicts_total",
+            "Transactions refused for spending the same sender nonce as one in the mempool",
+            &self.mempool_conflicts,
+        );
+        counter(&mut out, "signatures_verified_total", "Transaction signatures checked", &self.signatures_verified);
+        counter(
+            &mut out,
+            "signature_cache_hits_total",
+            "Transaction signatures skipped as already verified",
+            &self.signature_cache_hits,
+        );
+        gauge(&mut out, "peers", "Connected peers", &self.peers);
+        counter_vec(&mut out, "message_bytes_in_total", "Bytes received per message type", &self.message_bytes_in);
+        counter_vec(&mut out, "message_bytes_out_total", "Bytes sent per message type", &self.message_bytes_out);
+        counter(&mut out, "miner_hashes_total", "Nonces tried by the miner", &self.hashes);
+        gauge(&mut out, "miner_hash_rate", "Nonces tried per second by the miner", &self.hash_rate);
+        counter_vec(&mut out, "pool_shares_total", "Pool shares per outcome", &self.pool_shares);
+        out
+    }
+}
+
+fn counter(out: &mut String, name: &str, help: &str, c: &Counter) {
+    writeln!(out, "# HELP {} {}\n# TYPE {} counter\n{} {}", name, help, name, name, c.get()).unwrap();
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, g: &Gauge) {
+    writeln!(out, "# HELP {} {}\n# TYPE {} gauge\n{} {}", name, help, name, name, g.get()).unwrap();
+}
+
+fn counter_vec(out: &mut String, name: &str, help: &str, c: &CounterVec) {
+    writeln!(out, "# HELP {} {}\n# TYPE {} counter", name, help, name).unwrap();
+    for (label, v) in c.0.lock().unwrap().iter() {
+        writeln!(out, "{}{{type=\"{}\"}} {}", name, label, v).unwrap();
+    }
+}
+
+fn histogram(out: &mut String, name: &str, help: &str, h: &Histogram) {
+    writeln!(out, "# HELP {} {}\n# TYPE {} histogram", name, help, name).unwrap();
+    let data = h.inner.lock().unwrap();
+    for (bound, count) in h.bounds.iter().zip(data.buckets.iter()) {
+        writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, count).unwrap();
+    }
+    writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, data.count).unwrap();
+    writeln!(out, "{}_sum {}\n{}_count {}", name, data.sum, name, data.count).unwrap();
+}
+
+/// The metrics of this process
+pub fn global() -> &'static Metrics {
+    static METRICS: OnceLock<Metrics> = OnceLock::new();
+    METRICS.get_or_init(Metrics::new)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histogram_buckets_are_cumulative() {
+        let h = Histogram::new(&[1.0, 10.0]);
+        h.observe(0.5);
+        h.observe(5.0);
+        h.observe(50.0);
+        let mut out = String::new();
+        histogram(&mut out, "x", "test", &h);
+        assert!(out.contains("x_bucket{le=\"1\"} 1\n"));
+        assert!(out.contains("x_bucket{le=\"10\"} 2\n"));
+        assert!(out.contains("x_bucket{le=\"+Inf\"} 3\n"));
+        assert!(out.contains("x_sum 55.5\nx_count 3\n"));
+    }
+
+    #[test]
+    fn render_labels() {
+        let metrics = Metrics::new();
+        metrics.message_bytes_in.add("blocks", 10);
+        metrics.message_bytes_in.add("blocks", 5);
+        assert!(metrics.render().contains("message_bytes_in_total{type=\"blocks\"} 15\n"));
+    }
+}
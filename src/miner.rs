@@ -1,26 +1,50 @@
 use crate::network::server::Handle as ServerHandle;
-use log::info;
+use log::{debug, info, warn};
 use crate::blockchain::{Blockchain, State};
-use crate::block::{Block, Content, Header};
-use crate::crypto::merkle::{MerkleTree};
-use crate::transaction::{Transaction, SignedTransaction};
+use crate::block::{Block, Content, Header, transactions_root};
+use crate::transaction::SignedTransaction;
 use crate::crypto::hash::{H256, H160, Hashable};
 use crate::network::message::Message;
-use crossbeam::channel::{unbounded, Receiver, Sender, TryRecvError};
+use crate::events::EventBus;
+use crate::validation::timestamp_bounds;
+use crate::pow::ProofOfWork;
+use crate::consensus::pos;
+use crate::uncles;
+use crate::mempool::{self, Mempool};
+use crate::validation;
+use crate::wallet::Wallet;
+use crossbeam::channel::{unbounded, Receiver, Sender};
+use crossbeam::select;
 use std::time;
+use std::ops::Range;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread;
-extern crate rand;
-use rand::Rng;
-use crate::crypto::key_pair;
-use crate::transaction::sign;
-use ring::signature::{Ed25519KeyPair, Signature, KeyPair, VerificationAlgorithm, EdDSAParameters};
-use std::collections::{HashMap, VecDeque};
+use std::collections::{HashSet, VecDeque};
+use crate::metrics;
+use crate::node::Shared;
+use crate::shutdown::Stopped;
+use serde::Serialize;
 
 
 
+/// Upper bound on the number of transactions per block, unless changed with `Handle::set_max_txs`
+pub const DEFAULT_MAX_TXS: usize = 1;
+
+/// How often the block template is checked against the mempool when nothing else happens
+const TEMPLATE_REFRESH: time::Duration = time::Duration::from_millis(100);
+
+/// Nonces a worker tries before adding them to the shared hash count
+const HASH_BATCH: u64 = 1024;
+
 enum ControlSignal {
     Start(u64), // the number controls the lambda of interval between block generation
+    Pause,
+    Resume,
+    SetLambda(u64),
+    SetAddress(H160),
+    SetMaxTxs(usize),
+    SetThreads(usize),
     Exit,
 }
 
@@ -30,52 +54,377 @@ enum OperatingState {
     ShutDown,
 }
 
+/// Number of one-second samples the reported hash rate is averaged over
+const HASH_RATE_WINDOW: usize = 10;
+
+/// Snapshot of the miner's activity, as returned by `Handle::status`
+#[derive(Serialize, Debug, Clone)]
+pub struct Status {
+    /// One of "paused", "running" or "shutdown"
+    pub state: &'static str,
+    pub lambda: Option<u64>,
+    /// Address recorded as the miner of found blocks
+    pub address: String,
+    pub max_txs: usize,
+    pub threads: usize,
+    /// Nonces tried since the miner started
+    pub hashes: u64,
+    /// Nonces tried per second, averaged over the last `HASH_RATE_WINDOW` seconds
+    pub hash_rate: f64,
+    pub blocks_found: u64,
+    /// When the last block was found, in milliseconds since the UNIX epoch
+    pub last_block_time: Option<u128>,
+}
+
+/// Samples of the total hash count, for a rolling hash rate
+struct RateWindow {
+    samples: VecDeque<(time::Instant, u64)>,
+}
+
+impl RateWindow {
+    fn new() -> Self {
+        Self { samples: VecDeque::new() }
+    }
+
+    /// Record the hash count at `now` and return the rate over the window
+    fn sample(&mut self, now: time::Instant, hashes: u64) -> f64 {
+        self.samples.push_back((now, hashes));
+        if self.samples.len() > HASH_RATE_WINDOW + 1 {
+            self.samples.pop_front();
+        }
+        let (start, start_hashes) = self.samples[0];
+        let elapsed = now.duration_since(start).as_secs_f64();
+        if elapsed == 0.0 {
+            return 0.0;
+        }
+        (hashes - start_hashes) as f64 / elapsed
+    }
+
+    fn reset(&mut self) {
+        self.samples.clear();
+    }
+}
+
 pub struct Context {
     /// Channel for receiving control signal
     control_chan: Receiver<ControlSignal>,
     operating_state: OperatingState,
+    /// Lambda to resume with after a pause
+    lambda: Option<u64>,
+    address: H160,
+    max_txs: usize,
+    threads: usize,
+    /// Sleep between nonces in microseconds, shared with the workers
+    delay: Arc<AtomicU64>,
+    /// Bumped to make the workers of the current job stop
+    generation: Arc<AtomicU64>,
+    /// Nonces tried by all workers
+    hashes: Arc<AtomicU64>,
+    job: Option<Job>,
+    /// Job channels of the running workers, which exit once theirs is dropped
+    workers: Vec<Sender<Work>>,
+    solution_chan: (Sender<Block>, Receiver<Block>),
+    /// Signalled when a new tip arrives, after the current job has been stopped
+    tip_chan: Receiver<()>,
+    rate_window: RateWindow,
+    last_sample: time::Instant,
+    reported_hashes: u64,
+    status: Arc<Mutex<Status>>,
     server: ServerHandle,
     blockchain : Arc<Mutex<Blockchain>>,
-    mempool : Arc<Mutex<HashMap<H256, SignedTransaction>>>,
+    mempool : Arc<Mutex<Mempool>>,
     states : Arc<Mutex<State>>,
     txs: Arc<Mutex<VecDeque<SignedTransaction>>>,
+    /// Holds the key that seals blocks under slot-based consensus
+    wallet: Arc<Mutex<Wallet>>,
+    /// The last slot this node checked its eligibility for
+    last_slot: Option<u64>,
+}
+
+/// The block template the workers are currently mining on
+struct Job {
+    generation: u64,
+    parent: H256,
+    tx_hashes: Vec<H256>,
 }
 
 #[derive(Clone)]
 pub struct Handle {
     /// Channel for sending signal to the miner thread
     control_chan: Sender<ControlSignal>,
+    status: Arc<Mutex<Status>>,
 }
 
-pub fn new(server: &ServerHandle, blockchain: &Arc<Mutex<Blockchain>>, mempool: &Arc<Mutex<HashMap<H256, SignedTransaction>>>, states: &Arc<Mutex<State>>, txs: &Arc<Mutex<VecDeque<SignedTransaction>>>) -> (Context, Handle) {
+pub fn new(node: &Shared, address: &H160, threads: usize) -> (Context, Handle) {
     let (signal_chan_sender, signal_chan_receiver) = unbounded();
+    let generation = Arc::new(AtomicU64::new(0));
+    let tip_chan = watch_tip(&node.events, &generation);
+    let status = Arc::new(Mutex::new(Status {
+        state: "paused",
+        lambda: None,
+        address: address.to_string(),
+        max_txs: DEFAULT_MAX_TXS,
+        threads,
+        hashes: 0,
+        hash_rate: 0.0,
+        blocks_found: 0,
+        last_block_time: None,
+    }));
 
     let ctx = Context {
         control_chan: signal_chan_receiver,
         operating_state: OperatingState::Paused,
-        server: server.clone(),
-        blockchain: Arc::clone(blockchain),
-        mempool: Arc::clone(mempool),
-        states: Arc::clone(states),
-        txs: Arc::clone(txs),
+        lambda: None,
+        address: *address,
+        max_txs: DEFAULT_MAX_TXS,
+        threads,
+        delay: Arc::new(AtomicU64::new(0)),
+        generation,
+        hashes: Arc::new(AtomicU64::new(0)),
+        job: None,
+        workers: Vec::new(),
+        solution_chan: unbounded(),
+        tip_chan,
+        rate_window: RateWindow::new(),
+        last_sample: time::Instant::now(),
+        reported_hashes: 0,
+        status: Arc::clone(&status),
+        server: node.server.clone(),
+        blockchain: Arc::clone(&node.blockchain),
+        mempool: Arc::clone(&node.mempool),
+        states: Arc::clone(&node.states),
+        txs: Arc::clone(&node.txs),
+        wallet: Arc::clone(&node.wallet),
+        last_slot: None,
     };
 
     let handle = Handle {
         control_chan: signal_chan_sender,
+        status,
     };
 
     (ctx, handle)
 }
 
+/// Stop the current job as soon as a new tip is published, without waiting for the miner thread
+/// to notice. The returned channel is signalled afterwards so that a new template gets built.
+fn watch_tip(events: &EventBus, generation: &Arc<AtomicU64>) -> Receiver<()> {
+    let (sender, receiver) = unbounded();
+    let events = events.clone();
+    let generation = Arc::clone(generation);
+    thread::Builder::new()
+        .name("miner-tip-watcher".to_string())
+        .spawn(move || {
+            let kinds = vec!["new_tip".to_string()];
+            let mut since = 0;
+            loop {
+                let poll = events.poll(since, &kinds, time::Duration::from_secs(60));
+                since = poll.next;
+                if poll.events.is_empty() {
+                    continue;
+                }
+                generation.fetch_add(1, Ordering::SeqCst);
+                if sender.send(()).is_err() {
+                    return;
+                }
+            }
+        })
+        .unwrap();
+    receiver
+}
+
+/// Add a block that passed validation to the chain and announce it to peers. The global state
+/// follows the tip, and the transactions of the block leave the mempool.
+pub fn accept_block(
+    new_block: &Block,
+    blockchain: &Arc<Mutex<Blockchain>>,
+    mempool: &Arc<Mutex<Mempool>>,
+    states: &Arc<Mutex<State>>,
+    txs: &Arc<Mutex<VecDeque<SignedTransaction>>>,
+    server: &ServerHandle,
+) {
+    let height = {
+        let mut blockchain = blockchain.lock().unwrap();
+        blockchain.insert(new_block);
+        *states.lock().unwrap() = blockchain.tip_state();
+        blockchain.height(&blockchain.tip()).unwrap_or(0)
+    };
+    {
+        let included: HashSet<H256> = new_block.content.data.iter().map(|tx| tx.hash()).collect();
+        let mut mempool = mempool.lock().unwrap();
+        let mut txs = txs.lock().unwrap();
+        mempool.retain(|hash, _| !included.contains(hash));
+        txs.retain(|tx| !included.contains(&tx.hash()));
+        mempool::evict_expired(&mut mempool, &mut txs, height + 1);
+    }
+
+    server.broadcast(Message::NewBlockHashes(vec![new_block.hash()]));
+}
+
+/// Up to `max_txs` transactions from the front of `txs` that a child of the tip may include,
+/// skipping those for another chain or expired
+fn includable(blockchain: &Blockchain, txs: &VecDeque<SignedTransaction>, max_txs: usize) -> Vec<SignedTransaction> {
+    let (chain_id, height) = (blockchain.chain_id(), blockchain.height(&blockchain.tip()).unwrap_or(0) + 1);
+    txs.iter()
+        .filter(|tx| validation::check_transaction(&tx.Transaction, chain_id, height).is_ok())
+        .take(max_txs)
+        .cloned()
+        .collect()
+}
+
+/// A block on the tip of `blockchain` paying `miner`, with up to `max_txs` includable
+/// transactions from the front of `txs` and a zero nonce, or `None` if there is nothing to include
+pub fn block_template(
+    blockchain: &Blockchain,
+    txs: &VecDeque<SignedTransaction>,
+    max_txs: usize,
+    miner: &H160,
+) -> Option<Block> {
+    let data = includable(blockchain, txs, max_txs);
+    if data.is_empty() {
+        return None;
+    }
+    let parent = blockchain.tip();
+    let parent_header = &blockchain.hash_blocks[&parent].header;
+    let (min_timestamp, _) = timestamp_bounds(parent_header);
+    let uncles = uncles::select(blockchain, &parent);
+    let header = Header {
+        parent,
+        nonce: 0,
+        extra_nonce: 0,
+        difficulty: parent_header.difficulty,
+        timestamp: now_millis().max(min_timestamp),
+        merkle_root: transactions_root(&data),
+        uncles_root: uncles::uncles_root(&uncles),
+        miner: *miner,
+        seal: Vec::new(),
+    };
+    Some(Block { header, content: Content { data, uncles } })
+}
+
+/// The share of the 32-bit nonce space that worker `worker` out of `workers` searches
+fn nonce_range(worker: usize, workers: usize) -> Range<u64> {
+    let space = u32::MAX as u64 + 1;
+    let span = space / workers as u64;
+    let start = worker as u64 * span;
+    let end = if worker + 1 == workers { space } else { start + span };
+    start..end
+}
+
+fn now_millis() -> u128 {
+    match time::SystemTime::now().duration_since(time::UNIX_EPOCH) {
+        Ok(n) => n.as_millis(),
+        Err(_) => panic!("SystemTime before UNIX EPOCH!"),
+    }
+}
+
+/// A template handed to the workers, valid until `generation` moves past `job`
+struct Work {
+    header: Header,
+    content: Arc<Content>,
+    job: u64,
+}
+
+/// A mining thread, which searches its share of the nonce space of each job it is sent
+struct Worker {
+    work: Receiver<Work>,
+    range: Range<u64>,
+    pow: Arc<dyn ProofOfWork>,
+    generation: Arc<AtomicU64>,
+    hashes: Arc<AtomicU64>,
+    delay: Arc<AtomicU64>,
+    solution: Sender<Block>,
+}
+
+impl Worker {
+    /// Search jobs until the miner drops the sending side
+    fn run(self) {
+        while let Ok(work) = self.work.recv() {
+            // only the newest job is worth searching, the others have been stopped
+            let work = self.work.try_iter().last().unwrap_or(work);
+            self.search(work);
+        }
+    }
+
+    /// Search the template for a nonce until a solution is found or the job is stopped. When
+    /// the nonce range runs out, the extra nonce and timestamp are rolled and the range is
+    /// searched again.
+    fn search(&self, work: Work) {
+        let Work { mut header, content, job } = work;
+        let mut tried = 0;
+        loop {
+            for nonce in self.range.clone() {
+                if self.generation.load(Ordering::Relaxed) != job {
+                    self.hashes.fetch_add(tried, Ordering::Relaxed);
+                    return;
+                }
+                header.nonce = nonce as u32;
+                tried += 1;
+                if tried == HASH_BATCH {
+                    self.hashes.fetch_add(tried, Ordering::Relaxed);
+                    tried = 0;
+                }
+                if self.pow.meets_target(&header, &header.difficulty) {
+                    self.hashes.fetch_add(tried, Ordering::Relaxed);
+                    // only the first worker to solve the job reports it
+                    if self.generation.compare_exchange(job, job + 1, Ordering::SeqCst, Ordering::SeqCst).is_ok() {
+                        let _ = self.solution.send(Block { header, content: (*content).clone() });
+                    }
+                    return;
+                }
+                let delay = self.delay.load(Ordering::Relaxed);
+                if delay != 0 {
+                    thread::sleep(time::Duration::from_micros(delay));
+                }
+            }
+            header.extra_nonce = header.extra_nonce.wrapping_add(1);
+            // never below the template's timestamp, which respects the parent's
+            header.timestamp = now_millis().max(header.timestamp);
+        }
+    }
+}
+
 impl Handle {
-    pub fn exit(&self) {
-        self.control_chan.send(ControlSignal::Exit).unwrap();
+    fn send(&self, signal: ControlSignal) -> Result<(), Stopped> {
+        self.control_chan.send(signal).map_err(|_| Stopped("miner"))
     }
 
-    pub fn start(&self, lambda: u64) {
-        self.control_chan
-            .send(ControlSignal::Start(lambda))
-            .unwrap();
+    pub fn exit(&self) -> Result<(), Stopped> {
+        self.send(ControlSignal::Exit)
+    }
+
+    pub fn start(&self, lambda: u64) -> Result<(), Stopped> {
+        self.send(ControlSignal::Start(lambda))
+    }
+
+    /// Stop mining but keep the miner thread, so it can be resumed
+    pub fn pause(&self) -> Result<(), Stopped> {
+        self.send(ControlSignal::Pause)
+    }
+
+    /// Continue mining with the lambda used before the pause
+    pub fn resume(&self) -> Result<(), Stopped> {
+        self.send(ControlSignal::Resume)
+    }
+
+    pub fn set_lambda(&self, lambda: u64) -> Result<(), Stopped> {
+        self.send(ControlSignal::SetLambda(lambda))
+    }
+
+    pub fn set_address(&self, address: H160) -> Result<(), Stopped> {
+        self.send(ControlSignal::SetAddress(address))
+    }
+
+    pub fn set_max_txs(&self, max_txs: usize) -> Result<(), Stopped> {
+        self.send(ControlSignal::SetMaxTxs(max_txs))
+    }
+
+    pub fn set_threads(&self, threads: usize) -> Result<(), Stopped> {
+        self.send(ControlSignal::SetThreads(threads))
+    }
+
+    pub fn status(&self) -> Status {
+        self.status.lock().unwrap().clone()
     }
 
 }
@@ -96,146 +445,294 @@ impl Context {
             ControlSignal::Exit => {
                 info!("Miner shutting down");
                 self.operating_state = OperatingState::ShutDown;
+                let mut status = self.status.lock().unwrap();
+                status.state = "shutdown";
+                status.hash_rate = 0.0;
             }
             ControlSignal::Start(i) => {
                 info!("Miner starting in continuous mode with lambda {}", i);
                 self.operating_state = OperatingState::Run(i);
+                self.lambda = Some(i);
+                let mut status = self.status.lock().unwrap();
+                status.state = "running";
+                status.lambda = Some(i);
+            }
+            ControlSignal::Pause => {
+                if let OperatingState::Run(_) = self.operating_state {
+                    info!("Miner paused");
+                    self.operating_state = OperatingState::Paused;
+                    self.rate_window.reset();
+                    let mut status = self.status.lock().unwrap();
+                    status.state = "paused";
+                    status.hash_rate = 0.0;
+                }
+            }
+            ControlSignal::Resume => match (&self.operating_state, self.lambda) {
+                (OperatingState::Paused, Some(i)) => {
+                    info!("Miner resuming with lambda {}", i);
+                    self.operating_state = OperatingState::Run(i);
+                    self.status.lock().unwrap().state = "running";
+                }
+                (OperatingState::Paused, None) => {
+                    info!("Miner was never started, ignoring resume");
+                }
+                _ => {}
+            },
+            ControlSignal::SetLambda(i) => {
+                info!("Miner lambda set to {}", i);
+                self.lambda = Some(i);
+                if let OperatingState::Run(_) = self.operating_state {
+                    self.operating_state = OperatingState::Run(i);
+                }
+                self.status.lock().unwrap().lambda = Some(i);
+            }
+            ControlSignal::SetAddress(address) => {
+                info!("Miner address set to {}", address);
+                self.address = address;
+                self.stop_job();
+                self.status.lock().unwrap().address = address.to_string();
             }
+            ControlSignal::SetMaxTxs(max_txs) => {
+                info!("Miner max transactions per block set to {}", max_txs);
+                self.max_txs = max_txs;
+                self.stop_job();
+                self.status.lock().unwrap().max_txs = max_txs;
+            }
+            ControlSignal::SetThreads(threads) => {
+                let threads = threads.max(1);
+                info!("Miner threads set to {}", threads);
+                self.threads = threads;
+                self.stop_job();
+                self.workers.clear();
+                self.status.lock().unwrap().threads = threads;
+            }
+        }
+    }
+
+    /// Make the workers of the current job stop
+    fn stop_job(&mut self) {
+        if self.job.take().is_some() {
+            self.generation.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    /// Spawn `threads` workers, each searching its own share of the nonce space
+    fn spawn_workers(&mut self) {
+        let pow = self.blockchain.lock().unwrap().pow.clone();
+        for worker in 0..self.threads {
+            let (sender, receiver) = unbounded();
+            let worker_thread = Worker {
+                work: receiver,
+                range: nonce_range(worker, self.threads),
+                pow: Arc::clone(&pow),
+                generation: Arc::clone(&self.generation),
+                hashes: Arc::clone(&self.hashes),
+                delay: Arc::clone(&self.delay),
+                solution: self.solution_chan.0.clone(),
+            };
+            thread::Builder::new()
+                .name(format!("miner-worker-{}", worker))
+                .spawn(move || worker_thread.run())
+                .unwrap();
+            self.workers.push(sender);
+        }
+    }
+
+    /// Send the workers a fresh template unless the current job is still on the tip, has the
+    /// transactions that would be picked now, and hasn't been stopped
+    fn ensure_job(&mut self) {
+        let template = {
+            let blockchain = self.blockchain.lock().unwrap();
+            block_template(&blockchain, &self.txs.lock().unwrap(), self.max_txs, &self.address)
+        };
+        let template = match template {
+            Some(block) => block,
+            None => {
+                self.stop_job();
+                return;
+            }
+        };
+        let parent = template.header.parent;
+        let tx_hashes: Vec<H256> = template.content.data.iter().map(|tx| tx.hash()).collect();
+        if let Some(job) = &self.job {
+            if job.generation == self.generation.load(Ordering::SeqCst) && job.parent == parent && job.tx_hashes == tx_hashes {
+                return;
+            }
+        }
+        self.stop_job();
+        if self.workers.is_empty() {
+            self.spawn_workers();
+        }
+
+        let generation = self.generation.fetch_add(1, Ordering::SeqCst) + 1;
+        let content = Arc::new(template.content);
+        for worker in self.workers.iter() {
+            let work = Work { header: template.header.clone(), content: Arc::clone(&content), job: generation };
+            // a worker only goes away when its channel is dropped
+            worker.send(work).unwrap();
+        }
+        self.job = Some(Job { generation, parent, tx_hashes });
+    }
+
+    /// Under slot-based consensus, produce a block once per slot if this node's address leads it
+    fn try_produce(&mut self, slot_ms: u64) {
+        let now = now_millis();
+        let slot = (now / slot_ms as u128) as u64;
+        if self.last_slot == Some(slot) {
+            return;
+        }
+        self.last_slot = Some(slot);
+        let (template, parent_header, state, consensus) = {
+            let blockchain = self.blockchain.lock().unwrap();
+            let txs = self.txs.lock().unwrap();
+            let template = block_template(&blockchain, &txs, self.max_txs, &self.address);
+            let parent_header = blockchain.hash_blocks[&blockchain.tip()].header.clone();
+            // stake as of the parent, as peers will check it
+            let state = blockchain.tip_state();
+            (template, parent_header, state, blockchain.consensus.clone())
+        };
+        let mut block = match template {
+            Some(block) => block,
+            None => return,
+        };
+        if !consensus.is_eligible(&block.header.parent, &self.address, block.header.timestamp, &state) {
+            return;
+        }
+        {
+            let wallet = self.wallet.lock().unwrap();
+            let key = match wallet.key_pair(&self.address) {
+                Some(key) => key,
+                None => {
+                    info!("Miner leads slot {} but the wallet holds no key for {}", slot, self.address);
+                    return;
+                }
+            };
+            pos::seal(&mut block.header, key);
+        }
+        if let Err(e) = consensus.verify_header(&block.header, &parent_header, &state) {
+            info!("Miner skipping slot {}: {}", slot, e);
+            return;
+        }
+        self.process_block(block);
+    }
+
+    fn process_block(&mut self, new_block: Block) {
+        if let Err(e) = validation::validate_block(&new_block, &self.blockchain.lock().unwrap()) {
+            warn!("Mined block {} rejected: {}", new_block.hash(), e);
+            return;
+        }
+        metrics::global().blocks_mined.inc();
+        let blocks_found = {
+            let mut status = self.status.lock().unwrap();
+            status.blocks_found += 1;
+            status.last_block_time = Some(new_block.header.timestamp);
+            status.blocks_found
+        };
+        debug!("The current number of blocks mined: {} blocks", blocks_found);
+        accept_block(&new_block, &self.blockchain, &self.mempool, &self.states, &self.txs, &self.server);
+    }
+
+    /// Publish the hash count and, once a second, the hash rate
+    fn update_status(&mut self) {
+        let hashes = self.hashes.load(Ordering::Relaxed);
+        metrics::global().hashes.add(hashes - self.reported_hashes);
+        self.reported_hashes = hashes;
+        let mut status = self.status.lock().unwrap();
+        status.hashes = hashes;
+        let now = time::Instant::now();
+        if now.duration_since(self.last_sample) >= time::Duration::from_secs(1) {
+            status.hash_rate = self.rate_window.sample(now, hashes);
+            metrics::global().hash_rate.set(status.hash_rate);
+            self.last_sample = now;
         }
     }
 
     fn miner_loop(&mut self) {
         // main mining loop
         info!("In miner_loop and start to mine blocks...");
-        let mut block_counter = 0;
         loop {
             // check and react to control signals
             match self.operating_state {
                 OperatingState::Paused => {
+                    self.stop_job();
+                    self.update_status();
                     let signal = self.control_chan.recv().unwrap();
                     self.handle_control_signal(signal);
                     continue;
                 }
                 OperatingState::ShutDown => {
+                    self.stop_job();
+                    self.workers.clear();
                     return;
                 }
-                _ => match self.control_chan.try_recv() {
-                    Ok(signal) => {
-                        self.handle_control_signal(signal);
-                    }
-                    Err(TryRecvError::Empty) => {}
-                    Err(TryRecvError::Disconnected) => panic!("Miner control channel detached"),
-                },
-            }
-            if let OperatingState::ShutDown = self.operating_state {
-                return;
+                OperatingState::Run(i) => self.delay.store(i, Ordering::Relaxed),
             }
 
-            // TODO: actual mining
-            let parent = self.blockchain.lock().unwrap().tip();
-            let timestamp;
-            match time::SystemTime::now().duration_since(time::UNIX_EPOCH) 
-            {
-                Ok(n) => timestamp = n.as_millis(),
-                Err(_) => panic!("SystemTime before UNIX EPOCH!"),
-            }
-            let difficulty = self.blockchain.lock().unwrap().hash_blocks[&parent].header.difficulty;
-
-            let mut rng = rand::thread_rng();
-            let nonce : u32 = rng.gen();
-            let random_recipient: Vec<u8> = (0..20).map(|_| 1).collect();
-            let mut raw_bytes_recipient = [0; 20];
-            raw_bytes_recipient.copy_from_slice(&random_recipient);
-            let Recipient : H160 =(&raw_bytes_recipient).into();
-            let val : u32 = rng.gen();
-            let mut accountNonce : u16 = 0;
-
-            //sign the transaction and store it in SignedTransactions vector
-            let transaction = Transaction{recipAddress : Recipient, val : val, accountNonce : accountNonce};
-            let key = key_pair::random();
-            let signature = sign(&transaction, &key);
-            let hash_key : H256 = ring::digest::digest(&ring::digest::SHA256, key.public_key().as_ref()).into();
-            let hash_key_20bytes : H160 = hash_key.as_ref()[12..=31].into();
-            let signed_transaction = SignedTransaction{Transaction: transaction, Signature : signature.as_ref().to_vec(), public_key : key.public_key().as_ref().to_vec(), sender_addr : hash_key_20bytes};
-            
-            //in each block trial, we should remove them if we successfully mine the block
-            let mut SignedTransactions: Vec<SignedTransaction> = Vec::new();
-            let txs_perBlock = 1;
-            /*let mut counter = 0;
-            for (key, val) in self.mempool.lock().unwrap().iter() {
-                if (counter == txs_perBlock) {
-                    break;
-                }
-                SignedTransactions.push(val.clone());
-                counter += 1;
-            }*/
-            if (self.txs.lock().unwrap().is_empty()) {
-                println!("txs is empty");
-                continue;
-            }
-            match self.txs.lock().unwrap().front() {
-                Some(tx) => SignedTransactions.push(tx.clone()),
-                None => println!("Front have trouble"),
-            }
-            let merkle_tree = MerkleTree::new(&SignedTransactions);
-            let root = merkle_tree.root();
-     
-            //info!("Insert transactions into the block...");
-            let header = Header{parent : parent, nonce : nonce, difficulty : difficulty, timestamp : 0, merkle_root : root};
-            let content = Content{data : SignedTransactions};
-            let new_block = Block{header : header, content : content};
-
-
-            if(new_block.hash() <= difficulty)
-            {
-                let size = new_block.content.data.len(); // remove corresponding transactions in the blocks
-                for i in (0..size) {
-                    if (self.mempool.lock().unwrap().contains_key(&new_block.content.data[i].hash())) {
-
-                        let sender_addr = new_block.content.data[i].sender_addr;
-                        let recver_addr = new_block.content.data[i].Transaction.recipAddress;
-                        let trans_money = new_block.content.data[i].Transaction.val;
-                        let nonce = new_block.content.data[i].Transaction.accountNonce; 
-                        
-                        //println!("{:?}", nonce);
-                        //println!("{:?}", self.states.lock().unwrap().accountMaping[&sender_addr].0);
-                        if (nonce != self.states.lock().unwrap().accountMaping[&sender_addr].0 + 1) {
-                            //println!("{:?}", "wrong order");
-                            continue;
-                        }
-
-                        self.mempool.lock().unwrap().remove(&new_block.content.data[i].hash());
-                        self.txs.lock().unwrap().pop_front();
-
-                        if (self.states.lock().unwrap().accountMaping[&sender_addr].1 >= trans_money) {
-                            if let Some(x) = self.states.lock().unwrap().accountMaping.get_mut(&sender_addr) {
-                                x.0 += 1;
-                                x.1 -= trans_money; 
-                            }
-                            if let Some(y) = self.states.lock().unwrap().accountMaping.get_mut(&recver_addr) {
-                                y.1 += trans_money; 
-                            }
-                        }
-                        info!("{:?}", self.states.lock().unwrap().accountMaping);
+            let slot_ms = self.blockchain.lock().unwrap().consensus.slot_duration();
+            let wait = match slot_ms {
+                None => {
+                    self.ensure_job();
+                    TEMPLATE_REFRESH
+                }
+                Some(slot_ms) => {
+                    self.try_produce(slot_ms);
+                    let until_next_slot = slot_ms - (now_millis() % slot_ms as u128) as u64;
+                    TEMPLATE_REFRESH.min(time::Duration::from_millis(until_next_slot))
+                }
+            };
+
+            let control_chan = self.control_chan.clone();
+            let solution_chan = self.solution_chan.1.clone();
+            let tip_chan = self.tip_chan.clone();
+            select! {
+                recv(control_chan) -> signal => match signal {
+                    Ok(signal) => self.handle_control_signal(signal),
+                    Err(_) => panic!("Miner control channel detached"),
+                },
+                recv(solution_chan) -> block => {
+                    if let Ok(block) = block {
+                        self.process_block(block);
                     }
-                    block_counter += 1;
-                    println!("The current number of blocks mined: {} blocks", block_counter);
-                    self.blockchain.lock().unwrap().insert(&new_block);
-                    //println!("{:?}", self.blockchain.lock().unwrap().next_len - 1);
-    
-                    let mut new_blockHash: Vec<H256> = Vec::new();
-                    new_blockHash.push(new_block.hash());
-                    //let longest_chain = self.blockchain.lock().unwrap().all_blocks_in_longest_chain();
-                    self.server.broadcast(Message::NewBlockHashes(new_blockHash));
                 }
+                // the job was already stopped by the watcher, `ensure_job` picks up the new tip
+                recv(tip_chan) -> _ => {}
+                default(wait) => {}
             }
+            self.update_status();
+        }
+    }
+}
 
-            if let OperatingState::Run(i) = self.operating_state {
-                if i != 0 {
-                    let interval = time::Duration::from_micros(i as u64);
-                    thread::sleep(interval);
-                }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nonce_ranges_cover_the_space() {
+        for workers in 1..=7 {
+            let mut next = 0;
+            for worker in 0..workers {
+                let range = nonce_range(worker, workers);
+                assert_eq!(range.start, next);
+                assert!(range.end > range.start);
+                next = range.end;
             }
+            assert_eq!(next, u32::MAX as u64 + 1);
+        }
+    }
+
+    #[test]
+    fn rate_window_rolls() {
+        let mut window = RateWindow::new();
+        let start = time::Instant::now();
+        assert_eq!(window.sample(start, 0), 0.0);
+        for i in 1..=HASH_RATE_WINDOW as u64 {
+            window.sample(start + time::Duration::from_secs(i), i * 100);
         }
+        let last = start + time::Duration::from_secs(HASH_RATE_WINDOW as u64 + 1);
+        // the first sample has dropped out, so the rate reflects only the latest window
+        assert_eq!(window.sample(last, (HASH_RATE_WINDOW as u64) * 100 + 1000), 190.0);
+        window.reset();
+        assert_eq!(window.sample(last, 0), 0.0);
     }
 }
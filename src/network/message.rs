@@ -5,6 +5,8 @@ use crate::transaction::{Transaction, SignedTransaction};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum Message {
+    /// First message on every connection, naming the genesis block of the sender's chain
+    Hello(H256),
     Ping(String),
     Pong(String),
     NewBlockHashes(Vec<H256>),
@@ -14,3 +16,20 @@ pub enum Message {
     GetTransactions(Vec<H256>),
     Transactions(Vec<SignedTransaction>),
 }
+
+impl Message {
+    /// Short name of the message type, used to label traffic metrics
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Message::Hello(_) => "hello",
+            Message::Ping(_) => "ping",
+            Message::Pong(_) => "pong",
+            Message::NewBlockHashes(_) => "new_block_hashes",
+            Message::GetBlocks(_) => "get_blocks",
+            Message::Blocks(_) => "blocks",
+            Message::NewTransactionHashes(_) => "new_transaction_hashes",
+            Message::GetTransactions(_) => "get_transactions",
+            Message::Transactions(_) => "transactions",
+        }
+    }
+}
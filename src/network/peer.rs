@@ -1,4 +1,5 @@
 use super::message;
+use crate::metrics;
 use log::{trace, warn};
 use mio;
 use mio_extras::channel;
@@ -192,6 +193,7 @@ pub fn new(
         writer: write_ctx,
         handle: handle.clone(),
         direction,
+        verified: false,
     };
     Ok((ctx, handle))
 }
@@ -209,6 +211,8 @@ pub struct Context {
     pub writer: WriteContext,
     pub handle: Handle,
     pub direction: Direction,
+    /// Whether the peer has shown it is on the same chain
+    pub verified: bool,
 }
 
 #[derive(Clone)]
@@ -221,6 +225,7 @@ impl Handle {
     pub fn write(&self, msg: message::Message) {
         // TODO: return result
         let buffer = bincode::serialize(&msg).unwrap();
+        metrics::global().message_bytes_out.add(msg.kind(), buffer.len() as u64);
         if self.write_queue.send(buffer).is_err() {
             warn!("Failed to send write request for peer {}, channel detached", self.addr);
         }
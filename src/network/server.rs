@@ -1,5 +1,8 @@
 use super::message;
 use super::peer::{self, ReadResult, WriteResult};
+use crate::crypto::hash::H256;
+use crate::events::{Event, EventBus};
+use crate::shutdown::Stopped;
 use crossbeam::channel as cbchannel;
 use log::{debug, error, info, trace, warn};
 use mio::{self, net};
@@ -7,12 +10,14 @@ use mio_extras::channel;
 use std::sync::mpsc;
 use std::thread;
 
-const MAX_INCOMING_CLIENT: usize = 256;
 const MAX_EVENT: usize = 1024;
 
 pub fn new(
     addr: std::net::SocketAddr,
     msg_sink: cbchannel::Sender<(Vec<u8>, peer::Handle)>,
+    genesis: H256,
+    max_peers: usize,
+    events: &EventBus,
 ) -> std::io::Result<(Context, Handle)> {
     let (control_signal_sender, control_signal_receiver) = channel::channel();
     let handle = Handle {
@@ -25,6 +30,10 @@ pub fn new(
         poll: mio::Poll::new()?,
         control_chan: control_signal_receiver,
         new_msg_chan: msg_sink,
+        genesis,
+        max_peers,
+        events: events.clone(),
+        stopping: false,
         _handle: handle.clone(),
     };
     Ok((ctx, handle))
@@ -37,6 +46,12 @@ pub struct Context {
     poll: mio::Poll,
     control_chan: channel::Receiver<ControlSignal>,
     new_msg_chan: cbchannel::Sender<(Vec<u8>, peer::Handle)>,
+    /// Genesis of our chain; peers must greet with the same one before their messages are passed on
+    genesis: H256,
+    max_peers: usize,
+    events: EventBus,
+    /// Set once a shutdown request is processed, ending the event loop
+    stopping: bool,
     _handle: Handle,
 }
 
@@ -60,7 +75,7 @@ impl Context {
         // get a new slot in the connection set
         let vacant = self.peers.vacant_entry();
         let key: usize = vacant.key();
-        if key >= MAX_INCOMING_CLIENT {
+        if key >= self.max_peers {
             // too many connections
             return Err(std::io::Error::new(
                 std::io::ErrorKind::Other,
@@ -80,6 +95,7 @@ impl Context {
             mio::PollOpt::edge(),
         )?;
         let (ctx, handle) = peer::new(stream, direction)?;
+        let addr = ctx.addr;
 
         // register the writer queue
         self.poll.register(
@@ -89,14 +105,26 @@ impl Context {
             mio::PollOpt::edge() | mio::PollOpt::oneshot(),
         )?;
 
+        // greet the peer, it will not listen until it knows we are on the same chain
+        handle.write(message::Message::Hello(self.genesis));
+
         // insert the context and return the handle
         vacant.insert(ctx);
         // record the key of this peer
         self.peer_list.push(key);
         trace!("Registering peer with event token={}", key);
+        self.events.publish(Event::PeerConnected { addr: addr.to_string() });
         Ok(handle)
     }
 
+    /// Drop a peer from the connection set
+    fn remove_peer(&mut self, peer_id: usize) {
+        let peer = self.peers.remove(peer_id);
+        let index = self.peer_list.iter().position(|&x| x == peer_id).unwrap();
+        self.peer_list.swap_remove(index);
+        self.events.publish(Event::PeerDisconnected { addr: peer.addr.to_string() });
+    }
+
     /// Connect to a peer, and register this peer
     fn connect(&mut self, addr: &std::net::SocketAddr) -> std::io::Result<peer::Handle> {
         // we need to estabilsh a stdlib tcp stream, since we need it to block
@@ -134,9 +162,32 @@ impl Context {
             ControlSignal::BroadcastMessage(msg) => {
                 trace!("Processing BroadcastMessage command");
                 for peer_id in &self.peer_list {
-                    self.peers[*peer_id].handle.write(msg.clone());
+                    if self.peers[*peer_id].verified {
+                        self.peers[*peer_id].handle.write(msg.clone());
+                    }
                 }
             }
+            ControlSignal::ListPeers(result_chan) => {
+                trace!("Processing ListPeers command");
+                let addrs = self.peer_list.iter().map(|id| self.peers[*id].addr).collect();
+                result_chan.send(addrs).unwrap();
+            }
+            ControlSignal::Shutdown(result_chan) => {
+                info!("P2P server shutting down, disconnecting {} peers", self.peer_list.len());
+                let mut outgoing = Vec::new();
+                for peer_id in self.peer_list.clone() {
+                    let peer = &self.peers[peer_id];
+                    if let peer::Direction::Outgoing = peer.direction {
+                        outgoing.push(peer.addr);
+                    }
+                    if let Err(e) = peer.stream.shutdown(std::net::Shutdown::Both) {
+                        debug!("Error closing connection to peer {}: {}", peer.addr, e);
+                    }
+                    self.remove_peer(peer_id);
+                }
+                self.stopping = true;
+                let _ = result_chan.send(outgoing);
+            }
         }
         Ok(())
     }
@@ -164,9 +215,7 @@ impl Context {
                 Ok(ReadResult::EOF) => {
                     // EOF, remove it from the connections set
                     info!("Peer {} dropped connection", peer.addr);
-                    self.peers.remove(peer_id);
-                    let index = self.peer_list.iter().position(|&x| x == peer_id).unwrap();
-                    self.peer_list.swap_remove(index);
+                    self.remove_peer(peer_id);
                     break;
                 }
                 Ok(ReadResult::Continue) => {
@@ -177,6 +226,21 @@ impl Context {
                 Ok(ReadResult::Message(m)) => {
                     trace!("Peer {} yield message", peer_id);
                     // we just received a full message
+                    if !peer.verified {
+                        // the first message must be a greeting from the same chain
+                        match bincode::deserialize::<message::Message>(&m) {
+                            Ok(message::Message::Hello(genesis)) if genesis == self.genesis => {
+                                debug!("Peer {} is on our chain", peer.addr);
+                                peer.verified = true;
+                                continue;
+                            }
+                            _ => {
+                                warn!("Peer {} is not on our chain, disconnecting", peer.addr);
+                                self.remove_peer(peer_id);
+                                break;
+                            }
+                        }
+                    }
                     self.new_msg_chan.send((m, peer.handle.clone())).unwrap();
                     continue;
                 }
@@ -187,9 +251,7 @@ impl Context {
                         break;
                     } else {
                         warn!("Error reading peer {}, disconnecting: {}", peer.addr, e);
-                        self.peers.remove(peer_id);
-                        let index = self.peer_list.iter().position(|&x| x == peer_id).unwrap();
-                        self.peer_list.swap_remove(index);
+                        self.remove_peer(peer_id);
                         break;
                     }
                 }
@@ -224,9 +286,7 @@ impl Context {
             Ok(WriteResult::EOF) => {
                 // EOF, remove it from the connections set
                 info!("Peer {} dropped connection", peer.addr);
-                self.peers.remove(peer_id);
-                let index = self.peer_list.iter().position(|&x| x == peer_id).unwrap();
-                self.peer_list.swap_remove(index);
+                self.remove_peer(peer_id);
             }
             Ok(WriteResult::ChanClosed) => {
                 // the channel is closed. no more writes.
@@ -246,9 +306,7 @@ impl Context {
                 // socket is not ready anymore, stop reading
                 } else {
                     warn!("Error writing peer {}, disconnecting: {}", peer.addr, e);
-                    self.peers.remove(peer_id);
-                    let index = self.peer_list.iter().position(|&x| x == peer_id).unwrap();
-                    self.peer_list.swap_remove(index);
+                    self.remove_peer(peer_id);
                 }
             }
         }
@@ -296,6 +354,10 @@ impl Context {
                             match self.control_chan.try_recv() {
                                 Ok(req) => {
                                     self.process_control(req).unwrap();
+                                    if self.stopping {
+                                        // dropping the context closes the channel to the workers
+                                        return Ok(());
+                                    }
                                 }
                                 Err(e) => match e {
                                     mpsc::TryRecvError::Empty => break,
@@ -376,22 +438,41 @@ impl Handle {
             addr,
             result_chan: sender,
         };
-        self.control_chan
-            .send(ControlSignal::ConnectNewPeer(request))
-            .unwrap();
-        receiver.recv().unwrap()
+        let stopped = || std::io::Error::new(std::io::ErrorKind::NotConnected, Stopped("P2P server"));
+        self.control_chan.send(ControlSignal::ConnectNewPeer(request)).map_err(|_| stopped())?;
+        receiver.recv().map_err(|_| stopped())?
     }
 
+    /// Send `msg` to every peer; dropped if the server has stopped
     pub fn broadcast(&self, msg: message::Message) {
-        self.control_chan
-            .send(ControlSignal::BroadcastMessage(msg))
-            .unwrap();
+        if self.control_chan.send(ControlSignal::BroadcastMessage(msg)).is_err() {
+            debug!("P2P server stopped, dropping broadcast");
+        }
+    }
+
+    /// Addresses of the currently connected peers
+    pub fn peers(&self) -> Result<Vec<std::net::SocketAddr>, Stopped> {
+        let (sender, receiver) = cbchannel::unbounded();
+        self.control_chan.send(ControlSignal::ListPeers(sender)).map_err(|_| Stopped("P2P server"))?;
+        receiver.recv().map_err(|_| Stopped("P2P server"))
+    }
+
+    /// Close every peer connection and stop the server, returning the addresses of the peers
+    /// we had connected to. Once the server has stopped the workers see their channel close.
+    pub fn shutdown(&self) -> Vec<std::net::SocketAddr> {
+        let (sender, receiver) = cbchannel::unbounded();
+        if self.control_chan.send(ControlSignal::Shutdown(sender)).is_err() {
+            return Vec::new();
+        }
+        receiver.recv().unwrap_or_default()
     }
 }
 
 enum ControlSignal {
     ConnectNewPeer(ConnectRequest),
     BroadcastMessage(message::Message),
+    ListPeers(cbchannel::Sender<Vec<std::net::SocketAddr>>),
+    Shutdown(cbchannel::Sender<Vec<std::net::SocketAddr>>),
 }
 
 struct ConnectRequest {
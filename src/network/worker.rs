@@ -12,7 +12,13 @@ use std::time;
 use serde::{Serialize,Deserialize};
 use std::collections::{HashMap, VecDeque};
 use crate::transaction::{Transaction, SignedTransaction};
-use crate::transaction::verify;
+use crate::verifier;
+use crate::validation;
+use crate::miner;
+use crate::mempool::{self, Admission, Mempool};
+use crate::events::EventBus;
+use crate::metrics;
+use crate::node::Shared;
 use log::{info};
 
 
@@ -26,61 +32,66 @@ pub struct Context {
     num_worker: usize,
     server: ServerHandle,
     blockchain : Arc<Mutex<Blockchain>>,
-    mempool : Arc<Mutex<HashMap<H256, SignedTransaction>>>,
+    mempool : Arc<Mutex<Mempool>>,
+    /// Transactions received once the mempool holds this many are dropped
+    max_mempool : usize,
     states : Arc<Mutex<State>>,
     txs : Arc<Mutex<VecDeque<SignedTransaction>>>,
+    events : EventBus,
 }
 
 pub fn new(
     num_worker: usize,
     msg_src: channel::Receiver<(Vec<u8>, peer::Handle)>,
-    server: &ServerHandle,
-    blockchain: &Arc<Mutex<Blockchain>>,
-    mempool: &Arc<Mutex<HashMap<H256, SignedTransaction>>>,
-    states: &Arc<Mutex<State>>,
-    txs: &Arc<Mutex<VecDeque<SignedTransaction>>>,
+    node: &Shared,
 ) -> Context {
     Context {
         msg_chan: msg_src,
         num_worker,
-        server: server.clone(),
-        blockchain: Arc::clone(blockchain),
-        mempool: Arc::clone(mempool),
-        states: Arc::clone(states),
-        txs: Arc::clone(txs),
+        server: node.server.clone(),
+        blockchain: Arc::clone(&node.blockchain),
+        mempool: Arc::clone(&node.mempool),
+        max_mempool: node.max_mempool,
+        states: Arc::clone(&node.states),
+        txs: Arc::clone(&node.txs),
+        events: node.events.clone(),
     }
 }
 
 impl Context {
-    pub fn start(self) {
+    /// Spawn the workers. They run until the server stops and its queued messages are handled.
+    pub fn start(self) -> Vec<thread::JoinHandle<()>> {
         let num_worker = self.num_worker;
+        let mut workers = Vec::new();
         for i in 0..num_worker {
             let cloned = self.clone();
-            thread::spawn(move || {
+            workers.push(thread::spawn(move || {
                 cloned.worker_loop();
                 warn!("Worker thread {} exited", i);
-            });
+            }));
         }
+        workers
     }
 
     fn worker_loop(&self) {
 
         let mut orphan_blocks: HashMap<H256, Block> = HashMap::new();
         let mut orphan_buffer = Orphan {orphan_blocks: orphan_blocks};
-        let mut counter = 0;
-        let mut sum = 0;
-        let mut mark = 0;
-        let mut start = 0;
         loop {
-            //println!("Total blockchain len: {}", self.blockchain.lock().unwrap().hash_blocks.len());
-            //println!("Orphan size: {}", orphan.len());
-            //println!("sum: {:?}", sum);
-            //println!("counter: {:?}", counter);
-
-            let msg = self.msg_chan.recv().unwrap();
+            let msg = match self.msg_chan.recv() {
+                Ok(msg) => msg,
+                // the server stopped and every queued message was handled
+                Err(_) => return,
+            };
             let (msg, peer) = msg;
+            let msg_len = msg.len();
             let msg: Message = bincode::deserialize(&msg).unwrap();
+            metrics::global().message_bytes_in.add(msg.kind(), msg_len as u64);
             match msg {
+                Message::Hello(genesis) => {
+                    // greetings are checked by the server, this is a repeated one
+                    debug!("Hello: {}", genesis);
+                }
                 Message::Ping(nonce) => {
                     debug!("Ping: {}", nonce);
                     peer.write(Message::Pong(nonce.to_string()));
@@ -101,9 +112,7 @@ impl Context {
                     }
                     let longest_chain = self.blockchain.lock().unwrap().all_blocks_in_longest_chain();
                     info!("{:?}", longest_chain);
-                    //println!("{:?}", longest_chain);
-                    println!("Total number of blocks in blockchain: {} blocks", self.blockchain.lock().unwrap().hash_blocks.len());
-                    println!("The number of orphan blocks: {} blocks", orphan_buffer.orphan_blocks.len());
+                    debug!("Total number of blocks in blockchain: {} blocks", self.blockchain.lock().unwrap().hash_blocks.len());
                 }
                 Message::GetBlocks(getblocks) => {
                     debug!("GetBlocks");
@@ -123,210 +132,62 @@ impl Context {
                         }
                     }
                     let longest_chain = self.blockchain.lock().unwrap().all_blocks_in_longest_chain();
-                    //println!("{:?}", longest_chain);
                     info!("{:?}", longest_chain);
-                    println!("Total number of blocks in blockchain: {} blocks", self.blockchain.lock().unwrap().hash_blocks.len());
-                    println!("The number of orphan blocks: {} blocks", orphan_buffer.orphan_blocks.len());
+                    debug!("Total number of blocks in blockchain: {} blocks", self.blockchain.lock().unwrap().hash_blocks.len());
                     peer.write(Message::Blocks(exist_blocks));
 
                 }
                 Message::Blocks(blocks) => {
                     debug!("Blocks");
                     info!("Receiving blocks mined by the other...");
-                    let size = blocks.len();
-                
-                    let mut orphan_size = orphan_buffer.orphan_blocks.len();
-                    for i in (0..size) {
-                        //check 
-                        let mut valid_block = true;
-                        let signedTxs_size = blocks[i].content.data.len();
-                        for j in (0..signedTxs_size) {
-                            //Transaction signature check
-                            let public_key_bytes : &[u8] = &blocks[i].content.data[j].public_key;
-                            let hash_key : H256 = ring::digest::digest(&ring::digest::SHA256, public_key_bytes).into();
-                            let hash_key_20bytes : H160 = hash_key.as_ref()[12..=31].into();
-
-                            if(!verify(&blocks[i].content.data[j].Transaction, &blocks[i].content.data[j].public_key, &blocks[i].content.data[j].Signature)){
-                                //we need to discard the block or not is not yet decided
-                                valid_block = false;
-                            }
-                            else if(hash_key_20bytes != blocks[i].content.data[j].sender_addr){
-                                valid_block = false;
-                            }
+                    for block in blocks.iter() {
+                        metrics::global().blocks_received.inc();
+                        if let Err(e) = validation::check_body(block) {
+                            debug!("Discarding block {}: {}", block.hash(), e);
+                            continue;
                         }
-
-                        if (!valid_block) {
+                        if self.blockchain.lock().unwrap().hash_blocks.contains_key(&block.hash()) {
                             continue;
                         }
-
-                        //transactions in the blocks are valid, we should remove them from mempool and update the states
-
-                        for i in (0..size) {
-                            let signedTxs_size = blocks[i].content.data.len();
-                            for j in (0..signedTxs_size) {
-                               if (self.mempool.lock().unwrap().contains_key(&blocks[i].content.data[j].hash())) {
-                            
-                                    let sender_addr = blocks[i].content.data[j].sender_addr;
-                                    let recver_addr = blocks[i].content.data[j].Transaction.recipAddress;
-                                    let trans_money = blocks[i].content.data[j].Transaction.val;
-                                    let accountNonce = blocks[i].content.data[j].Transaction.accountNonce;
-
-                                    //Transaction spending check
-                                    //println!("{:?}", accountNonce);
-                                    //println!("{:?}", self.states.lock().unwrap().accountMaping[&sender_addr].0);
-                                    if (accountNonce != self.states.lock().unwrap().accountMaping[&sender_addr].0 + 1) {
-                                        continue;
-                                    }
-
-
-                                    self.mempool.lock().unwrap().remove(&blocks[i].content.data[j].hash()); 
-
-                                    if (self.states.lock().unwrap().accountMaping[&sender_addr].1 >= trans_money) {
-                                        if let Some(x) = self.states.lock().unwrap().accountMaping.get_mut(&sender_addr) {
-                                            x.0 += 1;
-                                            x.1 -= trans_money; 
-                                        }
-                                        if let Some(y) = self.states.lock().unwrap().accountMaping.get_mut(&recver_addr) {
-                                            y.1 += trans_money; 
-                                        }
-
-                                    }
-                                    println!("{:?}", "new block txs");
-                                    info!("{:?}", self.states.lock().unwrap().accountMaping);
-
-                                } 
-                            }
+                        if !self.blockchain.lock().unwrap().hash_blocks.contains_key(&block.header.parent) {
+                            orphan_buffer.orphan_blocks.insert(block.header.parent, block.clone());
+                            metrics::global().orphan_blocks.inc();
+                            metrics::global().orphan_pool_size.set(orphan_buffer.orphan_blocks.len() as f64);
+                            peer.write(Message::GetBlocks(vec![block.header.parent]));
+                            continue;
                         }
-                        
-                        if (!self.blockchain.lock().unwrap().hash_blocks.contains_key(&blocks[i].hash())) {
-                            if (!self.blockchain.lock().unwrap().hash_blocks.contains_key(&blocks[i].header.parent)) {
-                                orphan_buffer.orphan_blocks.insert(blocks[i].header.parent, blocks[i].clone());
-                                println!("The number of orphan blocks is increased to {} blocks", orphan_buffer.orphan_blocks.len());
-                                let mut parent_hash: Vec<H256> = Vec::new();
-                                parent_hash.push(blocks[i].header.parent);
-                                peer.write(Message::GetBlocks(parent_hash.clone()));
-                            }
-                            else {
-                                let par = self.blockchain.lock().unwrap().tip();
-                                let diff = self.blockchain.lock().unwrap().hash_blocks[&par].header.difficulty;
-                                if (blocks[i].hash() <= diff) {
-
-                                    // get network delay
-                                    let mut timestamp = blocks[i].header.timestamp;
-                                    let mut cur_time;
-                                    match time::SystemTime::now().duration_since(time::UNIX_EPOCH) 
-                                    {
-                                        Ok(n) => cur_time = n.as_millis(),
-                                        Err(_) => panic!("SystemTime before UNIX EPOCH!"),
-                                    }
-                                    let mut delay = cur_time - timestamp;
-                                    println!("Network delay: {:?} ms", delay);
 
-                                    // get the average delay
-                                    sum += delay;
-                                    counter += 1;
-                                    let avg: f32 = (sum as f32)/(counter as f32);
-                                    println!("Average network delay: {:?} ms", avg);
+                        // add the block, then the orphan waiting for it, if any, and so on
+                        let mut next = Some(block.clone());
+                        while let Some(block) = next.take() {
+                            if let Err(e) = validation::validate_block(&block, &self.blockchain.lock().unwrap()) {
+                                debug!("Discarding block {}: {}", block.hash(), e);
+                                break;
+                            }
 
-                                    // get block size
-                                    let serialized: Vec<u8> = bincode::serialize(&blocks[i]).unwrap();
-                                    let block_size = serialized.len();
-                                    println!("Block size: {:?}", block_size);
+                            // get network delay
+                            let cur_time = time::SystemTime::now()
+                                .duration_since(time::UNIX_EPOCH)
+                                .expect("SystemTime before UNIX EPOCH!")
+                                .as_millis();
+                            let delay = cur_time.saturating_sub(block.header.timestamp);
+                            metrics::global().block_propagation_delay_ms.observe(delay as f64);
 
-                                    // get duration
-                                    // setting start as starting time
-                                    if mark == 0
-                                    {
-                                        start = cur_time;
-                                        mark = 1;
-                                    }
-                                    let time_diff = cur_time - start;
-                                    let dura = (time_diff as f32)/(1000 as f32);
-                                    println!("Time elapsed: {:?} seconds", dura.clone());
+                            // get block size
+                            let block_size = bincode::serialize(&block).unwrap().len();
+                            metrics::global().block_size_bytes.observe(block_size as f64);
 
-                                    //To check the block is on the longset chain, 
-                                    //1). If it is on the longest_chain, update the global state
-                                    //2). If is is the fork, use its parent block to calculate temp state and store in chainState
-                                    //3). If the block's parent is not the tip and after the insertion the block is the tip, that means new longest chain
-                                    let prev_tip = self.blockchain.lock().unwrap().tip();
-                                    self.blockchain.lock().unwrap().insert(&blocks[i]);
-                                    if (blocks[i].header.parent == prev_tip) { // condition 1.
-                                        self.blockchain.lock().unwrap().chainState.insert(blocks[i].hash(), self.states.lock().unwrap().clone());
-                                    }
-                                    else { //condition 2 & 3
-                                        let sender_addr = blocks[i].content.data[0].sender_addr;
-                                        let recver_addr = blocks[i].content.data[0].Transaction.recipAddress;
-                                        let trans_money = blocks[i].content.data[0].Transaction.val;
-                                        let accountNonce = blocks[i].content.data[0].Transaction.accountNonce;
+                            miner::accept_block(&block, &self.blockchain, &self.mempool, &self.states, &self.txs, &self.server);
 
-                                        if (self.blockchain.lock().unwrap().tip() != blocks[i].hash()) { // condition 2
-                                            let mut parent_state = self.blockchain.lock().unwrap().chainState[&blocks[i].header.parent].clone();
-                                            if (parent_state.accountMaping[&sender_addr].1 >= trans_money) {
-                                                if let Some(x) = parent_state.accountMaping.get_mut(&sender_addr) {
-                                                    x.0 += 1;
-                                                    x.1 -= trans_money; 
-                                                }
-                                                if let Some(y) = parent_state.accountMaping.get_mut(&recver_addr) {
-                                                    y.1 += trans_money; 
-                                                }
-                                            }
-                                            self.blockchain.lock().unwrap().chainState.insert(blocks[i].hash(), parent_state.clone());
-                                            self.states.lock().unwrap().accountMaping = self.blockchain.lock().unwrap().chainState[&prev_tip].accountMaping.clone();
-                                        }
-                                        
-                                        else { // condition 3
-                                            let mut parent_state = self.blockchain.lock().unwrap().chainState[&blocks[i].header.parent].clone();
-                                            if (parent_state.accountMaping[&sender_addr].1 >= trans_money) {
-                                                if let Some(x) = parent_state.accountMaping.get_mut(&sender_addr) {
-                                                    x.0 += 1;
-                                                    x.1 -= trans_money; 
-                                                }
-                                                if let Some(y) = parent_state.accountMaping.get_mut(&recver_addr) {
-                                                    y.1 += trans_money; 
-                                                }
-                                            }
-                                            self.blockchain.lock().unwrap().chainState.insert(blocks[i].hash(), parent_state.clone());
-                                            self.states.lock().unwrap().accountMaping = parent_state.accountMaping.clone();
-                                        }
-                                        
-                                    }
-                                    
-                                    //insert new block to blockchain, so we need to remove SignedTransaction inside this block
-                                    let size = blocks[i].content.data.len();
-                                    for j in (0..size) {
-                                       if (self.mempool.lock().unwrap().contains_key(&blocks[i].content.data[j].hash())) {
-                                            self.mempool.lock().unwrap().remove(&blocks[i].content.data[j].hash());
-                                        }
-                                    }
-                                    let mut new_blockHash: Vec<H256> = Vec::new();
-                                    new_blockHash.push(blocks[i].hash());
-                                    self.server.broadcast(Message::NewBlockHashes(new_blockHash));
-                                }
+                            next = orphan_buffer.orphan_blocks.remove(&block.hash());
+                            if next.is_some() {
+                                metrics::global().orphan_pool_size.set(orphan_buffer.orphan_blocks.len() as f64);
                             }
-
-                            if (orphan_buffer.orphan_blocks.contains_key(&blocks[0].hash())) {
-                                self.blockchain.lock().unwrap().insert(&orphan_buffer.orphan_blocks[&blocks[0].hash()]); 
-                                //remove corresponding txs in the inserted block from mempool
-                                let size = blocks[0].content.data.len();
-                                for j in (0..size) {
-                                    if (self.mempool.lock().unwrap().contains_key(&blocks[0].content.data[j].hash())) {
-                                        self.mempool.lock().unwrap().remove(&blocks[0].content.data[j].hash());
-                                    }
-                                }
-
-                                let mut new_blockHash_orphan: Vec<H256> = Vec::new();
-                                new_blockHash_orphan.push(orphan_buffer.orphan_blocks[&blocks[0].hash()].hash());
-                                self.server.broadcast(Message::NewBlockHashes(new_blockHash_orphan));
-                                orphan_buffer.orphan_blocks.remove(&blocks[0].hash());
-                                println!("The number of orphan blocks is decreased to {} blocks", orphan_buffer.orphan_blocks.len());
-                                break;
-                            }                        
                         }
                     }
                     let longest_chain = self.blockchain.lock().unwrap().all_blocks_in_longest_chain();
                     info!("{:?}", longest_chain);
-                    println!("Total number of blocks in blockchain: {} blocks", self.blockchain.lock().unwrap().hash_blocks.len());
-                    println!("The number of orphan blocks: {} blocks", orphan_buffer.orphan_blocks.len());
+                    debug!("Total number of blocks in blockchain: {} blocks", self.blockchain.lock().unwrap().hash_blocks.len());
                 }
 
                 Message::NewTransactionHashes(trans_hashes) => {
@@ -344,21 +205,11 @@ impl Context {
 
                 Message::GetTransactions(get_trans) => {
                     debug!("GetTransactions");
-                    let size = get_trans.len();
-                    let mut exist = true;
-                    for i in (0..size) {
-                        if(!self.mempool.lock().unwrap().contains_key(&get_trans[i]))
-                        {
-                            exist = false;
-                            break;
-                        }
-                    }
-                    let mut exist_trans : Vec<SignedTransaction> = Vec::new();
-                    if exist {
-                        for i in (0..size) {
-                            exist_trans.push(self.mempool.lock().unwrap()[&get_trans[i]].clone());
-                        }
-                    }
+                    // all of them or none, looked up under one lock as blocks remove transactions
+                    let exist_trans: Vec<SignedTransaction> = {
+                        let mempool = self.mempool.lock().unwrap();
+                        get_trans.iter().map(|hash| mempool.get(hash).cloned()).collect::<Option<_>>().unwrap_or_default()
+                    };
                     peer.write(Message::Transactions(exist_trans));
 
                 }
@@ -368,10 +219,29 @@ impl Context {
                     let size = trans.len();
                     let mut new_transHash: Vec<H256> = Vec::new();
                     let mut verified = true;
+                    let (chain_id, height) = {
+                        let blockchain = self.blockchain.lock().unwrap();
+                        (blockchain.chain_id(), blockchain.height(&blockchain.tip()).unwrap_or(0) + 1)
+                    };
+                    let signed = verifier::global().verify(&trans);
                     for i in (0..size) {
-                        if(verify(&trans[i].Transaction, &trans[i].public_key, &trans[i].Signature)){
-                            //put into mempool
-                            self.mempool.lock().unwrap().insert(trans[i].hash(), trans[i].clone());
+                        if(signed[i]){
+                            // signed for another chain, or too late to be included
+                            if let Err(e) = validation::check_transaction(&trans[i].Transaction, chain_id, height) {
+                                debug!("Dropping transaction {}: {}", trans[i].hash(), e);
+                                continue;
+                            }
+                            //put into mempool, unless it is full or conflicts with a transaction seen first
+                            let admission = mempool::admit(&mut self.mempool.lock().unwrap(), &trans[i], self.max_mempool, &self.events);
+                            match admission {
+                                Admission::Added => {}
+                                // already relayed when it was added
+                                Admission::Known | Admission::Conflict(_) => continue,
+                                Admission::Full => {
+                                    debug!("Mempool full, dropping transaction {}", trans[i].hash());
+                                    continue;
+                                }
+                            }
                             //self.txs.lock().unwrap().push_back(trans[i].clone());
                             new_transHash.push(trans[i].hash());
                         }
@@ -391,7 +261,6 @@ impl Context {
                         peer.write(Message::GetTransactions(ask_trans));
                         continue;
                     }
-                    println!("mempool size: {}", self.mempool.lock().unwrap().len());
                     self.server.broadcast(Message::NewTransactionHashes(new_transHash));
 
                 }
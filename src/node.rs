@@ -0,0 +1,26 @@
+//! State shared by the node's components. `main` builds it once, and each component clones the
+//! handles it needs out of it.
+
+use crate::blockchain::{Blockchain, State};
+use crate::events::EventBus;
+use crate::mempool::Mempool;
+use crate::network::server::Handle as ServerHandle;
+use crate::transaction::SignedTransaction;
+use crate::wallet::Wallet;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+#[derive(Clone)]
+pub struct Shared {
+    pub server: ServerHandle,
+    pub blockchain: Arc<Mutex<Blockchain>>,
+    pub mempool: Arc<Mutex<Mempool>>,
+    /// Transactions are refused once the mempool holds this many
+    pub max_mempool: usize,
+    /// The state after the tip
+    pub states: Arc<Mutex<State>>,
+    /// Pending transactions in arrival order, the candidates for the next block
+    pub txs: Arc<Mutex<VecDeque<SignedTransaction>>>,
+    pub wallet: Arc<Mutex<Wallet>>,
+    pub events: EventBus,
+}
@@ -0,0 +1,472 @@
+//! Stratum-like mining pool. Workers connect over TCP and exchange JSON objects, one per line:
+//!
+//! - `{"id":1,"method":"subscribe","params":{"worker":"alice"}}` registers the connection and
+//!   answers with the worker's extra nonce and the share target. The current job follows.
+//! - Jobs are pushed as `{"method":"job","params":{...}}` whenever the template changes. The
+//!   `header` is the hex canonical encoding of the block header with the worker's extra nonce
+//!   filled in. The nonce is the little-endian `u32` at byte offset 32. Shares and blocks are
+//!   judged by the chain's proof-of-work hash of the header, named by `pow`.
+//! - `{"id":2,"method":"submit","params":{"job_id":7,"nonce":12345}}` submits a share. Shares at
+//!   or below the share target are credited to the worker. Those also at or below the block
+//!   difficulty are added to the chain.
+
+use crate::block::Block;
+use crate::blockchain::{Blockchain, State};
+use crate::crypto::domain;
+use crate::crypto::hash::{H160, H256, Hashable};
+use crate::events::EventBus;
+use crate::metrics;
+use crate::miner;
+use crate::network::server::Handle as ServerHandle;
+use crate::node::Shared as Node;
+use crate::pow::ProofOfWork;
+use crate::transaction::SignedTransaction;
+use crate::validation;
+use crate::mempool::Mempool;
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time;
+
+/// How often the job is rebuilt to pick up new transactions and a fresh timestamp when no new
+/// tip arrives
+const JOB_REFRESH: time::Duration = time::Duration::from_secs(5);
+
+/// Longest a write to a worker may block before the connection is given up
+const WRITE_TIMEOUT: time::Duration = time::Duration::from_secs(5);
+
+/// Extra nonces handed to pool workers start here, leaving the lower half to the node's own
+/// miner, which rolls its extra nonce up from zero
+const FIRST_EXTRA_NONCE: u32 = 0x8000_0000;
+
+/// Transactions per pool block
+const POOL_MAX_TXS: usize = 1;
+
+/// Share accounting for one worker name, summed over its connections
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct WorkerStats {
+    pub accepted: u64,
+    pub rejected: u64,
+    /// Shares for a job that had already been replaced
+    pub stale: u64,
+    pub blocks: u64,
+    /// When the last accepted share arrived, in milliseconds since the UNIX epoch
+    pub last_share_time: Option<u128>,
+}
+
+/// Snapshot of the pool, as returned by `Handle::stats`
+#[derive(Serialize, Debug, Clone)]
+pub struct PoolStats {
+    pub address: String,
+    /// Each share counts as this fraction of a block's work
+    pub share_factor: u32,
+    pub job_id: Option<u64>,
+    pub connections: usize,
+    pub workers: BTreeMap<String, WorkerStats>,
+}
+
+/// The template workers currently search, with an extra nonce of zero
+struct Job {
+    id: u64,
+    block: Block,
+    share_target: H256,
+}
+
+struct Session {
+    /// Shared with the thread serving the connection, so lines from both never interleave
+    writer: Arc<Mutex<TcpStream>>,
+    worker: Option<String>,
+    extra_nonce: u32,
+    /// Nonces already submitted for the current job
+    submitted: HashSet<u32>,
+}
+
+struct Shared {
+    job: Mutex<Option<Job>>,
+    sessions: Mutex<HashMap<u64, Session>>,
+    stats: Mutex<BTreeMap<String, WorkerStats>>,
+    next_session: AtomicU64,
+    next_extra_nonce: AtomicU32,
+}
+
+pub struct Context {
+    addr: SocketAddr,
+    address: H160,
+    share_factor: u32,
+    /// The chain's puzzle, fixed at genesis
+    pow: Arc<dyn ProofOfWork>,
+    shared: Arc<Shared>,
+    server: ServerHandle,
+    blockchain: Arc<Mutex<Blockchain>>,
+    mempool: Arc<Mutex<Mempool>>,
+    states: Arc<Mutex<State>>,
+    txs: Arc<Mutex<VecDeque<SignedTransaction>>>,
+    events: EventBus,
+}
+
+#[derive(Clone)]
+pub struct Handle {
+    address: H160,
+    share_factor: u32,
+    shared: Arc<Shared>,
+}
+
+#[derive(Deserialize)]
+struct Request {
+    #[serde(default)]
+    id: Value,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Deserialize)]
+struct SubscribeParams {
+    worker: String,
+}
+
+#[derive(Deserialize)]
+struct SubmitParams {
+    job_id: u64,
+    nonce: u32,
+}
+
+pub fn new(
+    addr: SocketAddr,
+    address: &H160,
+    share_factor: u32,
+    node: &Node,
+) -> (Context, Handle) {
+    let shared = Arc::new(Shared {
+        job: Mutex::new(None),
+        sessions: Mutex::new(HashMap::new()),
+        stats: Mutex::new(BTreeMap::new()),
+        next_session: AtomicU64::new(0),
+        next_extra_nonce: AtomicU32::new(FIRST_EXTRA_NONCE),
+    });
+    let share_factor = share_factor.max(1);
+    let ctx = Context {
+        addr,
+        address: *address,
+        share_factor,
+        pow: node.blockchain.lock().unwrap().pow.clone(),
+        shared: Arc::clone(&shared),
+        server: node.server.clone(),
+        blockchain: Arc::clone(&node.blockchain),
+        mempool: Arc::clone(&node.mempool),
+        states: Arc::clone(&node.states),
+        txs: Arc::clone(&node.txs),
+        events: node.events.clone(),
+    };
+    let handle = Handle { address: *address, share_factor, shared };
+    (ctx, handle)
+}
+
+impl Handle {
+    pub fn stats(&self) -> PoolStats {
+        PoolStats {
+            address: self.address.to_string(),
+            share_factor: self.share_factor,
+            job_id: self.shared.job.lock().unwrap().as_ref().map(|job| job.id),
+            connections: self.shared.sessions.lock().unwrap().len(),
+            workers: self.shared.stats.lock().unwrap().clone(),
+        }
+    }
+}
+
+/// The target `factor` times easier than `target`, saturating at the maximum
+pub fn scale_target(target: &H256, factor: u32) -> H256 {
+    let mut out = [0u8; 32];
+    let mut carry: u64 = 0;
+    for (i, byte) in target.as_ref().iter().enumerate().rev() {
+        let v = *byte as u64 * factor as u64 + carry;
+        out[i] = v as u8;
+        carry = v >> 8;
+    }
+    if carry != 0 {
+        return [0xffu8; 32].into();
+    }
+    out.into()
+}
+
+fn now_millis() -> u128 {
+    match time::SystemTime::now().duration_since(time::UNIX_EPOCH) {
+        Ok(n) => n.as_millis(),
+        Err(_) => panic!("SystemTime before UNIX EPOCH!"),
+    }
+}
+
+fn send_line(writer: &mut TcpStream, message: &Value) -> std::io::Result<()> {
+    let mut line = serde_json::to_string(message).unwrap();
+    line.push('\n');
+    writer.write_all(line.as_bytes())
+}
+
+fn job_notification(job: &Job, pow: &str, extra_nonce: u32) -> Value {
+    let mut header = job.block.header.clone();
+    header.extra_nonce = extra_nonce;
+    json!({
+        "method": "job",
+        "params": {
+            "job_id": job.id,
+            "header": hex::encode(domain::encode(&header)),
+            "target": header.difficulty.to_string(),
+            "share_target": job.share_target.to_string(),
+            "pow": pow,
+        }
+    })
+}
+
+impl Context {
+    pub fn start(self) -> std::io::Result<()> {
+        let listener = TcpListener::bind(self.addr)?;
+        info!("Pool listening on {}", self.addr);
+        let ctx = Arc::new(self);
+
+        let updater = Arc::clone(&ctx);
+        thread::Builder::new()
+            .name("pool-jobs".to_string())
+            .spawn(move || updater.job_loop())
+            .unwrap();
+
+        thread::Builder::new()
+            .name("pool-listener".to_string())
+            .spawn(move || {
+                for stream in listener.incoming() {
+                    match stream {
+                        Ok(stream) => {
+                            let ctx = Arc::clone(&ctx);
+                            thread::spawn(move || ctx.serve(stream));
+                        }
+                        Err(e) => warn!("Pool failed to accept connection: {}", e),
+                    }
+                }
+            })
+            .unwrap();
+        Ok(())
+    }
+
+    /// Rebuild the job on every new tip, and every `JOB_REFRESH` for new transactions and a
+    /// timestamp that keeps up with the clock
+    fn job_loop(&self) {
+        let kinds = vec!["new_tip".to_string()];
+        let mut since = 0;
+        let mut next_id = 0;
+        let mut published = time::Instant::now();
+        loop {
+            let template = {
+                let blockchain = self.blockchain.lock().unwrap();
+                let txs = self.txs.lock().unwrap();
+                miner::block_template(&blockchain, &txs, POOL_MAX_TXS, &self.address)
+            };
+            let changed = {
+                let job = self.shared.job.lock().unwrap();
+                match (&*job, &template) {
+                    (Some(job), Some(block)) => {
+                        job.block.header.parent != block.header.parent
+                            || job.block.header.merkle_root != block.header.merkle_root
+                    }
+                    (None, None) => false,
+                    _ => true,
+                }
+            };
+            if changed || (template.is_some() && published.elapsed() >= JOB_REFRESH) {
+                published = time::Instant::now();
+                let job = template.map(|block| {
+                    next_id += 1;
+                    let share_target = scale_target(&block.header.difficulty, self.share_factor);
+                    Job { id: next_id, block, share_target }
+                });
+                self.publish_job(job);
+            }
+            since = self.events.poll(since, &kinds, JOB_REFRESH).next;
+        }
+    }
+
+    /// Replace the job and push it to every subscribed worker. The notifications are built under
+    /// the locks but sent after releasing them, so a slow worker holds up no one else.
+    fn publish_job(&self, job: Option<Job>) {
+        let notifications: Vec<(u64, Arc<Mutex<TcpStream>>, Value)> = {
+            let mut current = self.shared.job.lock().unwrap();
+            *current = job;
+            let mut sessions = self.shared.sessions.lock().unwrap();
+            let job = match &*current {
+                Some(job) => job,
+                None => {
+                    sessions.values_mut().for_each(|session| session.submitted.clear());
+                    return;
+                }
+            };
+            debug!("Pool job {} on parent {}", job.id, job.block.header.parent);
+            sessions
+                .iter_mut()
+                .filter_map(|(id, session)| {
+                    session.submitted.clear();
+                    session.worker.as_ref()?;
+                    let notification = job_notification(job, &self.pow.name(), session.extra_nonce);
+                    Some((*id, Arc::clone(&session.writer), notification))
+                })
+                .collect()
+        };
+        let dead: Vec<u64> = notifications
+            .into_iter()
+            .filter(|(_, writer, notification)| send_line(&mut writer.lock().unwrap(), notification).is_err())
+            .map(|(id, _, _)| id)
+            .collect();
+        let mut sessions = self.shared.sessions.lock().unwrap();
+        for id in dead {
+            sessions.remove(&id);
+        }
+    }
+
+    fn serve(&self, stream: TcpStream) {
+        let peer = stream.peer_addr().ok();
+        let writer = match stream.try_clone().and_then(|writer| writer.set_write_timeout(Some(WRITE_TIMEOUT)).map(|_| writer)) {
+            Ok(writer) => Arc::new(Mutex::new(writer)),
+            Err(e) => {
+                warn!("Pool failed to set up connection: {}", e);
+                return;
+            }
+        };
+        let id = self.shared.next_session.fetch_add(1, Ordering::Relaxed);
+        let session = Session { writer: Arc::clone(&writer), worker: None, extra_nonce: 0, submitted: HashSet::new() };
+        self.shared.sessions.lock().unwrap().insert(id, session);
+        info!("Pool connection from {:?}", peer);
+
+        for line in BufReader::new(stream).lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => break,
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            let mut messages = Vec::new();
+            match serde_json::from_str::<Request>(&line) {
+                Ok(request) => match self.dispatch(id, &request.method, request.params) {
+                    Ok(result) => {
+                        // a new subscriber gets the current job right after the confirmation
+                        let follow_up = match result["extra_nonce"].as_u64() {
+                            Some(extra_nonce) => self.shared.job.lock().unwrap().as_ref().map(|job| job_notification(job, &self.pow.name(), extra_nonce as u32)),
+                            None => None,
+                        };
+                        messages.push(json!({ "id": request.id, "result": result, "error": null }));
+                        messages.extend(follow_up);
+                    }
+                    Err(e) => messages.push(json!({ "id": request.id, "result": null, "error": e })),
+                },
+                Err(e) => messages.push(json!({ "id": null, "result": null, "error": format!("invalid request: {}", e) })),
+            }
+            // the session is gone once a failed job push dropped it
+            if !self.shared.sessions.lock().unwrap().contains_key(&id) {
+                break;
+            }
+            let mut writer = writer.lock().unwrap();
+            if messages.iter().any(|message| send_line(&mut writer, message).is_err()) {
+                break;
+            }
+        }
+        self.shared.sessions.lock().unwrap().remove(&id);
+        info!("Pool connection from {:?} closed", peer);
+    }
+
+    fn dispatch(&self, session_id: u64, method: &str, params: Value) -> Result<Value, String> {
+        match method {
+            "subscribe" => {
+                let p: SubscribeParams = serde_json::from_value(params).map_err(|e| format!("invalid params: {}", e))?;
+                let extra_nonce = self.shared.next_extra_nonce.fetch_add(1, Ordering::Relaxed);
+                let mut sessions = self.shared.sessions.lock().unwrap();
+                let session = sessions.get_mut(&session_id).ok_or("connection closed")?;
+                session.worker = Some(p.worker.clone());
+                session.extra_nonce = extra_nonce;
+                self.shared.stats.lock().unwrap().entry(p.worker).or_default();
+                Ok(json!({ "extra_nonce": extra_nonce, "share_factor": self.share_factor }))
+            }
+            "submit" => {
+                let p: SubmitParams = serde_json::from_value(params).map_err(|e| format!("invalid params: {}", e))?;
+                self.submit(session_id, p.job_id, p.nonce)
+            }
+            _ => Err(format!("unknown method {}", method)),
+        }
+    }
+
+    fn submit(&self, session_id: u64, job_id: u64, nonce: u32) -> Result<Value, String> {
+        let (worker, extra_nonce, duplicate) = {
+            let mut sessions = self.shared.sessions.lock().unwrap();
+            let session = sessions.get_mut(&session_id).ok_or("connection closed")?;
+            let worker = session.worker.clone().ok_or("not subscribed")?;
+            (worker, session.extra_nonce, !session.submitted.insert(nonce))
+        };
+        let (block, share_target) = {
+            let job = self.shared.job.lock().unwrap();
+            match &*job {
+                Some(job) if job.id == job_id => (job.block.clone(), job.share_target),
+                _ => {
+                    self.record(&worker, "stale", |s| s.stale += 1);
+                    return Err("stale job".to_string());
+                }
+            }
+        };
+        if duplicate {
+            self.record(&worker, "rejected", |s| s.rejected += 1);
+            return Err("duplicate share".to_string());
+        }
+        let mut block = block;
+        block.header.extra_nonce = extra_nonce;
+        block.header.nonce = nonce;
+        let pow_hash = self.pow.pow_hash(&block.header);
+        let hash = block.hash();
+        if pow_hash > share_target {
+            self.record(&worker, "rejected", |s| s.rejected += 1);
+            return Err("share above target".to_string());
+        }
+        let now = now_millis();
+        self.record(&worker, "accepted", |s| {
+            s.accepted += 1;
+            s.last_share_time = Some(now);
+        });
+        if pow_hash > block.header.difficulty {
+            return Ok(json!({ "accepted": true, "block": null }));
+        }
+
+        if let Err(e) = validation::validate_block(&block, &self.blockchain.lock().unwrap()) {
+            warn!("Pool block {} from {} rejected: {}", hash, worker, e);
+            return Ok(json!({ "accepted": true, "block": null }));
+        }
+        info!("Pool worker {} found block {}", worker, hash);
+        self.record(&worker, "block", |s| s.blocks += 1);
+        metrics::global().blocks_mined.inc();
+        miner::accept_block(&block, &self.blockchain, &self.mempool, &self.states, &self.txs, &self.server);
+        Ok(json!({ "accepted": true, "block": hash.to_string() }))
+    }
+
+    fn record<F: FnOnce(&mut WorkerStats)>(&self, worker: &str, outcome: &'static str, update: F) {
+        metrics::global().pool_shares.add(outcome, 1);
+        update(self.shared.stats.lock().unwrap().entry(worker.to_string()).or_default());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scale_target_multiplies() {
+        let mut bytes = [0u8; 32];
+        bytes[31] = 0x80;
+        bytes[30] = 0x01;
+        let scaled = scale_target(&bytes.into(), 4);
+        assert_eq!(scaled.as_ref()[31], 0x00);
+        assert_eq!(scaled.as_ref()[30], 0x06);
+
+        let easy: H256 = [0x40u8; 32].into();
+        assert_eq!(scale_target(&easy, 4), [0xffu8; 32].into());
+        assert_eq!(scale_target(&easy, 1), easy);
+    }
+}
@@ -0,0 +1,162 @@
+//! Proof-of-work puzzles. The puzzle is fixed per chain by `GenesisParams::pow`; the block hash
+//! used to identify blocks is always the header hash, SHA256 of the canonical header encoding in
+//! the header domain (see `crypto::domain`), whatever the puzzle.
+
+use crate::block::Header;
+use crate::crypto::hash::{H256, Hashable};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// Memory used by `MemoryHard` unless configured otherwise, in KiB
+pub const DEFAULT_MEMORY_KIB: u32 = 64;
+/// Most memory `MemoryHard` may be configured with, in KiB, as every hash allocates all of it
+pub const MAX_MEMORY_KIB: u32 = 16 * 1024;
+
+pub trait ProofOfWork: Send + Sync {
+    /// Name of the puzzle, as accepted by `PowAlgorithm::from_str`
+    fn name(&self) -> String;
+
+    /// The hash compared against the header's difficulty
+    fn pow_hash(&self, header: &Header) -> H256;
+
+    fn meets_target(&self, header: &Header, target: &H256) -> bool {
+        self.pow_hash(header) <= *target
+    }
+}
+
+/// The header hash itself, i.e. the block hash
+pub struct Sha256;
+
+impl ProofOfWork for Sha256 {
+    fn name(&self) -> String {
+        "sha256".to_string()
+    }
+
+    fn pow_hash(&self, header: &Header) -> H256 {
+        header.hash()
+    }
+}
+
+/// SHA256 applied twice, as in Bitcoin
+pub struct DoubleSha256;
+
+impl ProofOfWork for DoubleSha256 {
+    fn name(&self) -> String {
+        "double-sha256".to_string()
+    }
+
+    fn pow_hash(&self, header: &Header) -> H256 {
+        ring::digest::digest(&ring::digest::SHA256, header.hash().as_ref()).into()
+    }
+}
+
+/// A scrypt-like puzzle: a buffer of `memory_kib` KiB is filled with a hash chain seeded by the
+/// header, then read back at data-dependent positions, so each attempt needs the whole buffer
+pub struct MemoryHard {
+    pub memory_kib: u32,
+}
+
+impl ProofOfWork for MemoryHard {
+    fn name(&self) -> String {
+        format!("memory-hard:{}", self.memory_kib)
+    }
+
+    fn pow_hash(&self, header: &Header) -> H256 {
+        let slots = (self.memory_kib.max(1) as usize * 1024) / 32;
+        let mut buffer: Vec<[u8; 32]> = Vec::with_capacity(slots);
+        let mut x = [0u8; 32];
+        x.copy_from_slice(header.hash().as_ref());
+        for _ in 0..slots {
+            x = sha256(&x);
+            buffer.push(x);
+        }
+        for _ in 0..slots {
+            let mut index = [0u8; 8];
+            index.copy_from_slice(&x[..8]);
+            let slot = &buffer[(u64::from_le_bytes(index) % slots as u64) as usize];
+            let mut mixed = [0u8; 32];
+            for (m, (a, b)) in mixed.iter_mut().zip(x.iter().zip(slot.iter())) {
+                *m = a ^ b;
+            }
+            x = sha256(&mixed);
+        }
+        x.into()
+    }
+}
+
+fn sha256(data: &[u8]) -> [u8; 32] {
+    let mut out = [0u8; 32];
+    out.copy_from_slice(ring::digest::digest(&ring::digest::SHA256, data).as_ref());
+    out
+}
+
+/// The puzzle of a chain, as recorded in its genesis parameters
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum PowAlgorithm {
+    #[default]
+    Sha256,
+    DoubleSha256,
+    MemoryHard { memory_kib: u32 },
+}
+
+impl PowAlgorithm {
+    pub fn build(&self) -> Arc<dyn ProofOfWork> {
+        match *self {
+            PowAlgorithm::Sha256 => Arc::new(Sha256),
+            PowAlgorithm::DoubleSha256 => Arc::new(DoubleSha256),
+            PowAlgorithm::MemoryHard { memory_kib } => Arc::new(MemoryHard { memory_kib }),
+        }
+    }
+}
+
+/// Parses `sha256`, `double-sha256`, `memory-hard` or `memory-hard:<KiB>`, with 1 to
+/// `MAX_MEMORY_KIB` KiB
+impl std::str::FromStr for PowAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sha256" => Ok(PowAlgorithm::Sha256),
+            "double-sha256" => Ok(PowAlgorithm::DoubleSha256),
+            "memory-hard" => Ok(PowAlgorithm::MemoryHard { memory_kib: DEFAULT_MEMORY_KIB }),
+            _ => match s.strip_prefix("memory-hard:") {
+                Some(kib) => match kib.parse::<u32>() {
+                    Ok(memory_kib) if (1..=MAX_MEMORY_KIB).contains(&memory_kib) => {
+                        Ok(PowAlgorithm::MemoryHard { memory_kib })
+                    }
+                    Ok(_) => Err(format!("memory size {} KiB out of range 1 to {}", kib, MAX_MEMORY_KIB)),
+                    Err(e) => Err(format!("invalid memory size {}: {}", kib, e)),
+                },
+                None => Err(format!("unknown proof of work {}", s)),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::test::generate_random_block;
+
+    #[test]
+    fn algorithms_differ() {
+        let header = generate_random_block(&Default::default()).header;
+        let sha = Sha256.pow_hash(&header);
+        let double = DoubleSha256.pow_hash(&header);
+        let memory = MemoryHard { memory_kib: 4 }.pow_hash(&header);
+        assert_eq!(sha, header.hash());
+        assert!(sha != double && double != memory && sha != memory);
+        assert_eq!(memory, MemoryHard { memory_kib: 4 }.pow_hash(&header));
+    }
+
+    #[test]
+    fn parse_names() {
+        for algorithm in [PowAlgorithm::Sha256, PowAlgorithm::DoubleSha256, PowAlgorithm::MemoryHard { memory_kib: 8 }].iter() {
+            assert_eq!(algorithm.build().name().parse::<PowAlgorithm>(), Ok(*algorithm));
+        }
+        assert!("scrypt".parse::<PowAlgorithm>().is_err());
+        assert!("memory-hard:0".parse::<PowAlgorithm>().is_err());
+        assert!(format!("memory-hard:{}", MAX_MEMORY_KIB + 1).parse::<PowAlgorithm>().is_err());
+    }
+}
@@ -0,0 +1,67 @@
+//! Coordinated shutdown. Any component may request it, e.g. the signal handler or the API;
+//! `main` waits for the request, then stops the node in order and flushes its state to disk.
+
+use std::sync::{Arc, Condvar, Mutex};
+
+/// A component's control channel is closed, as the component named here has stopped
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Stopped(pub &'static str);
+
+impl std::fmt::Display for Stopped {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} has stopped", self.0)
+    }
+}
+
+impl std::error::Error for Stopped {}
+
+#[derive(Clone, Default)]
+pub struct Shutdown {
+    requested: Arc<(Mutex<bool>, Condvar)>,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Ask the node to shut down; later calls have no further effect
+    pub fn request(&self) {
+        let (requested, condvar) = &*self.requested;
+        *requested.lock().unwrap() = true;
+        condvar.notify_all();
+    }
+
+    pub fn is_requested(&self) -> bool {
+        *self.requested.0.lock().unwrap()
+    }
+
+    /// Block until shutdown is requested
+    pub fn wait(&self) {
+        let (requested, condvar) = &*self.requested;
+        let mut requested = requested.lock().unwrap();
+        while !*requested {
+            requested = condvar.wait(requested).unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn wait_returns_once_requested() {
+        let shutdown = Shutdown::new();
+        assert!(!shutdown.is_requested());
+        let waiter = {
+            let shutdown = shutdown.clone();
+            thread::spawn(move || shutdown.wait())
+        };
+        shutdown.request();
+        waiter.join().unwrap();
+        assert!(shutdown.is_requested());
+        shutdown.wait();
+    }
+}
@@ -0,0 +1,139 @@
+use crate::block::Block;
+use crate::crypto::hash::H256;
+use crate::index::AddressIndex;
+use crate::transaction::SignedTransaction;
+use log::warn;
+use std::net::SocketAddr;
+use std::convert::TryInto;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+const BLOCKS_FILE: &str = "blocks.dat";
+const ADDRESS_INDEX_FILE: &str = "addr_index.dat";
+const MEMPOOL_FILE: &str = "mempool.dat";
+const PEERS_FILE: &str = "peers.dat";
+const GENESIS_FILE: &str = "genesis.dat";
+
+/// On-disk storage of the chain inside a data directory. Blocks are kept in an append-only log
+/// of length-prefixed bincode records, in the order they were inserted into the blockchain, so
+/// every block comes after its parent.
+pub struct Storage {
+    dir: PathBuf,
+}
+
+impl Storage {
+    pub fn open(dir: &Path) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        Ok(Self { dir: dir.to_path_buf() })
+    }
+
+    pub fn append_block(&self, block: &Block) -> io::Result<()> {
+        let encoded = bincode::serialize(block).unwrap();
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.dir.join(BLOCKS_FILE))?;
+        file.write_all(&(encoded.len() as u32).to_be_bytes())?;
+        file.write_all(&encoded)?;
+        Ok(())
+    }
+
+    /// Force the appended blocks out to disk
+    pub fn sync_blocks(&self) -> io::Result<()> {
+        match File::open(self.dir.join(BLOCKS_FILE)) {
+            Ok(file) => file.sync_all(),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn load_blocks(&self) -> io::Result<Vec<Block>> {
+        let mut buffer = Vec::new();
+        match File::open(self.dir.join(BLOCKS_FILE)) {
+            Ok(mut file) => file.read_to_end(&mut buffer)?,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let mut blocks = Vec::new();
+        let mut offset = 0;
+        while offset + 4 <= buffer.len() {
+            let length = u32::from_be_bytes(buffer[offset..offset + 4].try_into().unwrap()) as usize;
+            if offset + 4 + length > buffer.len() {
+                break;
+            }
+            let block = bincode::deserialize(&buffer[offset + 4..offset + 4 + length])
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            blocks.push(block);
+            offset += 4 + length;
+        }
+        if offset < buffer.len() {
+            // a torn write at the end of the log, cut the partial record so appends follow the
+            // last whole one
+            warn!("Dropping {} bytes of a partial block record", buffer.len() - offset);
+            OpenOptions::new().write(true).open(self.dir.join(BLOCKS_FILE))?.set_len(offset as u64)?;
+        }
+        Ok(blocks)
+    }
+
+    /// Record the hash of the genesis parameters the stored chain grows from
+    pub fn save_genesis(&self, genesis: &H256) -> io::Result<()> {
+        self.replace(GENESIS_FILE, &bincode::serialize(genesis).unwrap())
+    }
+
+    pub fn load_genesis(&self) -> io::Result<Option<H256>> {
+        match fs::read(self.dir.join(GENESIS_FILE)) {
+            Ok(encoded) => bincode::deserialize(&encoded).map(Some).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Replace the stored address index
+    pub fn save_address_index(&self, index: &AddressIndex) -> io::Result<()> {
+        self.replace(ADDRESS_INDEX_FILE, &bincode::serialize(index).unwrap())
+    }
+
+    pub fn load_address_index(&self) -> io::Result<Option<AddressIndex>> {
+        match fs::read(self.dir.join(ADDRESS_INDEX_FILE)) {
+            Ok(encoded) => Ok(bincode::deserialize(&encoded).ok()),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Replace the stored pending transactions, written at shutdown
+    pub fn save_mempool(&self, txs: &[SignedTransaction]) -> io::Result<()> {
+        self.replace(MEMPOOL_FILE, &bincode::serialize(txs).unwrap())
+    }
+
+    pub fn load_mempool(&self) -> io::Result<Vec<SignedTransaction>> {
+        self.load_list(MEMPOOL_FILE)
+    }
+
+    /// Replace the stored addresses of known peers, written at shutdown
+    pub fn save_peers(&self, peers: &[SocketAddr]) -> io::Result<()> {
+        self.replace(PEERS_FILE, &bincode::serialize(peers).unwrap())
+    }
+
+    pub fn load_peers(&self) -> io::Result<Vec<SocketAddr>> {
+        self.load_list(PEERS_FILE)
+    }
+
+    /// Replace file `name` with `data`. The data is written to a temporary file first so a crash
+    /// never leaves a half-written file behind.
+    fn replace(&self, name: &str, data: &[u8]) -> io::Result<()> {
+        let tmp = self.dir.join(format!("{}.tmp", name));
+        fs::write(&tmp, data)?;
+        fs::rename(&tmp, self.dir.join(name))
+    }
+
+    /// A list stored by `replace`, empty if the file is missing
+    fn load_list<T: serde::de::DeserializeOwned>(&self, name: &str) -> io::Result<Vec<T>> {
+        match fs::read(self.dir.join(name)) {
+            Ok(encoded) => bincode::deserialize(&encoded).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(e),
+        }
+    }
+}
@@ -1,7 +1,9 @@
 use serde::{Serialize,Deserialize};
 use ring::signature::{Ed25519KeyPair, Signature, KeyPair, VerificationAlgorithm, EdDSAParameters};
 use crate::crypto::hash::{H256, H160, Hashable};
+use crate::crypto::domain::{self, Domain};
 use log::{info};
+use std::convert::TryInto;
 
 
 
@@ -10,6 +12,10 @@ pub struct Transaction {
     pub recipAddress : H160,
     pub val : u32,
     pub accountNonce : u16,
+    /// The chain the transaction is meant for, see `chain_id`; other chains reject it
+    pub chain_id : u64,
+    /// Height of the last block that may include the transaction; it never expires if `None`
+    pub expiry : Option<u64>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
@@ -18,47 +24,152 @@ pub struct SignedTransaction {
     pub public_key : Vec<u8>,
     pub Signature :  Vec<u8>, // by function into_vec
     pub sender_addr : H160,
+    /// Signatures of a transaction spending from a multisig account, whose `public_key` and
+    /// `Signature` are then left empty
+    pub multisig : Option<MultisigWitness>,
 }
 
-/// Create digital signature of a transaction
-pub fn sign(t: &Transaction, key: &Ed25519KeyPair) -> Signature {
+/// Most keys a multisig account may have
+pub const MAX_MULTISIG_KEYS: usize = 16;
 
-    let encoded = bincode::serialize(t).unwrap();
-    //println!("{:?}", encoded);
-    let signature = key.sign(&encoded);
+/// An M-of-N account: spending from it takes signatures by `threshold` of `public_keys`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MultisigPolicy {
+    pub threshold : u8,
+    pub public_keys : Vec<Vec<u8>>,
+}
+
+/// The signatures collected for a transaction spending from a multisig account
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MultisigWitness {
+    pub policy : MultisigPolicy,
+    /// Pairs of an index into `policy.public_keys` and the signature of that key, by increasing index
+    pub signatures : Vec<(u8, Vec<u8>)>,
+}
+
+impl MultisigPolicy {
+    /// A policy needing `threshold` signatures of `public_keys`, which must be distinct Ed25519 keys
+    pub fn new(threshold: u8, public_keys: Vec<Vec<u8>>) -> Result<Self, String> {
+        let policy = Self { threshold, public_keys };
+        policy.check()?;
+        Ok(policy)
+    }
+
+    /// Check the rules of `new`, for a policy received from elsewhere
+    pub fn check(&self) -> Result<(), String> {
+        if self.threshold == 0 || self.threshold as usize > self.public_keys.len() {
+            return Err(format!("threshold {} out of range for {} keys", self.threshold, self.public_keys.len()));
+        }
+        if self.public_keys.len() > MAX_MULTISIG_KEYS {
+            return Err(format!("at most {} keys are allowed", MAX_MULTISIG_KEYS));
+        }
+        if self.public_keys.iter().any(|key| key.len() != 32) {
+            return Err("public keys must be 32 bytes".to_string());
+        }
+        for (i, key) in self.public_keys.iter().enumerate() {
+            if self.public_keys[..i].contains(key) {
+                return Err("public keys must be distinct".to_string());
+            }
+        }
+        Ok(())
+    }
+
+    /// The account address: the last 20 bytes of the multisig hash of the policy. It commits to
+    /// the keys, their order and the threshold, and cannot collide with a single key address.
+    pub fn address(&self) -> H160 {
+        let hash = domain::hash(Domain::Multisig, &domain::encode(self));
+        hash.as_ref()[12..=31].into()
+    }
+}
+
+impl MultisigWitness {
+    /// A witness for `policy` without signatures yet
+    pub fn new(policy: MultisigPolicy) -> Self {
+        Self { policy, signatures: Vec::new() }
+    }
+
+    /// Whether enough keys have signed
+    pub fn is_complete(&self) -> bool {
+        self.signatures.len() >= self.policy.threshold as usize
+    }
 
-    return signature;
-    //unimplemented!()
+    /// Add the signature of key `index`, keeping the signatures ordered. Returns false if that key
+    /// has signed already.
+    pub fn add_signature(&mut self, index: u8, signature: Vec<u8>) -> bool {
+        match self.signatures.binary_search_by_key(&index, |(i, _)| *i) {
+            Ok(_) => false,
+            Err(position) => {
+                self.signatures.insert(position, (index, signature));
+                true
+            }
+        }
+    }
+}
+
+/// Identifier of the chain whose genesis block is `genesis`: the first 8 bytes of its hash.
+/// Transactions sign it, so they cannot be replayed on a chain with another genesis.
+pub fn chain_id(genesis: &H256) -> u64 {
+    u64::from_be_bytes(genesis.as_ref()[..8].try_into().unwrap())
+}
+
+/// Derive the account address of a public key: the last 20 bytes of its SHA256 digest
+pub fn address_from_public_key(public_key: &[u8]) -> H160 {
+    let hash_key : H256 = ring::digest::digest(&ring::digest::SHA256, public_key).into();
+    hash_key.as_ref()[12..=31].into()
+}
+
+/// The message the sender of `t` signs: the transaction together with the account it spends
+/// from, so a signature by a key is only good for that account, not for every multisig account
+/// listing the key
+fn signing_message(t: &Transaction, sender: &H160) -> Vec<u8> {
+    domain::message(Domain::TxSign, &domain::encode(&(t, sender)))
 }
 
-/// Verify digital signature of a transaction, using public key instead of secret key
-/*pub fn verify(t: &Transaction, public_key: &<Ed25519KeyPair as KeyPair>::PublicKey, signature: &Signature) -> bool {
-
-    let peer_public_key_bytes = public_key.as_ref();
-    //println!("{:?}", peer_public_key_bytes);
-    let peer_public_key = ring::signature::UnparsedPublicKey::new(&ring::signature::ED25519, peer_public_key_bytes);
-    let encoded = bincode::serialize(t).unwrap();
-    let res = peer_public_key.verify(&encoded, signature.as_ref());
-    //println!("{:?}", res);
-    match res {
-        Ok(v) => return true,
-        Err(e) => return false,
-    }
-    //unimplemented!()
-}*/
-
-pub fn verify(t: &Transaction, public_key: &Vec<u8>, signature: &Vec<u8>) -> bool {
-
-    let peer_public_key_bytes : &[u8] = &public_key;
-    let peer_public_key = ring::signature::UnparsedPublicKey::new(&ring::signature::ED25519, peer_public_key_bytes);
-    let encoded = bincode::serialize(t).unwrap();
-    let signature_bytes : &[u8] = &signature;
-    let res = peer_public_key.verify(&encoded, signature_bytes);
-    match res {
-        Ok(v) => return true,
-        Err(e) => return false,
-    }
-    //unimplemented!()
+/// Create digital signature of a transaction spending from `sender`
+pub fn sign(t: &Transaction, sender: &H160, key: &Ed25519KeyPair) -> Signature {
+    key.sign(&signing_message(t, sender))
+}
+
+/// The address a signed transaction spends from, according to its witness
+pub fn sender_address(tx: &SignedTransaction) -> H160 {
+    match &tx.multisig {
+        Some(witness) => witness.policy.address(),
+        None => address_from_public_key(&tx.public_key),
+    }
+}
+
+/// Verify the witness of a signed transaction: its signature, or for a multisig account at least
+/// `threshold` valid signatures by distinct keys of the policy, listed by increasing key index.
+/// The sender address must be the one the public key or policy hashes to.
+pub fn verify_witness(tx: &SignedTransaction) -> bool {
+    if sender_address(tx) != tx.sender_addr {
+        return false;
+    }
+    let witness = match &tx.multisig {
+        Some(witness) => witness,
+        None => return verify(&tx.Transaction, &tx.sender_addr, &tx.public_key, &tx.Signature),
+    };
+    let policy = &witness.policy;
+    if policy.check().is_err()
+        || !tx.public_key.is_empty()
+        || !tx.Signature.is_empty()
+        || !witness.is_complete()
+    {
+        return false;
+    }
+    let sender = policy.address();
+    let ordered = witness.signatures.windows(2).all(|pair| pair[0].0 < pair[1].0);
+    ordered
+        && witness.signatures.iter().all(|(index, signature)| match policy.public_keys.get(*index as usize) {
+            Some(key) => verify(&tx.Transaction, &sender, key, signature),
+            None => false,
+        })
+}
+
+/// Verify the signature by `public_key` of a transaction spending from `sender`
+pub fn verify(t: &Transaction, sender: &H160, public_key: &[u8], signature: &[u8]) -> bool {
+    let peer_public_key = ring::signature::UnparsedPublicKey::new(&ring::signature::ED25519, public_key);
+    peer_public_key.verify(&signing_message(t, sender), signature).is_ok()
 }
 
 #[cfg(any(test, test_utilities))]
@@ -72,20 +183,105 @@ pub mod tests {
         use rand::Rng;
 
         let mut rng = rand::thread_rng();
-        let In : u8 = rng.gen();
-        let Out : u8 = rng.gen();
-        //println!("{:?}", In);
-        //println!("{:?}", Out);
-        let transaction = Transaction{Input: In, Output: Out};
+        let recipient: [u8; 20] = rng.gen();
+        let transaction = Transaction{recipAddress : recipient.into(), val : rng.gen(), accountNonce : rng.gen(), chain_id : rng.gen(), expiry : None};
         return transaction;
-        //unimplemented!()
+    }
+
+    pub fn generate_random_signed_transaction() -> SignedTransaction {
+        generate_signed_transaction(generate_random_transaction())
+    }
+
+    /// Sign `transaction` with a random key
+    pub fn generate_signed_transaction(transaction: Transaction) -> SignedTransaction {
+        let key = key_pair::random();
+        let sender = address_from_public_key(key.public_key().as_ref());
+        let signature = sign(&transaction, &sender, &key);
+        SignedTransaction{Transaction: transaction, Signature : signature.as_ref().to_vec(), public_key : key.public_key().as_ref().to_vec(), sender_addr : sender, multisig : None}
     }
 
     #[test]
     fn sign_verify() {
         let t = generate_random_transaction();
         let key = key_pair::random();
-        let signature = sign(&t, &key);
-        assert!(verify(&t, &(key.public_key()), &signature));
+        let sender = address_from_public_key(key.public_key().as_ref());
+        let signature = sign(&t, &sender, &key);
+        assert!(verify(&t, &sender, key.public_key().as_ref(), signature.as_ref()));
+        assert!(!verify(&t, &H160::from([1u8; 20]), key.public_key().as_ref(), signature.as_ref()));
+    }
+
+    #[test]
+    fn txid_leaves_out_witness() {
+        let tx = generate_random_signed_transaction();
+        let mut reencoded = tx.clone();
+        reencoded.Signature[63] ^= 0x80;
+        assert_eq!(tx.hash(), reencoded.hash());
+        assert_ne!(tx.witness_hash(), reencoded.witness_hash());
+
+        let mut other_sender = tx.clone();
+        other_sender.sender_addr = H160::from([1u8; 20]);
+        assert_ne!(tx.hash(), other_sender.hash());
+    }
+
+    #[test]
+    fn sender_must_own_the_key() {
+        let transaction = generate_random_transaction();
+        let key = key_pair::random();
+        let other = H160::from([1u8; 20]);
+        // correctly signed, but spending from an account the key does not control
+        let signature = sign(&transaction, &other, &key);
+        let tx = SignedTransaction{Transaction: transaction, Signature : signature.as_ref().to_vec(), public_key : key.public_key().as_ref().to_vec(), sender_addr : other, multisig : None};
+        assert!(verify(&tx.Transaction, &other, &tx.public_key, &tx.Signature));
+        assert!(!verify_witness(&tx));
+        assert!(verify_witness(&generate_random_signed_transaction()));
+    }
+
+    #[test]
+    fn multisig_needs_threshold_of_distinct_keys() {
+        let keys: Vec<Ed25519KeyPair> = (0..3).map(|_| key_pair::random()).collect();
+        let public_keys: Vec<Vec<u8>> = keys.iter().map(|k| k.public_key().as_ref().to_vec()).collect();
+        assert!(MultisigPolicy::new(0, public_keys.clone()).is_err());
+        assert!(MultisigPolicy::new(2, vec![public_keys[0].clone(), public_keys[0].clone()]).is_err());
+        let policy = MultisigPolicy::new(2, public_keys.clone()).unwrap();
+        let address = policy.address();
+        assert_ne!(address, MultisigPolicy::new(1, public_keys).unwrap().address());
+
+        let transaction = generate_random_transaction();
+        let mut tx = SignedTransaction{Transaction: transaction.clone(), Signature : Vec::new(), public_key : Vec::new(), sender_addr : policy.address(), multisig : Some(MultisigWitness::new(policy))};
+        let witness = tx.multisig.as_mut().unwrap();
+        assert!(witness.add_signature(2, sign(&transaction, &address, &keys[2]).as_ref().to_vec()));
+        assert!(!witness.add_signature(2, sign(&transaction, &address, &keys[2]).as_ref().to_vec()));
+        assert!(!verify_witness(&tx));
+
+        let witness = tx.multisig.as_mut().unwrap();
+        witness.add_signature(0, sign(&transaction, &address, &keys[1]).as_ref().to_vec());
+        assert!(!verify_witness(&tx));
+        tx.multisig.as_mut().unwrap().signatures[0].1 = sign(&transaction, &address, &keys[0]).as_ref().to_vec();
+        assert!(verify_witness(&tx));
+        assert_eq!(sender_address(&tx), tx.sender_addr);
+
+        // the same key counted twice
+        let witness = tx.multisig.as_mut().unwrap();
+        witness.signatures[1] = witness.signatures[0].clone();
+        assert!(!verify_witness(&tx));
+    }
+
+    #[test]
+    fn signature_is_bound_to_its_account() {
+        let key = key_pair::random();
+        let public_key = key.public_key().as_ref().to_vec();
+        let policy = MultisigPolicy::new(1, vec![public_key.clone()]).unwrap();
+        let transaction = generate_random_transaction();
+
+        // signed for the single key account of `key`, then attached to a spend of the 1-of-1 policy
+        let single = address_from_public_key(&public_key);
+        let signature = sign(&transaction, &single, &key).as_ref().to_vec();
+        let mut witness = MultisigWitness::new(policy.clone());
+        witness.add_signature(0, signature);
+        let mut tx = SignedTransaction{Transaction: transaction.clone(), Signature : Vec::new(), public_key : Vec::new(), sender_addr : policy.address(), multisig : Some(witness)};
+        assert!(!verify_witness(&tx));
+
+        tx.multisig.as_mut().unwrap().signatures[0].1 = sign(&transaction, &policy.address(), &key).as_ref().to_vec();
+        assert!(verify_witness(&tx));
     }
 }
@@ -0,0 +1,195 @@
+//! Uncles: headers of recent stale blocks that a block may reference, so the work spent on them
+//! is not wasted. An uncle must be the child of one of the block's recent ancestors without
+//! being an ancestor itself, and may be referenced only once.
+//!
+//! Uncle miners, and the miners of the blocks including their uncles, are credited a fraction
+//! of the chain's block reward on top of it.
+
+use crate::block::{Block, Header};
+use crate::blockchain::{Blockchain, State};
+use crate::crypto::hash::{H160, H256, Hashable};
+use crate::crypto::merkle::MerkleTree;
+use crate::validation::BlockError;
+use std::collections::HashSet;
+
+/// Most uncles a block may reference
+pub const MAX_UNCLES: usize = 2;
+/// Most blocks an uncle may be below the block referencing it
+pub const MAX_UNCLE_DEPTH: u64 = 6;
+/// Paid to the miner of a block for each uncle it references, as a fraction of the block reward
+pub const NEPHEW_REWARD_DIVISOR: u32 = 32;
+
+/// Commitment to the uncles of a block, stored in `Header::uncles_root`. Blocks without uncles
+/// commit to the zero hash.
+pub fn uncles_root(uncles: &[Header]) -> H256 {
+    MerkleTree::new(uncles).root()
+}
+
+/// `parent` followed by its ancestors, as far as an uncle's parent may be from a child of `parent`
+fn recent_ancestors(blockchain: &Blockchain, parent: &H256) -> Vec<H256> {
+    let mut ancestors = vec![*parent];
+    let mut pointer = *parent;
+    while (ancestors.len() as u64) <= MAX_UNCLE_DEPTH && pointer != blockchain.genesis.hash() {
+        pointer = blockchain.hash_blocks[&pointer].header.parent;
+        ancestors.push(pointer);
+    }
+    ancestors
+}
+
+/// Uncles already referenced by `ancestors`
+fn included_uncles(blockchain: &Blockchain, ancestors: &[H256]) -> HashSet<H256> {
+    ancestors
+        .iter()
+        .flat_map(|hash| blockchain.hash_blocks[hash].content.uncles.iter().map(|uncle| uncle.hash()))
+        .collect()
+}
+
+/// Known stale headers a new child of `parent` may reference, the most recent first
+pub fn select(blockchain: &Blockchain, parent: &H256) -> Vec<Header> {
+    let ancestors = recent_ancestors(blockchain, parent);
+    let included = included_uncles(blockchain, &ancestors);
+    let mut uncles = Vec::new();
+    for ancestor in ancestors.iter().skip(1) {
+        for child in blockchain.tree.children(ancestor) {
+            if uncles.len() == MAX_UNCLES {
+                return uncles;
+            }
+            if !ancestors.contains(child) && !included.contains(child) {
+                uncles.push(blockchain.hash_blocks[child].header.clone());
+            }
+        }
+    }
+    uncles
+}
+
+/// Check the uncles of `block`, whose parent must be in `blockchain`. The header of an uncle is
+/// checked with stake read from the state after its own parent.
+pub fn check(block: &Block, blockchain: &Blockchain) -> Result<(), BlockError> {
+    let uncles = &block.content.uncles;
+    if uncles.len() > MAX_UNCLES {
+        return Err(BlockError::TooManyUncles);
+    }
+    let ancestors = recent_ancestors(blockchain, &block.header.parent);
+    let included = included_uncles(blockchain, &ancestors);
+    let mut seen = HashSet::new();
+    for uncle in uncles.iter() {
+        let hash = uncle.hash();
+        if !seen.insert(hash) || included.contains(&hash) {
+            return Err(BlockError::DuplicateUncle(hash));
+        }
+        if ancestors.contains(&hash) || !ancestors[1..].contains(&uncle.parent) {
+            return Err(BlockError::UncleOutOfRange(hash));
+        }
+    }
+    for uncle in uncles.iter() {
+        let hash = uncle.hash();
+        let parent = &blockchain.hash_blocks[&uncle.parent].header;
+        let state = match blockchain.chainState.get(&uncle.parent) {
+            Some(state) => state,
+            None => return Err(BlockError::InvalidUncle(hash)),
+        };
+        if blockchain.consensus.verify_header(uncle, parent, state).is_err() {
+            return Err(BlockError::InvalidUncle(hash));
+        }
+    }
+    Ok(())
+}
+
+/// Credits earned by `block`, whose parent must be in `blockchain`: its miner gets the block
+/// reward, plus a `NEPHEW_REWARD_DIVISOR`th of it per uncle, and each uncle miner gets a share
+/// of the block reward shrinking with the uncle's depth
+pub fn rewards(block: &Block, blockchain: &Blockchain) -> Vec<(H160, u32)> {
+    let uncles = &block.content.uncles;
+    let block_reward = blockchain.params.block_reward;
+    let height = blockchain.height(&block.header.parent).unwrap_or(0) + 1;
+    let mut rewards: Vec<(H160, u32)> = uncles
+        .iter()
+        .filter_map(|uncle| {
+            let depth = height - (blockchain.height(&uncle.parent)? + 1);
+            let share = (MAX_UNCLE_DEPTH + 2).saturating_sub(depth);
+            Some((uncle.miner, (block_reward as u64 * share / (MAX_UNCLE_DEPTH + 2)) as u32))
+        })
+        .collect();
+    let nephew_reward = block_reward / NEPHEW_REWARD_DIVISOR * uncles.len() as u32;
+    rewards.push((block.header.miner, block_reward + nephew_reward));
+    rewards.retain(|(_, amount)| *amount > 0);
+    rewards
+}
+
+/// Credit `rewards` to `state`, opening accounts as needed
+pub fn credit(state: &mut State, rewards: &[(H160, u32)]) {
+    for (addr, amount) in rewards.iter() {
+        let account = state.accountMaping.entry(*addr).or_insert((0, 0));
+        account.1 = account.1.saturating_add(*amount);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::test::generate_random_block;
+
+    /// Genesis with a main chain of `length` blocks and a stale sibling of its first block
+    fn chain_with_stale(length: usize) -> (Blockchain, Vec<Block>, Block) {
+        let mut blockchain = Blockchain::new();
+        let mut main = vec![generate_random_block(&blockchain.genesis.hash())];
+        let stale = generate_random_block(&blockchain.genesis.hash());
+        blockchain.insert(&main[0]);
+        blockchain.insert(&stale);
+        while main.len() < length {
+            let block = generate_random_block(&main[main.len() - 1].hash());
+            blockchain.insert(&block);
+            main.push(block);
+        }
+        (blockchain, main, stale)
+    }
+
+    fn with_uncles(parent: &H256, uncles: Vec<Header>) -> Block {
+        let mut block = generate_random_block(parent);
+        block.header.uncles_root = uncles_root(&uncles);
+        block.content.uncles = uncles;
+        block
+    }
+
+    #[test]
+    fn selects_and_rewards_stale_sibling() {
+        let (blockchain, main, stale) = chain_with_stale(3);
+        let tip = main[2].hash();
+        assert_eq!(select(&blockchain, &tip).iter().map(|h| h.hash()).collect::<Vec<_>>(), vec![stale.hash()]);
+
+        let mut block = with_uncles(&tip, vec![stale.header.clone()]);
+        block.header.miner = [9u8; 20].into();
+        let mut state = State::new();
+        credit(&mut state, &rewards(&block, &blockchain));
+        // the uncle is at height 1, three below the new block
+        let block_reward = blockchain.params.block_reward;
+        assert_eq!(state.accountMaping[&stale.header.miner].1, block_reward * 5 / 8);
+        assert_eq!(state.accountMaping[&block.header.miner].1, block_reward + block_reward / NEPHEW_REWARD_DIVISOR);
+    }
+
+    #[test]
+    fn rejects_invalid_uncles() {
+        let (mut blockchain, main, stale) = chain_with_stale(2);
+        let tip = main[1].hash();
+
+        let on_chain = with_uncles(&tip, vec![main[0].header.clone()]);
+        assert_eq!(check(&on_chain, &blockchain), Err(BlockError::UncleOutOfRange(main[0].hash())));
+
+        let twice = with_uncles(&tip, vec![stale.header.clone(), stale.header.clone()]);
+        assert_eq!(check(&twice, &blockchain), Err(BlockError::DuplicateUncle(stale.hash())));
+
+        let nephew = with_uncles(&tip, vec![stale.header.clone()]);
+        blockchain.insert(&nephew);
+        let again = with_uncles(&nephew.hash(), vec![stale.header.clone()]);
+        assert_eq!(check(&again, &blockchain), Err(BlockError::DuplicateUncle(stale.hash())));
+        assert!(select(&blockchain, &nephew.hash()).is_empty());
+
+        let too_deep = (0..MAX_UNCLE_DEPTH).fold(nephew.hash(), |parent, _| {
+            let block = generate_random_block(&parent);
+            blockchain.insert(&block);
+            block.hash()
+        });
+        let late = with_uncles(&too_deep, vec![generate_random_block(&main[0].hash()).header]);
+        assert!(matches!(check(&late, &blockchain), Err(BlockError::UncleOutOfRange(_))));
+    }
+}
@@ -0,0 +1,238 @@
+//! Checks a block must pass before it is added to the chain, shared by every block producer:
+//! the in-process miner, external miners through the API, and peers.
+
+use crate::block::{Block, Header, transactions_root};
+use crate::blockchain::{Blockchain, State};
+use crate::crypto::hash::{H256, H160, Hashable};
+use crate::consensus::Consensus;
+use crate::transaction::Transaction;
+use crate::uncles;
+use crate::verifier;
+use std::time;
+
+/// How far ahead of the local clock a block timestamp may be, in milliseconds
+pub const MAX_FUTURE_DRIFT_MS: u128 = 60_000;
+
+/// Reasons a block is rejected.
+#[derive(Debug, PartialEq)]
+pub enum BlockError {
+    Empty,
+    Duplicate,
+    UnknownParent(H256),
+    WrongDifficulty,
+    InsufficientWork,
+    MerkleRootMismatch,
+    TimestampTooOld { min: u128 },
+    TimestampTooNew { max: u128 },
+    InvalidSignature(H256),
+    SlotNotAfterParent,
+    InvalidSeal,
+    NotSlotLeader(H160),
+    UnclesRootMismatch,
+    TooManyUncles,
+    DuplicateUncle(H256),
+    UncleOutOfRange(H256),
+    InvalidUncle(H256),
+    Transaction(H256, TransactionError),
+}
+
+/// Reasons a correctly signed transaction cannot be included in a block
+#[derive(Debug, PartialEq)]
+pub enum TransactionError {
+    ForeignChain { chain_id: u64 },
+    Expired { expiry: u64 },
+}
+
+impl std::fmt::Display for TransactionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            TransactionError::ForeignChain { chain_id } => write!(f, "transaction is for chain {:016x}", chain_id),
+            TransactionError::Expired { expiry } => write!(f, "transaction expired at height {}", expiry),
+        }
+    }
+}
+
+impl std::fmt::Display for BlockError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            BlockError::Empty => write!(f, "block has no transactions"),
+            BlockError::Duplicate => write!(f, "block is already in the chain"),
+            BlockError::UnknownParent(parent) => write!(f, "parent {} is unknown", parent),
+            BlockError::WrongDifficulty => write!(f, "difficulty does not match the parent"),
+            BlockError::InsufficientWork => write!(f, "block hash is above the difficulty target"),
+            BlockError::MerkleRootMismatch => write!(f, "merkle root does not match the transactions"),
+            BlockError::TimestampTooOld { min } => write!(f, "timestamp must be at least {}", min),
+            BlockError::TimestampTooNew { max } => write!(f, "timestamp must be at most {}", max),
+            BlockError::InvalidSignature(tx) => {
+                write!(f, "transaction {} has an invalid signature or sender address", tx)
+            }
+            BlockError::SlotNotAfterParent => write!(f, "block is not in a slot after its parent"),
+            BlockError::InvalidSeal => write!(f, "seal is not a valid signature of the miner"),
+            BlockError::NotSlotLeader(miner) => write!(f, "{} is not the leader of the slot", miner),
+            BlockError::UnclesRootMismatch => write!(f, "uncles root does not match the uncles"),
+            BlockError::TooManyUncles => write!(f, "block has more than {} uncles", uncles::MAX_UNCLES),
+            BlockError::DuplicateUncle(uncle) => write!(f, "uncle {} is already referenced", uncle),
+            BlockError::UncleOutOfRange(uncle) => {
+                write!(f, "uncle {} is not a stale child of a recent ancestor", uncle)
+            }
+            BlockError::InvalidUncle(uncle) => write!(f, "uncle {} has an invalid header", uncle),
+            BlockError::Transaction(tx, e) => write!(f, "transaction {}: {}", tx, e),
+        }
+    }
+}
+
+/// Timestamps a child of `parent` may carry: later than the parent, and not too far in the future
+pub fn timestamp_bounds(parent: &Header) -> (u128, u128) {
+    let now = match time::SystemTime::now().duration_since(time::UNIX_EPOCH) {
+        Ok(n) => n.as_millis(),
+        Err(_) => panic!("SystemTime before UNIX EPOCH!"),
+    };
+    (parent.timestamp + 1, now + MAX_FUTURE_DRIFT_MS)
+}
+
+/// Checks that need only the block itself: transactions are present, correctly signed and
+/// committed to by the merkle root, and the uncles are committed to by the uncles root
+pub fn check_body(block: &Block) -> Result<(), BlockError> {
+    if block.content.data.is_empty() {
+        return Err(BlockError::Empty);
+    }
+    verifier::global().verify_all(&block.content.data).map_err(BlockError::InvalidSignature)?;
+    if transactions_root(&block.content.data) != block.header.merkle_root {
+        return Err(BlockError::MerkleRootMismatch);
+    }
+    if uncles::uncles_root(&block.content.uncles) != block.header.uncles_root {
+        return Err(BlockError::UnclesRootMismatch);
+    }
+    Ok(())
+}
+
+/// Check that `tx` may be included in a block at `height` of the chain `chain_id`
+pub fn check_transaction(tx: &Transaction, chain_id: u64, height: u64) -> Result<(), TransactionError> {
+    if tx.chain_id != chain_id {
+        return Err(TransactionError::ForeignChain { chain_id: tx.chain_id });
+    }
+    match tx.expiry {
+        Some(expiry) if height > expiry => Err(TransactionError::Expired { expiry }),
+        _ => Ok(()),
+    }
+}
+
+/// Checks of the transactions of `block`, whose parent must be in `blockchain`, against the
+/// chain: they are meant for it and not expired at the height of the block
+pub fn check_transactions(block: &Block, blockchain: &Blockchain) -> Result<(), BlockError> {
+    let height = blockchain.height(&block.header.parent).unwrap_or(0) + 1;
+    for tx in block.content.data.iter() {
+        check_transaction(&tx.Transaction, blockchain.chain_id(), height).map_err(|e| BlockError::Transaction(tx.hash(), e))?;
+    }
+    Ok(())
+}
+
+/// Checks of the header against its parent: the rules of the chain's `consensus`, and a
+/// timestamp within `timestamp_bounds`
+pub fn check_header(header: &Header, parent: &Header, consensus: &dyn Consensus, state: &State) -> Result<(), BlockError> {
+    consensus.verify_header(header, parent, state)?;
+    let (min, max) = timestamp_bounds(parent);
+    if header.timestamp < min {
+        return Err(BlockError::TimestampTooOld { min });
+    }
+    if header.timestamp > max {
+        return Err(BlockError::TimestampTooNew { max });
+    }
+    Ok(())
+}
+
+/// Full validation of a block that is about to extend `blockchain`, with stake read from the
+/// state after its parent, whichever branch that parent is on
+pub fn validate_block(block: &Block, blockchain: &Blockchain) -> Result<(), BlockError> {
+    if blockchain.hash_blocks.contains_key(&block.hash()) {
+        return Err(BlockError::Duplicate);
+    }
+    let (parent, state) = match (blockchain.hash_blocks.get(&block.header.parent), blockchain.chainState.get(&block.header.parent)) {
+        (Some(parent), Some(state)) => (parent, state),
+        _ => return Err(BlockError::UnknownParent(block.header.parent)),
+    };
+    check_header(&block.header, &parent.header, blockchain.consensus.as_ref(), state)?;
+    check_body(block)?;
+    check_transactions(block, blockchain)?;
+    uncles::check(block, blockchain)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::Content;
+    use crate::transaction::tests::{generate_random_transaction, generate_signed_transaction};
+
+    fn child_of(blockchain: &Blockchain, difficulty: H256) -> Block {
+        let parent = blockchain.tip();
+        let transaction = Transaction { chain_id: blockchain.chain_id(), ..generate_random_transaction() };
+        let data = vec![generate_signed_transaction(transaction)];
+        let header = Header {
+            parent,
+            nonce: 0,
+            extra_nonce: 0,
+            difficulty,
+            timestamp: blockchain.hash_blocks[&parent].header.timestamp + 1,
+            merkle_root: transactions_root(&data),
+            uncles_root: Default::default(),
+            miner: Default::default(),
+            seal: Vec::new(),
+        };
+        Block { header, content: Content { data, uncles: Vec::new() } }
+    }
+
+    #[test]
+    fn accepts_valid_block() {
+        let blockchain = Blockchain::new();
+        let difficulty = blockchain.hash_blocks[&blockchain.tip()].header.difficulty;
+        let mut block = child_of(&blockchain, difficulty);
+        while !blockchain.pow.meets_target(&block.header, &difficulty) {
+            block.header.nonce += 1;
+        }
+        assert_eq!(validate_block(&block, &blockchain), Ok(()));
+    }
+
+    #[test]
+    fn rejects_bad_blocks() {
+        let blockchain = Blockchain::new();
+        let difficulty = blockchain.hash_blocks[&blockchain.tip()].header.difficulty;
+
+        let mut block = child_of(&blockchain, [0u8; 32].into());
+        assert_eq!(validate_block(&block, &blockchain), Err(BlockError::WrongDifficulty));
+
+        block.header.difficulty = difficulty;
+        block.header.merkle_root = Default::default();
+        while !blockchain.pow.meets_target(&block.header, &difficulty) {
+            block.header.nonce += 1;
+        }
+        assert_eq!(validate_block(&block, &blockchain), Err(BlockError::MerkleRootMismatch));
+
+        block.header.parent = Default::default();
+        assert_eq!(
+            validate_block(&block, &blockchain),
+            Err(BlockError::UnknownParent(Default::default()))
+        );
+    }
+
+    #[test]
+    fn rejects_replayed_and_expired_transactions() {
+        let blockchain = Blockchain::new();
+        let chain_id = blockchain.chain_id();
+        let tx = Transaction { chain_id, expiry: Some(1), ..generate_random_transaction() };
+        assert_eq!(check_transaction(&tx, chain_id, 1), Ok(()));
+        assert_eq!(check_transaction(&tx, chain_id, 2), Err(TransactionError::Expired { expiry: 1 }));
+        assert_eq!(
+            check_transaction(&tx, chain_id ^ 1, 1),
+            Err(TransactionError::ForeignChain { chain_id })
+        );
+
+        let mut block = child_of(&blockchain, Default::default());
+        assert_eq!(check_transactions(&block, &blockchain), Ok(()));
+        let foreign = Transaction { chain_id: chain_id ^ 1, ..block.content.data[0].Transaction.clone() };
+        block.content.data[0] = generate_signed_transaction(foreign);
+        assert_eq!(
+            check_transactions(&block, &blockchain),
+            Err(BlockError::Transaction(block.content.data[0].hash(), TransactionError::ForeignChain { chain_id: chain_id ^ 1 }))
+        );
+    }
+}
@@ -0,0 +1,132 @@
+//! Signature verification for batches of transactions, such as the content of a block or a
+//! `Transactions` message. Signatures are checked in parallel across cores; ring offers no
+//! Ed25519 batch verification, so each thread checks its share one signature at a time.
+//! Transactions that passed are remembered by witness hash, as the txid leaves the signature
+//! out, so a transaction verified on its way into the mempool is not checked again when a block
+//! including it arrives.
+
+use crate::crypto::hash::{H256, Hashable};
+use crate::metrics;
+use crate::transaction::{verify_witness, SignedTransaction};
+use std::collections::{HashSet, VecDeque};
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+
+/// Batches smaller than this are verified on the calling thread
+const MIN_PARALLEL_BATCH: usize = 16;
+/// Witness hashes remembered by the global verifier
+const CACHE_SIZE: usize = 100_000;
+
+pub struct Verifier {
+    threads: usize,
+    verified: Mutex<Verified>,
+}
+
+/// Witness hashes of correctly signed transactions, forgetting the oldest beyond `capacity`
+struct Verified {
+    hashes: HashSet<H256>,
+    order: VecDeque<H256>,
+    capacity: usize,
+}
+
+impl Verified {
+    fn insert(&mut self, hash: H256) {
+        if self.capacity == 0 || !self.hashes.insert(hash) {
+            return;
+        }
+        self.order.push_back(hash);
+        if self.order.len() > self.capacity {
+            let oldest = self.order.pop_front().unwrap();
+            self.hashes.remove(&oldest);
+        }
+    }
+}
+
+impl Verifier {
+    /// A verifier using up to `threads` threads per batch and remembering `capacity` transactions
+    pub fn new(threads: usize, capacity: usize) -> Self {
+        let verified = Verified { hashes: HashSet::new(), order: VecDeque::new(), capacity };
+        Self { threads: threads.max(1), verified: Mutex::new(verified) }
+    }
+
+    /// Whether the witness of each of `txs` is valid, see `transaction::verify_witness`, in order
+    pub fn verify(&self, txs: &[SignedTransaction]) -> Vec<bool> {
+        let witness_hashes: Vec<H256> = txs.iter().map(|tx| tx.witness_hash()).collect();
+        let mut valid = vec![true; txs.len()];
+        let pending: Vec<usize> = {
+            let verified = self.verified.lock().unwrap();
+            (0..txs.len()).filter(|i| !verified.hashes.contains(&witness_hashes[*i])).collect()
+        };
+        metrics::global().signature_cache_hits.add((txs.len() - pending.len()) as u64);
+        metrics::global().signatures_verified.add(pending.len() as u64);
+
+        let check = |i: &usize| verify_witness(&txs[*i]);
+        let results: Vec<bool> = if pending.len() < MIN_PARALLEL_BATCH || self.threads == 1 {
+            pending.iter().map(check).collect()
+        } else {
+            let chunk = pending.len().div_ceil(self.threads);
+            thread::scope(|s| {
+                let handles: Vec<_> = pending
+                    .chunks(chunk)
+                    .map(|indices| s.spawn(move || indices.iter().map(check).collect::<Vec<bool>>()))
+                    .collect();
+                handles.into_iter().flat_map(|handle| handle.join().unwrap()).collect()
+            })
+        };
+
+        let mut verified = self.verified.lock().unwrap();
+        for (i, ok) in pending.into_iter().zip(results) {
+            if ok {
+                verified.insert(witness_hashes[i]);
+            } else {
+                valid[i] = false;
+            }
+        }
+        valid
+    }
+
+    /// The txid of the first of `txs` that is not correctly signed, if any
+    pub fn verify_all(&self, txs: &[SignedTransaction]) -> Result<(), H256> {
+        match self.verify(txs).iter().position(|ok| !ok) {
+            Some(i) => Err(txs[i].hash()),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The verifier of this process, using every core
+pub fn global() -> &'static Verifier {
+    static VERIFIER: OnceLock<Verifier> = OnceLock::new();
+    VERIFIER.get_or_init(|| {
+        let threads = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+        Verifier::new(threads, CACHE_SIZE)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::tests::generate_random_signed_transaction;
+
+    #[test]
+    fn flags_bad_signatures_and_remembers_good_ones() {
+        let verifier = Verifier::new(4, 40);
+        let mut txs: Vec<SignedTransaction> = (0..50).map(|_| generate_random_signed_transaction()).collect();
+        txs[7].Signature[0] ^= 1;
+        txs[31].Signature[0] ^= 1;
+
+        let valid = verifier.verify(&txs);
+        assert_eq!(valid.iter().filter(|ok| !**ok).count(), 2);
+        assert!(!valid[7] && !valid[31]);
+
+        // only the last 40 good transactions are remembered
+        {
+            let verified = verifier.verified.lock().unwrap();
+            assert_eq!(verified.hashes.len(), 40);
+            assert!(!verified.hashes.contains(&txs[7].witness_hash()));
+            assert!(verified.hashes.contains(&txs[49].witness_hash()));
+            assert!(!verified.hashes.contains(&txs[0].witness_hash()));
+        }
+        assert_eq!(verifier.verify_all(&txs), Err(txs[7].hash()));
+    }
+}
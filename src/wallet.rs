@@ -0,0 +1,239 @@
+use crate::blockchain::State;
+use crate::crypto::hash::H160;
+use crate::crypto::key_pair;
+use crate::transaction::{Transaction, SignedTransaction, MultisigPolicy, MultisigWitness, sign, address_from_public_key};
+use crate::mempool::Mempool;
+use ring::signature::{Ed25519KeyPair, KeyPair};
+
+/// Reasons a wallet cannot build a transaction.
+#[derive(Debug)]
+pub enum WalletError {
+    UnknownKey(H160),
+    UnknownAccount(H160),
+    /// `available` is the balance left after the transfers still pending in the mempool
+    InsufficientBalance { available: u32, value: u32 },
+    /// The account has sent a transaction with every nonce
+    NoncesExhausted(H160),
+    NotMultisig,
+}
+
+impl std::fmt::Display for WalletError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            WalletError::UnknownKey(addr) => write!(f, "wallet holds no key for {}", addr),
+            WalletError::UnknownAccount(addr) => write!(f, "account {} does not exist in state", addr),
+            WalletError::InsufficientBalance { available, value } => {
+                write!(f, "insufficient balance: have {}, need {}", available, value)
+            }
+            WalletError::NoncesExhausted(addr) => write!(f, "account {} has no nonce left", addr),
+            WalletError::NotMultisig => write!(f, "transaction does not spend from a multisig account"),
+        }
+    }
+}
+
+/// The key pairs this node can sign transactions with.
+pub struct Wallet {
+    keys: Vec<Ed25519KeyPair>,
+    /// Identifier of the chain the transactions are signed for
+    chain_id: u64,
+}
+
+impl Wallet {
+    /// Create a wallet for the chain `chain_id` holding the hard-coded ICO key, so the funded
+    /// account can spend.
+    pub fn new(chain_id: u64) -> Self {
+        Self { keys: vec![key_pair::Hardcoded()], chain_id }
+    }
+
+    /// Create a wallet for the chain `chain_id` holding exactly `keys`, the first one being the default
+    pub fn with_keys(keys: Vec<Ed25519KeyPair>, chain_id: u64) -> Self {
+        Self { keys, chain_id }
+    }
+
+    pub fn chain_id(&self) -> u64 {
+        self.chain_id
+    }
+
+    pub fn add_key(&mut self, key: Ed25519KeyPair) -> H160 {
+        let addr = address_from_public_key(key.public_key().as_ref());
+        self.keys.push(key);
+        addr
+    }
+
+    /// Addresses of all accounts controlled by this wallet, the default one first
+    pub fn addresses(&self) -> Vec<H160> {
+        self.keys.iter().map(|k| address_from_public_key(k.public_key().as_ref())).collect()
+    }
+
+    pub fn default_address(&self) -> H160 {
+        address_from_public_key(self.keys[0].public_key().as_ref())
+    }
+
+    /// The key pair controlling `addr`, if this wallet holds it
+    pub fn key_pair(&self, addr: &H160) -> Option<&Ed25519KeyPair> {
+        self.keys.iter().find(|k| address_from_public_key(k.public_key().as_ref()) == *addr)
+    }
+
+    /// Build and sign a transfer from `from` to `to`, which cannot be included after height
+    /// `expiry` if set. The account nonce continues after the confirmed nonce in `state` and any
+    /// transactions from `from` still pending in `mempool`.
+    pub fn create_transaction(
+        &self,
+        from: &H160,
+        to: &H160,
+        value: u32,
+        expiry: Option<u64>,
+        state: &State,
+        mempool: &Mempool,
+    ) -> Result<SignedTransaction, WalletError> {
+        let key = self.key_pair(from).ok_or(WalletError::UnknownKey(*from))?;
+        let nonce = next_nonce(from, value, state, mempool)?;
+        let transaction = Transaction{recipAddress : *to, val : value, accountNonce : nonce, chain_id : self.chain_id, expiry};
+        let signature = sign(&transaction, from, key);
+        Ok(SignedTransaction{Transaction: transaction, Signature : signature.as_ref().to_vec(), public_key : key.public_key().as_ref().to_vec(), sender_addr : *from, multisig : None})
+    }
+
+    /// Build a transfer from the multisig account of `policy`, like `create_transaction`, and sign
+    /// it with every key of the policy this wallet holds. The other signers add theirs with
+    /// `cosign` until the transaction is complete.
+    pub fn create_multisig_transaction(
+        &self,
+        policy: &MultisigPolicy,
+        to: &H160,
+        value: u32,
+        expiry: Option<u64>,
+        state: &State,
+        mempool: &Mempool,
+    ) -> Result<SignedTransaction, WalletError> {
+        let from = policy.address();
+        let nonce = next_nonce(&from, value, state, mempool)?;
+        let transaction = Transaction{recipAddress : *to, val : value, accountNonce : nonce, chain_id : self.chain_id, expiry};
+        let mut signed = SignedTransaction{Transaction: transaction, Signature : Vec::new(), public_key : Vec::new(), sender_addr : from, multisig : Some(MultisigWitness::new(policy.clone()))};
+        if self.cosign(&mut signed)? == 0 {
+            return Err(WalletError::UnknownKey(from));
+        }
+        Ok(signed)
+    }
+
+    /// Add the signatures of the keys of this wallet that the multisig transaction `tx` still
+    /// lacks. Returns how many were added.
+    pub fn cosign(&self, tx: &mut SignedTransaction) -> Result<usize, WalletError> {
+        let witness = tx.multisig.as_mut().ok_or(WalletError::NotMultisig)?;
+        let mut added = 0;
+        for key in self.keys.iter() {
+            let position = witness.policy.public_keys.iter().position(|k| k.as_slice() == key.public_key().as_ref());
+            if let Some(index) = position {
+                let signature = sign(&tx.Transaction, &witness.policy.address(), key).as_ref().to_vec();
+                if witness.add_signature(index as u8, signature) {
+                    added += 1;
+                }
+            }
+        }
+        Ok(added)
+    }
+
+    /// Public keys of all accounts controlled by this wallet, the default one first
+    pub fn public_keys(&self) -> Vec<Vec<u8>> {
+        self.keys.iter().map(|k| k.public_key().as_ref().to_vec()).collect()
+    }
+}
+
+/// The nonce of the next transfer of `value` from `from`, which continues after the confirmed
+/// nonce in `state` and any transactions from `from` still pending in `mempool`
+fn next_nonce(
+    from: &H160,
+    value: u32,
+    state: &State,
+    mempool: &Mempool,
+) -> Result<u16, WalletError> {
+    let (confirmed_nonce, balance) = *state
+        .accountMaping
+        .get(from)
+        .ok_or(WalletError::UnknownAccount(*from))?;
+
+    // pending transfers are not reflected in the state yet, so count them against the balance
+    let pending: Vec<&SignedTransaction> = mempool.values().filter(|tx| tx.sender_addr == *from).collect();
+    let pending_value: u64 = pending.iter().map(|tx| tx.Transaction.val as u64).sum();
+    // never more than `balance`, so it fits
+    let available = (balance as u64).saturating_sub(pending_value) as u32;
+    if available < value {
+        return Err(WalletError::InsufficientBalance { available, value });
+    }
+    let last_nonce = pending
+        .iter()
+        .map(|tx| tx.Transaction.accountNonce)
+        .max()
+        .unwrap_or(confirmed_nonce)
+        .max(confirmed_nonce);
+    last_nonce.checked_add(1).ok_or(WalletError::NoncesExhausted(*from))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::{verify, verify_witness};
+
+    #[test]
+    fn default_key_controls_ico_account() {
+        let ico: H160 = [70, 8, 220, 215, 80, 53, 152, 74, 136, 126, 87, 62, 230, 168, 2, 10, 237, 58, 51, 50].into();
+        assert_eq!(Wallet::new(0).default_address(), ico);
+    }
+
+    #[test]
+    fn nonce_follows_pending() {
+        let wallet = Wallet::new(7);
+        let from = wallet.default_address();
+        let to: H160 = [7; 20].into();
+        let mut state = State::new();
+        state.accountMaping.insert(from, (3, 100));
+        let mut mempool = Mempool::new();
+
+        let first = wallet.create_transaction(&from, &to, 10, None, &state, &mempool).unwrap();
+        assert_eq!(first.Transaction.accountNonce, 4);
+        assert_eq!(first.Transaction.chain_id, 7);
+        assert!(verify(&first.Transaction, &from, &first.public_key, &first.Signature));
+        mempool.insert(first);
+
+        let second = wallet.create_transaction(&from, &to, 10, None, &state, &mempool).unwrap();
+        assert_eq!(second.Transaction.accountNonce, 5);
+        mempool.insert(second);
+
+        match wallet.create_transaction(&from, &to, 81, None, &state, &mempool) {
+            Err(WalletError::InsufficientBalance { available, value }) => assert_eq!((available, value), (80, 81)),
+            other => panic!("expected insufficient balance, got {:?}", other.map(|tx| tx.Transaction.accountNonce)),
+        }
+    }
+
+    #[test]
+    fn exhausted_nonce_is_refused() {
+        let wallet = Wallet::new(7);
+        let from = wallet.default_address();
+        let mut state = State::new();
+        state.accountMaping.insert(from, (u16::MAX, 100));
+        match wallet.create_transaction(&from, &[7; 20].into(), 10, None, &state, &Mempool::new()) {
+            Err(WalletError::NoncesExhausted(addr)) => assert_eq!(addr, from),
+            other => panic!("expected exhausted nonces, got {:?}", other.map(|tx| tx.Transaction.accountNonce)),
+        }
+    }
+
+    #[test]
+    fn cosign_completes_multisig_transaction() {
+        let alice = Wallet::with_keys(vec![key_pair::random()], 7);
+        let bob = Wallet::with_keys(vec![key_pair::random()], 7);
+        let carol = Wallet::with_keys(vec![key_pair::random()], 7);
+        let public_keys = vec![alice.public_keys()[0].clone(), bob.public_keys()[0].clone(), carol.public_keys()[0].clone()];
+        let policy = MultisigPolicy::new(2, public_keys).unwrap();
+        let mut state = State::new();
+        state.accountMaping.insert(policy.address(), (0, 50));
+        let mempool = Mempool::new();
+
+        assert!(Wallet::new(7).create_multisig_transaction(&policy, &[7; 20].into(), 10, None, &state, &mempool).is_err());
+        let mut tx = carol.create_multisig_transaction(&policy, &[7; 20].into(), 10, None, &state, &mempool).unwrap();
+        assert_eq!(tx.sender_addr, policy.address());
+        assert!(!verify_witness(&tx));
+        assert_eq!(carol.cosign(&mut tx).unwrap(), 0);
+        assert_eq!(alice.cosign(&mut tx).unwrap(), 1);
+        assert!(tx.multisig.as_ref().unwrap().is_complete());
+        assert!(verify_witness(&tx));
+    }
+}